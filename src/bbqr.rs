@@ -0,0 +1,82 @@
+use crate::error::AppError;
+
+// BBQr (Better Bitcoin QR) 形式のフレーム分割。エアギャップ環境へアニメーションQRで渡すための
+// 各フレームのテキストを作る (QRコードの描画は行わない)。
+// フレームは "B$" + エンコーディング + ファイル種別 + 総フレーム数 (36進2桁) + フレーム番号 (36進2桁) + データ
+const HEADER_PREFIX: &str = "B$";
+const HEADER_LEN: usize = 8;
+// 総フレーム数は36進2桁で表せる範囲まで
+const MAX_PARTS: usize = 36 * 36 - 1;
+// エンコーディング '2': RFC 4648 の base32 (パディングなし)。QRの英数字モードで効率よく符号化できる
+const ENCODING_BASE32: char = '2';
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+// base32 は5バイトごとに8文字になるため、最後以外のフレームのデータ長は8文字の倍数にする
+const BASE32_BLOCK_CHARS: usize = 8;
+const BASE36_DIGITS: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BbqrFileType {
+    Transaction,
+    Psbt,
+}
+
+impl BbqrFileType {
+    fn code(self) -> char {
+        match self {
+            BbqrFileType::Transaction => 'T',
+            BbqrFileType::Psbt => 'P',
+        }
+    }
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(5) * 8);
+    for chunk in data.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let bits = block.iter().fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+        // 入力のビット数を5ビット単位で切り上げた文字数だけ出力する (パディングの '=' は付けない)
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for index in 0..chars {
+            let value = (bits >> (35 - index * 5)) & 0x1f;
+            encoded.push(char::from(BASE32_ALPHABET[value as usize]));
+        }
+    }
+    encoded
+}
+
+fn base36_pair(value: usize) -> String {
+    [BASE36_DIGITS[value / 36], BASE36_DIGITS[value % 36]].iter().map(|digit| char::from(*digit)).collect()
+}
+
+/// データをBBQrのフレームに分割する。max_frame_chars はヘッダーを含む1フレームの最大文字数。
+pub fn encode_frames(data: &[u8], file_type: BbqrFileType, max_frame_chars: usize) -> Result<Vec<String>, AppError> {
+    let part_chars = max_frame_chars.saturating_sub(HEADER_LEN) / BASE32_BLOCK_CHARS * BASE32_BLOCK_CHARS;
+    if part_chars == 0 {
+        return Err(AppError::InputValidation(format!(
+            "BBQrの1フレームの文字数 {} が小さすぎます ({} 文字以上を指定してください)", max_frame_chars, HEADER_LEN + BASE32_BLOCK_CHARS)));
+    }
+    let encoded = base32_encode(data);
+    let part_count = encoded.len().div_ceil(part_chars).max(1);
+    if part_count > MAX_PARTS {
+        return Err(AppError::InputValidation(format!(
+            "BBQrのフレーム数 {} が上限 {} を超えます。1フレームの文字数を増やしてください", part_count, MAX_PARTS)));
+    }
+    // base32 の出力はASCIIのみのため、バイト位置で分割してよい
+    let frames = (0..part_count)
+        .map(|part_index| {
+            let start = part_index * part_chars;
+            let end = (start + part_chars).min(encoded.len());
+            format!(
+                "{}{}{}{}{}{}",
+                HEADER_PREFIX,
+                ENCODING_BASE32,
+                file_type.code(),
+                base36_pair(part_count),
+                base36_pair(part_index),
+                &encoded[start..end]
+            )
+        })
+        .collect();
+    Ok(frames)
+}
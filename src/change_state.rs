@@ -0,0 +1,86 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+// --change-state-file の内容。使用済みのおつり先アドレスを実行をまたいで記録する
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ChangeState {
+    #[serde(default)]
+    used_change_addresses: Vec<String>,
+}
+
+// ファイルがない場合は未使用の状態 (初回の実行) とする
+fn load_state(state_file: &Path) -> Result<ChangeState, AppError> {
+    match fs::read_to_string(state_file) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| AppError::JsonParse { file_path: state_file.to_path_buf(), source: e }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(ChangeState::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// changeAddress のリストから、状態ファイルに使用済みとして記録されていない最初のアドレスを選ぶ
+pub fn next_change_address(addresses: &[String], state_file: &Path) -> Result<String, AppError> {
+    let state = load_state(state_file)?;
+    addresses
+        .iter()
+        .find(|address| !state.used_change_addresses.contains(address))
+        .cloned()
+        .ok_or_else(|| AppError::ChangeAddressesExhausted { count: addresses.len(), state_file: state_file.to_path_buf() })
+}
+
+/// トランザクションの保存後に、おつり先アドレスを使用済みとして記録する。
+/// 書き込み途中で中断しても状態ファイルが壊れないよう、一時ファイルに書いてから置き換える
+pub fn record_used_change_address(state_file: &Path, address: &str) -> Result<(), AppError> {
+    let mut state = load_state(state_file)?;
+    if !state.used_change_addresses.iter().any(|used| used == address) {
+        state.used_change_addresses.push(address.to_string());
+    }
+    let contents = serde_json::to_string_pretty(&state)
+        .map_err(|e| AppError::Internal(format!("おつり先の状態のシリアライズに失敗しました: {}", e)))?;
+    let temp_file = state_file.with_extension("tmp");
+    fs::write(&temp_file, contents)?;
+    fs::rename(&temp_file, state_file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_addresses_rotate_across_runs_until_exhausted() {
+        let dir = std::env::temp_dir().join(format!("signer-change-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let state_file = dir.join("change-state.json");
+        let addresses = vec!["address-a".to_string(), "address-b".to_string()];
+
+        // 状態ファイルがない初回はリストの先頭を使う。記録するまでは同じアドレスを返す
+        assert_eq!(next_change_address(&addresses, &state_file).unwrap(), "address-a");
+        assert_eq!(next_change_address(&addresses, &state_file).unwrap(), "address-a");
+        record_used_change_address(&state_file, "address-a").unwrap();
+        assert_eq!(next_change_address(&addresses, &state_file).unwrap(), "address-b");
+        // 同じアドレスを2回記録しても重複しない
+        record_used_change_address(&state_file, "address-a").unwrap();
+        record_used_change_address(&state_file, "address-b").unwrap();
+        let state: ChangeState = serde_json::from_str(&fs::read_to_string(&state_file).unwrap()).unwrap();
+        assert_eq!(state.used_change_addresses, addresses);
+        assert!(!state_file.with_extension("tmp").exists());
+
+        assert!(matches!(
+            next_change_address(&addresses, &state_file),
+            Err(AppError::ChangeAddressesExhausted { count: 2, .. })
+        ));
+        // リストにアドレスを追加すれば再開できる
+        let extended = [addresses.clone(), vec!["address-c".to_string()]].concat();
+        assert_eq!(next_change_address(&extended, &state_file).unwrap(), "address-c");
+
+        fs::write(&state_file, "not json").unwrap();
+        assert!(matches!(next_change_address(&addresses, &state_file), Err(AppError::JsonParse { .. })));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,22 +1,292 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
-use bitcoin::Network as BitcoinNetwork;
+use bitcoin::{Network as BitcoinNetwork, NetworkKind, PrivateKey};
 use crate::error::AppError;
+use crate::fee_rate::FeeRate;
+use crate::types::ScriptType;
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct CliArgs {
-    /// トランザクション情報を記述したJSONファイルへのパス
-    #[clap(short, long, value_parser)]
-    pub input_file: PathBuf,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// トランザクション情報を記述したJSONファイルへのパス。http(s):// で始まる場合はURLから取得する (`http` フィーチャーが必要)
+    #[clap(short, long, value_parser, required = true)]
+    pub input_file: Option<PathBuf>,
 
     /// 生成されたraw transaction hexを保存するファイルへのパス
+    #[clap(short, long, value_parser, required_unless_present_any = ["output_dir", "dump_effective_config"], conflicts_with = "output_dir")]
+    pub output_file: Option<PathBuf>,
+
+    /// --output-file の代わりに、このディレクトリへ --output-name-template のファイル名で保存する
+    #[clap(long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// --output-dir のファイル名テンプレート ({txid}, {first_address}, {input}, {fee_rate} を置換。使用できない文字は _ に置換される)
+    #[clap(long, default_value = "{txid}.hex", requires = "output_dir")]
+    pub output_name_template: String,
+
+    /// 使用するネットワーク ("bitcoin", "testnet", "regtest")。未指定時は設定ファイルの network、それもなければ testnet
     #[clap(short, long, value_parser)]
-    pub output_file: PathBuf,
+    pub network: Option<String>,
+
+    /// 出力ファイルにhexではなくraw transactionのバイナリ (consensus serialize) を書き込む
+    #[clap(long)]
+    pub binary: bool,
+
+    /// 手数料率を読み取るJSONファイルのパスまたはURL (指定時は設定ファイルの feeRateSatsPerVb を上書き)
+    #[clap(long)]
+    pub fee_source: Option<String>,
+
+    /// --fee-source のJSON内で手数料率 (sats/vB) を指すドット区切りのフィールドパス
+    #[clap(long, default_value = "fastestFee")]
+    pub fee_source_field: String,
+
+    /// 手数料率 (sats/vB) の上限。単位の取り違え (sat/kvB) などによる過大な手数料率を拒否する
+    #[clap(long, default_value_t = 2000)]
+    pub fee_rate_ceiling: u64,
+
+    /// 指定した手数料率 (sats/vB、例: 5,10,20) ごとにおつりを計算し直して署名し、それぞれ別ファイルに保存する。
+    /// --output-file では名前に手数料率を付ける (tx.hex -> tx-5satvb.hex)
+    /// compute-sighashes では保存せず、手数料率ごとの未署名トランザクションと署名ハッシュをJSONで出力する
+    #[clap(long, value_delimiter = ',', conflicts_with_all = ["fee_source", "exact_fee", "sign_inputs", "manifest", "review_package", "warnings_json", "psbt_out", "spent_outpoints_file"])]
+    pub fee_rate_variants: Option<Vec<FeeRate>>,
+
+    /// 1行に1つのUTXO (JSONオブジェクト) を記述したファイル。1行ずつパースして設定ファイルの utxos に追加する
+    /// (analyze-consolidation では1件ずつ見積もり、UTXO自体は保持しない)
+    #[clap(long)]
+    pub utxos_jsonl: Option<PathBuf>,
+
+    /// 実際の手数料 (sats) がこの値と完全に一致することを確認する。一致しなければ署名せずにエラー
+    #[clap(long)]
+    pub exact_fee: Option<u64>,
+
+    /// secp256k1 コンテキストを署名専用 (signing_only) で作成する (既定は署名・検証兼用)
+    #[clap(long)]
+    pub secp_signing_only: bool,
+
+    /// 署名後の実際のvsizeに対して手数料率が目標以上になるよう、必要なら手数料を上乗せして再署名する
+    #[clap(long)]
+    pub round_up_fee: bool,
+
+    /// 最終トランザクションがBIP125のRBF (いずれかの入力の sequence < 0xfffffffe) を通知しているかを表示する
+    #[clap(long)]
+    pub replaceability_check: bool,
+
+    /// 複数の受信者出力が同じアドレスに送金していても警告しない
+    #[clap(long)]
+    pub allow_address_reuse: bool,
+
+    /// メインネットで手数料率 0 sats/vB を許可する (regtest などでは指定不要)
+    #[clap(long)]
+    pub allow_low_fee: bool,
+
+    /// おつりがダスト閾値や minChangeSats 未満でも、1 sat以上あれば必ずおつり出力を作る (regtest などでの試験用。メインネットでは使用不可)。
+    /// ダスト出力を含むトランザクションは非標準のためリレーされない可能性がある
+    #[clap(long)]
+    pub force_change: bool,
+
+    /// 署名の再現に必要な情報 (入力、sighash type、手数料率、ロックタイム、txid など。秘密鍵は含まない) をJSONで書き出す
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+
+    /// 第三者がUTXOセットにアクセスせずに内容 (送金先・金額・手数料) を確認できるよう、署名済みhexと各入力の
+    /// scriptPubKey・金額、各出力のアドレス・おつりかどうかをまとめたJSONを書き出す (ブロードキャスト前のレビュー用)
+    #[clap(long)]
+    pub review_package: Option<PathBuf>,
+
+    /// 署名中に検出した警告を、種類ごとの安定したコードとメッセージを持つJSONとしてこのファイルに書き出す (警告がなければ空の配列)
+    #[clap(long)]
+    pub warnings_json: Option<PathBuf>,
+
+    /// changeAddress をリストで指定した場合の使用済みアドレスを記録するJSONファイル。未使用の最初のアドレスをおつり先にし、
+    /// トランザクションの保存後に使用済みとして記録する (実行をまたいだおつり先の再利用を防ぐ)
+    #[clap(long)]
+    pub change_state_file: Option<PathBuf>,
+
+    /// 消費するUTXOのアウトポイント (txid:vout) を1行に1件ずつこのファイルに書き出す (外部の帳簿管理用)
+    #[clap(long)]
+    pub spent_outpoints_file: Option<PathBuf>,
+
+    /// 各入力の署名適用後のトランザクションhexを、このディレクトリに input-<入力番号>.hex として書き出す (監査用)
+    #[clap(long)]
+    pub audit_dir: Option<PathBuf>,
+
+    /// TXIDを表示順 (RPC・ブロックエクスプローラーと同じ) と内部バイト順 (シリアライズ時の並び) の両方で表示する
+    #[clap(long)]
+    pub txid_byte_orders: bool,
+
+    /// witnessを除いたシリアライズ (txidの計算対象) のhexも出力する (デバッグ用)
+    #[clap(long)]
+    pub stripped_hex: bool,
+
+    /// 署名前 (script_sig/witness が空) のトランザクションhexも出力する
+    #[clap(long)]
+    pub also_unsigned: bool,
+
+    /// 署名後、ノードの testmempoolaccept に相当する確認 (バージョン、サイズ、手数料率、スクリプトの標準性、ダスト、署名検証) をローカルで行い、判定を表示する
+    #[clap(long)]
+    pub preflight: bool,
+
+    /// 計画用: 典型的な入力をこの数だけ追加した場合のvsizeと手数料 (同じ手数料率) を見積もって表示する (トランザクションには含めない)
+    #[clap(long)]
+    pub extra_inputs: Option<usize>,
+
+    /// --extra-inputs で想定する入力のタイプ ("p2wpkh", "p2pkh", "p2pk")
+    #[clap(long, default_value = "p2wpkh", requires = "extra_inputs")]
+    pub extra_input_type: String,
+
+    /// 成功時は何も表示しない (標準出力のhex/JSONと info ログを抑止)。エラーは標準エラー出力に出る。--output-file と併用する
+    #[clap(long, conflicts_with = "json")]
+    pub quiet: bool,
+
+    /// 標準出力にhexではなくJSONオブジェクトを出力する
+    #[clap(long)]
+    pub json: bool,
+
+    /// 指定した入力インデックス (例: 0,2,4。inputOrder による並び替え後の順序) のみ署名し、部分署名のトランザクションを出力する
+    #[clap(long, value_delimiter = ',', conflicts_with = "round_up_fee")]
+    pub sign_inputs: Option<Vec<usize>>,
+
+    /// 手数料が送金額のこの割合 (0.1 = 10%) を超えたら警告する (--strict ではエラー)
+    #[clap(long, default_value_t = 0.1)]
+    pub max_fee_ratio: f64,
 
-    /// 使用するネットワーク ("bitcoin", "testnet", "regtest")
-    #[clap(short, long, value_parser, default_value = "testnet")]
-    pub network: String,
+    /// 入力数と出力数 (おつりを含む) の比がこの値を超えたらエラーにする (どちらが多い場合も。例: 50)。
+    /// 自動生成した設定ファイルの誤り (1件の支払いに数百の入力など) を署名前に検出する
+    #[clap(long)]
+    pub max_io_ratio: Option<f64>,
+
+    /// 受信者出力の送金先として許可するアドレスの一覧 (1行に1件。空行と # で始まる行は無視)。一覧にない送金先があればエラー
+    #[clap(long)]
+    pub allowlist_file: Option<PathBuf>,
+
+    /// --strict に加え、許可リスト外への送金 (おつりを含む) や送金額に比べて高すぎる手数料があれば署名前に中断する
+    #[clap(long, requires = "safe_allowlist")]
+    pub safe: bool,
+
+    /// --safe で許可する送金先アドレスの一覧 (1行に1件。空行と # で始まる行は無視)
+    #[clap(long, requires = "safe")]
+    pub safe_allowlist: Option<PathBuf>,
+
+    /// --safe で許容する手数料の上限 (送金額に対する %)
+    #[clap(long, default_value_t = 1.0, requires = "safe")]
+    pub safe_max_fee_percent: f64,
+
+    /// ダストおつりの手数料への繰り入れなど、暗黙の仮定に関する警告をすべてエラーとして扱う
+    #[clap(long)]
+    pub strict: bool,
+
+    /// 各UTXOのscriptPubKeyから導出したアドレスを標準エラー出力に表示する
+    #[clap(long)]
+    pub show_input_addresses: bool,
+
+    /// 最初のUTXOのWIFからネットワークを自動判定する (テスト系WIFの場合は --network がテスト系ならそれを、そうでなければ testnet を使用)
+    #[clap(long)]
+    pub wif_network_autodetect: bool,
+
+    /// 既定値・CLIでの上書き (--utxos-jsonl, --fee-source など)・ネットワークの判定を反映した設定をJSONで標準出力に表示して終了する (署名しない)。
+    /// 秘密鍵は伏せて表示する
+    #[clap(long, conflicts_with = "fee_rate_variants")]
+    pub dump_effective_config: bool,
+
+    /// UTXO処理・署名ハッシュ計算・署名の所要時間と件数のサマリーを標準エラー出力に表示する
+    #[clap(long)]
+    pub metrics: bool,
+
+    /// 各入力の sighash type と部分署名を含むPSBTをbase64で保存するファイルへのパス
+    #[clap(long)]
+    pub psbt_out: Option<PathBuf>,
+
+    /// 署名済みトランザクション (--bbqr-psbt ではPSBT) をBBQr形式のアニメーションQR用テキストフレームに分割し、1行に1フレームずつ保存する
+    #[cfg(feature = "bbqr")]
+    #[clap(long)]
+    pub bbqr_out: Option<PathBuf>,
+
+    /// --bbqr-out でトランザクションの代わりにPSBT (各入力の部分署名を含む) を分割する
+    #[cfg(feature = "bbqr")]
+    #[clap(long, requires = "bbqr_out")]
+    pub bbqr_psbt: bool,
+
+    /// --bbqr-out の1フレームの最大文字数 (ヘッダー8文字を含む)
+    #[cfg(feature = "bbqr")]
+    #[clap(long, default_value_t = 500, requires = "bbqr_out")]
+    pub bbqr_frame_chars: usize,
+
+    /// CSV (既定列: txid,vout,value,scriptPubKey,wif。value は sats) からUTXOを取り込み、設定ファイルの utxos に追加する
+    #[cfg(feature = "csv-import")]
+    #[clap(long)]
+    pub utxos_csv: Option<PathBuf>,
+
+    /// --utxos-csv の列の並び (例: txid,vout,value,scriptPubKey,wif)。指定時はヘッダー行なしとして扱う
+    #[cfg(feature = "csv-import")]
+    #[clap(long, value_delimiter = ',', requires = "utxos_csv")]
+    pub csv_columns: Option<Vec<String>>,
+
+    /// Electrumの listunspent 形式のJSONからUTXOを取り込み、設定ファイルの utxos に追加する
+    #[cfg(feature = "electrum-import")]
+    #[clap(long)]
+    pub electrum_coins: Option<PathBuf>,
+
+    /// --electrum-coins 用の秘密鍵 (アドレス→WIF のJSONオブジェクト、Electrumの秘密鍵エクスポート形式)
+    #[cfg(feature = "electrum-import")]
+    #[clap(long, requires = "electrum_coins")]
+    pub electrum_keys: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 入力設定ファイル (InputConfig) のJSON Schemaを標準出力に出力する
+    JsonSchema,
+
+    /// 設定ファイルのUTXOのうち経済的なものだけを1つの出力に集約するトランザクションを作成する (outputs は無視)
+    Consolidate {
+        /// UTXO一覧と手数料率を記述したJSONファイルへのパス
+        #[clap(short, long, value_parser)]
+        input_file: PathBuf,
+
+        /// 生成されたraw transaction hexを保存するファイルへのパス
+        #[clap(short, long, value_parser)]
+        output_file: PathBuf,
+
+        /// 集約先アドレス (省略時は設定ファイルの changeAddress / changeDescriptor)
+        #[clap(long)]
+        target_address: Option<String>,
+    },
+
+    /// 集約の検討用に、各UTXOを含める場合の手数料と累積の純資産を表示する (署名しない。--json でJSON出力)
+    AnalyzeConsolidation {
+        /// UTXO一覧と手数料率を記述したJSONファイルへのパス
+        #[clap(short, long, value_parser)]
+        input_file: PathBuf,
+
+        /// 設定ファイルの feeRateSatsPerVb の代わりに使う手数料率 (sats/vB、小数点以下3桁まで)
+        #[clap(long)]
+        fee_rate: Option<FeeRate>,
+    },
+
+    /// 秘密鍵なしで各入力の署名ハッシュ・sighash type・期待される公開鍵をJSONで出力する (外部署名用)
+    ComputeSighashes {
+        /// トランザクション情報を記述したJSONファイルへのパス (privateKeyWif の代わりに publicKeyHex を指定できる)
+        #[clap(short, long, value_parser)]
+        input_file: PathBuf,
+    },
+
+    /// 外部で署名されたトランザクションを設定ファイルと照合する (使用するUTXO・送金先と金額・署名を確認し、不一致を報告する。--json でJSON出力)
+    VerifyTx {
+        /// 意図した入力と出力を記述したJSONファイルへのパス (秘密鍵は不要)
+        #[clap(short, long, value_parser)]
+        input_file: PathBuf,
+
+        /// 検証する署名済みトランザクション (raw transaction hex)
+        #[clap(long, required_unless_present = "tx_file", conflicts_with = "tx_file")]
+        tx_hex: Option<String>,
+
+        /// 署名済みトランザクションのhexを記述したファイルへのパス
+        #[clap(long, value_parser)]
+        tx_file: Option<PathBuf>,
+    },
 }
 
 pub fn parse_network(network_str: &str) -> Result<BitcoinNetwork, AppError> {
@@ -24,6 +294,27 @@ pub fn parse_network(network_str: &str) -> Result<BitcoinNetwork, AppError> {
         "bitcoin" | "mainnet" => Ok(BitcoinNetwork::Bitcoin),
         "testnet" => Ok(BitcoinNetwork::Testnet),
         "regtest" => Ok(BitcoinNetwork::Regtest),
-        s => Err(AppError::InputValidation(format!("無効なネットワークが指定されました: {}", s))),
+        _ => Err(AppError::InputValidation(format!("無効なネットワークが指定されました: {}", network_str))),
+    }
+}
+
+pub fn parse_extra_input_type(type_str: &str) -> Result<ScriptType, AppError> {
+    match type_str.to_lowercase().as_str() {
+        "p2wpkh" => Ok(ScriptType::P2WPKH),
+        "p2pkh" => Ok(ScriptType::P2PKH),
+        "p2pk" => Ok(ScriptType::P2PK),
+        _ => Err(AppError::InputValidation(format!("無効な入力タイプが指定されました: {} (p2wpkh, p2pkh, p2pk のいずれか)", type_str))),
+    }
+}
+
+// WIFのネットワークバイトからネットワークを判定する。
+// WIFはメインネットとテスト系 (testnet/regtest/signet) しか区別できないため、
+// テスト系の場合は test_network_hint がテスト系ならそれを採用し、そうでなければ testnet とする
+pub fn detect_network_from_wif(wif: &str, test_network_hint: BitcoinNetwork) -> Result<BitcoinNetwork, AppError> {
+    let private_key = PrivateKey::from_wif(wif).map_err(AppError::BitcoinKey)?;
+    match private_key.network {
+        NetworkKind::Main => Ok(BitcoinNetwork::Bitcoin),
+        NetworkKind::Test if test_network_hint != BitcoinNetwork::Bitcoin => Ok(test_network_hint),
+        NetworkKind::Test => Ok(BitcoinNetwork::Testnet),
     }
 }
\ No newline at end of file
@@ -1,31 +1,356 @@
-use serde::Deserialize;
+use bitcoin::Network as BitcoinNetwork;
+use schemars::JsonSchema;
+use std::fmt;
 
-#[derive(Deserialize, Debug)]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::cli::parse_network;
+use crate::fee_rate::FeeRate;
+use crate::redact::{Redacted, REDACTED};
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct InputConfig {
+    // 設定ファイルが想定するネットワーク。--network と食い違う場合はエラー
+    #[serde(default, deserialize_with = "deserialize_network", serialize_with = "serialize_network")]
+    #[schemars(with = "Option<String>")]
+    pub network: Option<BitcoinNetwork>,
+    // --utxos-jsonl で別ファイルから読み込む場合は省略できる
+    #[serde(default)]
     pub utxos: Vec<UtxoInput>,
     pub outputs: Vec<TransactionOutputDef>,
-    pub fee_rate_sats_per_vb: u64,
-    pub change_address: String,
+    // おつり出力の後ろに配置する出力 (プロトコル上の出力順の要件用)。手数料計算に含まれる
+    #[serde(default)]
+    pub append_outputs: Vec<TransactionOutputDef>,
+    // 手数料は feeRateSatsPerVb (手数料率) か feePercent のどちらか一方で指定する。手数料率は小数点以下3桁まで (例: 1.5)
+    #[serde(default)]
+    pub fee_rate_sats_per_vb: Option<FeeRate>,
+    // 手数料を受信者出力 (appendOutputs を含む) の合計額に対する割合 (%) で指定する。端数は切り上げ
+    #[serde(default)]
+    pub fee_percent: Option<f64>,
+    // このトランザクションが依存する未承認の祖先の手数料合計とvsize合計。両方指定すると、祖先を含むパッケージ全体が
+    // feeRateSatsPerVb を満たすように手数料を決める (feePercent とは併用できない)
+    #[serde(default, deserialize_with = "deserialize_optional_sats")]
+    #[schemars(with = "Option<SatsSchema>")]
+    pub ancestor_fee_sats: Option<u64>,
+    #[serde(default)]
+    pub ancestor_vsize: Option<u64>,
+    // おつり先は changeAddress か changeDescriptor のどちらか一方を指定する。
+    // changeAddress をリストにすると、--change-state-file に記録した使用済みのものを除いて先頭から順に使う
+    #[serde(default)]
+    pub change_address: Option<ChangeAddressDef>,
+    #[serde(default)]
+    pub change_descriptor: Option<String>,
     #[serde(default)]
     pub default_sequence: Option<u32>,
+    // 組織ポリシーによる入力数・出力数 (おつり含む) の上限
+    #[serde(default)]
+    pub max_inputs: Option<usize>,
+    #[serde(default)]
+    pub max_outputs: Option<usize>,
+    // 署名後の推定ウェイト (WU) の上限。未指定時や標準ルールの上限を超える値は 400,000 WU に制限される
+    #[serde(default)]
+    pub max_weight_wu: Option<u64>,
+    // ダスト閾値未満のおつりの扱い。閾値ちょうどのおつりは常に出力として作成される
+    #[serde(default)]
+    pub change_dust_policy: ChangeDustPolicy,
+    // includeAnyway 時にこの値以上 (閾値未満) のおつりのみ出力に含める。未指定時は1 sat以上すべて
+    #[serde(default, deserialize_with = "deserialize_optional_sats")]
+    #[schemars(with = "Option<SatsSchema>")]
+    pub change_dust_band_min_sats: Option<u64>,
+    // ダスト閾値の計算に使うダストリレー手数料率 (sats/kvB)。未指定時は Bitcoin Core の既定値 3000
+    #[serde(default)]
+    pub dust_relay_fee_sats_per_kvb: Option<u64>,
+    // おつり出力を置く位置 (最終的な出力の並びでのインデックス)。未指定時は受信者出力の後ろ (appendOutputs の前)
+    #[serde(default)]
+    pub change_position: Option<usize>,
+    // ダスト閾値以上でもこの値未満のおつりは出力を作らず手数料に含める (小さなUTXOを増やさないため)
+    #[serde(default, deserialize_with = "deserialize_optional_sats")]
+    #[schemars(with = "Option<SatsSchema>")]
+    pub min_change_sats: Option<u64>,
+    // 手数料を支払う入力 (utxos のインデックス)。指定時は他の入力が outputs と設定ファイルの順に1対1で対応し、
+    // それぞれ同じ金額を送る必要がある。手数料はこの入力から支払い、残りはおつりになる
+    #[serde(default)]
+    pub fee_input_index: Option<usize>,
+    // 手数料バンプ専用のUTXO。utxos の後ろに入力として追加される
+    #[serde(default)]
+    pub fee_bump_utxos: Vec<UtxoInput>,
+    // feeBumpUtxos がある場合に使用する目標手数料率 (未指定時は feeRateSatsPerVb)
+    #[serde(default)]
+    pub fee_bump_target_rate_sats_per_vb: Option<FeeRate>,
+    // スイープ (outputs が空) でおつり出力がダスト未満になる場合、最低リレー手数料率に下げて再計算する
+    #[serde(default)]
+    pub sweep_auto_lower_fee_rate: bool,
+    // 入力の並び順。署名ハッシュ計算の前に確定する
+    #[serde(default)]
+    pub input_order: InputOrder,
+    // nLockTime をブロック高 (500,000,000 未満) で指定する。lockTimeTimestamp とは同時に指定できない
+    #[serde(default)]
+    pub lock_time_height: Option<u32>,
+    // nLockTime をUNIXタイムスタンプ (500,000,000 以上) で指定する
+    #[serde(default)]
+    pub lock_time_timestamp: Option<u32>,
+    // おつり出力のBIP32導出情報 (PSBT出力用)
+    #[serde(default)]
+    pub change_bip32_derivation: Option<Bip32DerivationDef>,
+}
+
+impl InputConfig {
+    /// 秘密鍵 (WIF) を伏せた複製を返す。設定内容の表示・ログ出力用
+    pub fn redacted(&self) -> InputConfig {
+        let mut config = self.clone();
+        for utxo in config.utxos.iter_mut().chain(config.fee_bump_utxos.iter_mut()) {
+            if utxo.private_key_wif.is_some() {
+                utxo.private_key_wif = Some(Redacted(REDACTED.to_string()));
+            }
+            for wif in &mut utxo.private_key_wifs {
+                *wif = Redacted(REDACTED.to_string());
+            }
+        }
+        config
+    }
+}
+
+// --network と同じ表記 ("bitcoin"/"mainnet", "testnet", "regtest") を受け付け、不正な値はパース時にエラーにする
+fn deserialize_network<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<BitcoinNetwork>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|network| parse_network(&network).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+// --dump-effective-config 用。--network と同じ表記で出力する
+fn serialize_network<S: Serializer>(network: &Option<BitcoinNetwork>, serializer: S) -> Result<S::Ok, S::Error> {
+    network.map(|network| network.to_string()).serialize(serializer)
+}
+
+// 金額 (sats) は整数のほか、桁を読みやすくするため "1_000_000" のような _ 区切りの文字列でも指定できる
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code)] // JSON Schema の生成専用
+enum SatsSchema {
+    Integer(u64),
+    Text(String),
+}
+
+// _ は数字の間にのみ置ける (先頭・末尾・連続は不可)
+fn parse_sats(text: &str) -> Result<u64, String> {
+    let valid = !text.is_empty()
+        && text.split('_').all(|group| !group.is_empty() && group.bytes().all(|byte| byte.is_ascii_digit()));
+    if !valid {
+        return Err(format!("金額 \"{}\" は数字のみ、または数字の間に _ を1つずつ挟んだ形式で指定してください", text));
+    }
+    text.replace('_', "").parse().map_err(|_| format!("金額 \"{}\" が大きすぎます", text))
+}
+
+struct SatsVisitor;
+
+impl de::Visitor<'_> for SatsVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("非負の整数、または \"1_000_000\" のような _ 区切りの数字の文字列")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<u64, E> {
+        u64::try_from(value).map_err(|_| E::custom(format!("金額 {} が負の値です", value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+        parse_sats(value).map_err(E::custom)
+    }
+}
+
+fn deserialize_sats<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    deserializer.deserialize_any(SatsVisitor)
+}
+
+fn deserialize_optional_sats<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    struct Sats(#[serde(deserialize_with = "deserialize_sats")] u64);
+    Ok(Option::<Sats>::deserialize(deserializer)?.map(|Sats(sats)| sats))
+}
+
+// PSBTの bip32_derivation に格納する鍵の導出情報。ハードウェアウォレットが自分の鍵を認識するために使う
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Bip32DerivationDef {
+    // マスター鍵のフィンガープリント (8桁のhex)
+    pub master_fingerprint: String,
+    // 例: "m/84'/1'/0'/0/0"
+    pub path: String,
+    // 導出される公開鍵 (hex)。出力では必須。入力では省略時にWIFの公開鍵を使う
+    #[serde(default)]
+    pub public_key_hex: Option<String>,
+}
+
+// changeAddress は1つのアドレス、または実行ごとに順に使うアドレスのリスト
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(untagged)]
+pub enum ChangeAddressDef {
+    Single(String),
+    Rotation(Vec<String>),
+}
+
+impl ChangeAddressDef {
+    pub fn addresses(&self) -> &[String] {
+        match self {
+            ChangeAddressDef::Single(address) => std::slice::from_ref(address),
+            ChangeAddressDef::Rotation(addresses) => addresses,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum InputOrder {
+    // 設定ファイルに記述された順 (feeBumpUtxos は utxos の後ろ)
+    #[default]
+    Config,
+    // BIP69: 前トランザクションのtxid (表示順のhex) の昇順、次に vout の昇順
+    Bip69,
+    // 金額の降順 (同額の場合は設定ファイルの順)
+    ValueDesc,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeDustPolicy {
+    // ダスト閾値未満のおつりは出力を作らず手数料に含める (Bitcoin Coreと同じ挙動)
+    #[default]
+    FoldToFee,
+    // ダスト閾値未満でも band 内のおつりは出力として含める
+    IncludeAnyway,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct UtxoInput {
     pub txid: String,
     pub vout: u32,
-    pub script_pubkey_hex: String,
+    // scriptPubkeyHex か scriptPubkeyAddress (別名 address) のどちらか一方を指定する。
+    // 標準的なアドレス (P2PKH/P2WPKH) ではアドレスとWIFだけで足り、WIFがそのアドレスを制御していることを確認する
+    #[serde(default)]
+    pub script_pubkey_hex: Option<String>,
+    #[serde(default, alias = "scriptPubKeyAddress", alias = "address")]
+    pub script_pubkey_address: Option<String>,
+    #[serde(deserialize_with = "deserialize_sats")]
+    #[schemars(with = "SatsSchema")]
     pub value_sats: u64,
-    pub private_key_wif: String,
+    // skipSigning の入力、および compute-sighashes で publicKeyHex を指定した入力では不要
+    #[serde(default)]
+    pub private_key_wif: Option<Redacted<String>>,
+    // ベアマルチシグの入力で署名に使うWIF。必要署名数と同じ数を、scriptPubKey の公開鍵と同じ順で指定する
+    #[serde(default)]
+    pub private_key_wifs: Vec<Redacted<String>>,
+    // compute-sighashes 用の公開鍵 (hex)。P2PK では省略時に scriptPubKey から取得する
+    #[serde(default)]
+    pub public_key_hex: Option<String>,
     #[serde(default)]
     pub sequence: Option<u32>,
+    // 例: "SIGHASH_ALL" (既定), "SIGHASH_SINGLE|SIGHASH_ANYONECANPAY"
+    #[serde(default)]
+    pub sighash_type: Option<String>,
+    // true の場合は署名せず、scriptSigHex / witnessHex をそのまま使う (手数料・サイズ計算には含まれる)
+    #[serde(default)]
+    pub skip_signing: bool,
+    // 署名済みの script_sig (hex)。skipSigning の入力でのみ指定できる
+    #[serde(default)]
+    pub script_sig_hex: Option<String>,
+    // 署名済みの witness の各要素 (hex)。skipSigning の入力でのみ指定できる
+    #[serde(default)]
+    pub witness_hex: Vec<String>,
+    // ログやエラーメッセージで入力を識別するためのラベル
+    #[serde(default)]
+    pub label: Option<String>,
+    // PSBT出力用のBIP32導出情報
+    #[serde(default)]
+    pub bip32_derivation: Option<Bip32DerivationDef>,
+    // 署名ハッシュ計算に使う script code (hex)。指定時は scriptPubKey から導出した script code の代わりに使う (特殊なスクリプト用)
+    #[serde(default)]
+    pub script_code_hex: Option<String>,
+    // このUTXOを作った前トランザクション全体 (hex)。txidと参照先の出力を照合し、PSBTの non_witness_utxo に使う
+    #[serde(default)]
+    pub prev_tx_hex: Option<String>,
+    // coinbaseトランザクションの出力。confirmations が 100 以上でなければ使用できない
+    #[serde(default)]
+    pub is_coinbase: bool,
+    // 確認数 (チェーンにアクセスしないため利用者が指定する)。isCoinbase の場合は必須。
+    // sequence がブロック数の相対ロックタイム (BIP68) を表す場合は、それが満たされているかの確認にも使う
+    #[serde(default)]
+    pub confirmations: Option<u32>,
+    // scriptPubKey の OP_CHECKLOCKTIMEVERIFY が要求するロックタイム (500,000,000 未満はブロック高、以上はUNIXタイムスタンプ)。
+    // nLockTime をこの値以上に設定し、sequence が未指定なら 0xfffffffe にする
+    #[serde(default)]
+    pub requires_locktime: Option<u32>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct TransactionOutputDef {
-    pub address: String,
+    // address / descriptor / payToPubkeyHex のいずれか1つを指定する
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub descriptor: Option<String>,
+    // P2PK出力 (<pubkey> OP_CHECKSIG) の公開鍵 (hex、圧縮または非圧縮)。レガシー・テスト用
+    #[serde(default)]
+    pub pay_to_pubkey_hex: Option<String>,
+    // BIP352 サイレントペイメントアドレス。入力の秘密鍵からP2TR出力を導出する
+    #[cfg(feature = "silent-payments")]
+    #[serde(default)]
+    pub silent_payment_address: Option<String>,
+    #[serde(deserialize_with = "deserialize_sats")]
+    #[schemars(with = "SatsSchema")]
     pub value_sats: u64,
-}
\ No newline at end of file
+    // ログやエラーメッセージで出力を識別するためのラベル
+    #[serde(default)]
+    pub label: Option<String>,
+    // PSBT出力用のBIP32導出情報
+    #[serde(default)]
+    pub bip32_derivation: Option<Bip32DerivationDef>,
+    // 請求書番号など下流システムの照合用メモ。JSON出力とPSBTの独自キーに含める (トランザクションには影響しない)
+    #[serde(default)]
+    pub memo: Option<String>,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config_with_amounts(utxo_value: serde_json::Value, min_change: serde_json::Value) -> Result<InputConfig, serde_json::Error> {
+        serde_json::from_value(json!({
+            "utxos": [{"txid": format!("{:064x}", 1), "vout": 0, "scriptPubkeyHex": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                "valueSats": utxo_value}],
+            "outputs": [{"address": "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r", "valueSats": 60_000}],
+            "minChangeSats": min_change,
+        }))
+    }
+
+    #[test]
+    fn amounts_accept_underscore_separated_strings_and_plain_integers() {
+        let config = config_with_amounts(json!("1_000_000"), json!("10_000")).unwrap();
+        assert_eq!(config.utxos[0].value_sats, 1_000_000);
+        assert_eq!(config.outputs[0].value_sats, 60_000);
+        assert_eq!(config.min_change_sats, Some(10_000));
+
+        let config = config_with_amounts(json!(1_000_000), json!(null)).unwrap();
+        assert_eq!(config.utxos[0].value_sats, 1_000_000);
+        assert_eq!(config.min_change_sats, None);
+        // 区切りのない数字の文字列も受け付ける
+        assert_eq!(config_with_amounts(json!("42"), json!(null)).unwrap().utxos[0].value_sats, 42);
+    }
+
+    #[test]
+    fn malformed_or_negative_amounts_are_rejected() {
+        for invalid in ["_1000", "1000_", "1__000", "1,000", "1 000", "", "1.5", "18_446_744_073_709_551_616"] {
+            assert!(config_with_amounts(json!(invalid), json!(null)).is_err(), "{}", invalid);
+        }
+        assert!(config_with_amounts(json!(-1), json!(null)).is_err());
+        assert!(config_with_amounts(json!(1.5), json!(null)).is_err());
+        assert!(config_with_amounts(json!(1_000), json!("1__0")).is_err());
+    }
+}
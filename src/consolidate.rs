@@ -0,0 +1,105 @@
+use bitcoin::{network::Network as BitcoinNetwork, secp256k1::{Secp256k1, Signing}};
+
+use crate::{
+    config::UtxoInput,
+    error::AppError,
+    fee_rate::FeeRate,
+    transaction::{estimated_input_vsize, process_utxo_input, KeyCache},
+    types::SigningOptions,
+};
+
+// 集約に含めない (経済的でない) と判断したUTXO
+#[derive(Debug)]
+pub struct DroppedUtxo {
+    pub utxo: UtxoInput,
+    // この入力を追加するための手数料 (入力のvsize × 手数料率)
+    pub marginal_fee_sats: u64,
+}
+
+// 入力1つ分の集約コストの見積もり
+#[derive(Debug)]
+pub struct InputCost {
+    pub outpoint: String,
+    pub label: Option<String>,
+    pub value_sats: u64,
+    // 署名後の入力1つ分の推定vsize
+    pub vsize: u64,
+    pub marginal_fee_sats: u64,
+}
+
+impl InputCost {
+    // 集約に含めた場合に増える純資産 (負なら含めると損)
+    pub fn net_sats(&self) -> i64 {
+        self.value_sats as i64 - self.marginal_fee_sats as i64
+    }
+}
+
+#[derive(Debug)]
+pub struct ConsolidationPlan {
+    pub kept: Vec<UtxoInput>,
+    pub dropped: Vec<DroppedUtxo>,
+}
+
+/// 各UTXOを入力に含める場合の推定vsizeと手数料 (marginal fee) を、与えられた順に見積もる。署名はしない。
+pub fn estimate_input_costs<C: Signing>(
+    utxos: &[UtxoInput],
+    fee_rate_sats_per_vb: FeeRate,
+    default_sequence: Option<u32>,
+    cli_network: BitcoinNetwork,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+) -> Result<Vec<InputCost>, AppError> {
+    utxos
+        .iter()
+        .enumerate()
+        .map(|(utxo_index, utxo)| estimate_input_cost(utxo_index, utxo, fee_rate_sats_per_vb, default_sequence, cli_network, secp, key_cache))
+        .collect()
+}
+
+/// utxos[utxo_index] の1件分の見積もり。UTXOを保持しないため、1件ずつ読み込んで破棄する呼び出し元からも使える
+pub fn estimate_input_cost<C: Signing>(
+    utxo_index: usize,
+    utxo: &UtxoInput,
+    fee_rate_sats_per_vb: FeeRate,
+    default_sequence: Option<u32>,
+    cli_network: BitcoinNetwork,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+) -> Result<InputCost, AppError> {
+    // 見積もりのための検証のみ。警告は本番の署名時に改めて収集される
+    let options = SigningOptions::default();
+    let processed = process_utxo_input(utxo_index, &format!("utxos[{}]", utxo_index), utxo, default_sequence, cli_network, secp, key_cache, &options, &mut Vec::new())?;
+    let vsize = estimated_input_vsize(&processed);
+    Ok(InputCost {
+        outpoint: processed.out_point.to_string(),
+        label: utxo.label.clone(),
+        value_sats: utxo.value_sats,
+        vsize,
+        marginal_fee_sats: fee_rate_sats_per_vb.fee_for_vsize(vsize).unwrap_or(u64::MAX),
+    })
+}
+
+/// 手数料率に対して経済的に集約できるUTXOを選ぶ。
+///
+/// 入力1つ分の手数料 (marginal fee) 以下の金額しか持たないUTXOは、含めると純資産が減るため除外する。
+pub fn plan_consolidation<C: Signing>(
+    utxos: Vec<UtxoInput>,
+    fee_rate_sats_per_vb: FeeRate,
+    default_sequence: Option<u32>,
+    cli_network: BitcoinNetwork,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+) -> Result<ConsolidationPlan, AppError> {
+    let costs = estimate_input_costs(&utxos, fee_rate_sats_per_vb, default_sequence, cli_network, secp, key_cache)?;
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    for (utxo, cost) in utxos.into_iter().zip(costs) {
+        let marginal_fee_sats = cost.marginal_fee_sats;
+        if cost.net_sats() > 0 {
+            kept.push(utxo);
+        } else {
+            dropped.push(DroppedUtxo { utxo, marginal_fee_sats });
+        }
+    }
+    Ok(ConsolidationPlan { kept, dropped })
+}
@@ -0,0 +1,198 @@
+use bitcoin::{
+    key::{PublicKey, XOnlyPublicKey},
+    network::Network as BitcoinNetwork,
+    script::ScriptBuf,
+    secp256k1::Secp256k1,
+    Address, CompressedPublicKey,
+};
+use std::str::FromStr;
+
+use crate::error::AppError;
+
+// BIP380 ディスクリプタチェックサムの文字集合
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_GENERATOR: [u64; 5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+
+fn descriptor_error(descriptor: &str, reason: impl Into<String>) -> AppError {
+    AppError::InvalidDescriptor {
+        descriptor: descriptor.to_string(),
+        reason: reason.into(),
+    }
+}
+
+fn checksum_polymod(symbols: &[u64]) -> u64 {
+    let mut chk: u64 = 1;
+    for value in symbols {
+        let top = chk >> 35;
+        chk = ((chk & 0x7ffffffff) << 5) ^ value;
+        for (i, generator) in CHECKSUM_GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+// BIP380 のチェックサム (8文字) を計算する。INPUT_CHARSET 外の文字を含む場合は None
+fn descriptor_checksum(body: &str) -> Option<String> {
+    let mut symbols: Vec<u64> = Vec::new();
+    let mut groups: Vec<u64> = Vec::new();
+    for c in body.chars() {
+        let v = INPUT_CHARSET.find(c)? as u64;
+        symbols.push(v & 31);
+        groups.push(v >> 5);
+        if groups.len() == 3 {
+            symbols.push(groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups.len() {
+        1 => symbols.push(groups[0]),
+        2 => symbols.push(groups[0] * 3 + groups[1]),
+        _ => {}
+    }
+    symbols.extend_from_slice(&[0; 8]);
+    let checksum = checksum_polymod(&symbols) ^ 1;
+    Some((0..8).map(|i| CHECKSUM_CHARSET[((checksum >> (5 * (7 - i))) & 31) as usize] as char).collect())
+}
+
+// "name(inner)" 形式であれば inner を返す
+fn strip_function<'a>(expr: &'a str, name: &str) -> Option<&'a str> {
+    expr.strip_prefix(name)?.strip_prefix('(')?.strip_suffix(')')
+}
+
+fn parse_compressed_key(descriptor: &str, key_hex: &str) -> Result<CompressedPublicKey, AppError> {
+    CompressedPublicKey::from_str(key_hex)
+        .map_err(|e| descriptor_error(descriptor, format!("圧縮公開鍵のパース失敗 ({}): {}", key_hex, e)))
+}
+
+/// 出力ディスクリプタを scriptPubKey に解決する。
+///
+/// 対応形式: `pkh(KEY)`, `wpkh(KEY)`, `sh(wpkh(KEY))`, `tr(KEY)` (キーパスのみ), `addr(ADDR)`, `raw(HEX)`。
+/// KEY は16進数の具体的な公開鍵のみ (xpub や導出パスは未対応)。末尾の `#checksum` は検証される。
+pub fn descriptor_to_script_pubkey(descriptor: &str, network: BitcoinNetwork) -> Result<ScriptBuf, AppError> {
+    let trimmed = descriptor.trim();
+    let body = match trimmed.split_once('#') {
+        Some((body, checksum)) => {
+            let expected = descriptor_checksum(body)
+                .ok_or_else(|| descriptor_error(descriptor, "ディスクリプタに使用できない文字が含まれています"))?;
+            if checksum != expected {
+                return Err(descriptor_error(descriptor, format!("チェックサム不一致 (期待値: {})", expected)));
+            }
+            body
+        }
+        None => trimmed,
+    };
+
+    if let Some(inner) = strip_function(body, "sh") {
+        let key_hex = strip_function(inner, "wpkh")
+            .ok_or_else(|| descriptor_error(descriptor, "sh() は wpkh() のラップのみ対応しています"))?;
+        let key = parse_compressed_key(descriptor, key_hex)?;
+        let redeem_script = ScriptBuf::new_p2wpkh(&key.wpubkey_hash());
+        return Ok(ScriptBuf::new_p2sh(&redeem_script.script_hash()));
+    }
+    if let Some(key_hex) = strip_function(body, "wpkh") {
+        let key = parse_compressed_key(descriptor, key_hex)?;
+        return Ok(ScriptBuf::new_p2wpkh(&key.wpubkey_hash()));
+    }
+    if let Some(key_hex) = strip_function(body, "pkh") {
+        let key = PublicKey::from_str(key_hex)
+            .map_err(|e| descriptor_error(descriptor, format!("公開鍵のパース失敗 ({}): {}", key_hex, e)))?;
+        return Ok(ScriptBuf::new_p2pkh(&key.pubkey_hash()));
+    }
+    if let Some(key_hex) = strip_function(body, "tr") {
+        if key_hex.contains(',') {
+            return Err(descriptor_error(descriptor, "tr() のスクリプトツリーは未対応です"));
+        }
+        // x-only (64文字) と圧縮公開鍵 (66文字) の両方を受け付ける
+        let internal_key = match key_hex.len() {
+            64 => XOnlyPublicKey::from_str(key_hex)
+                .map_err(|e| descriptor_error(descriptor, format!("x-only公開鍵のパース失敗 ({}): {}", key_hex, e)))?,
+            _ => parse_compressed_key(descriptor, key_hex)?.0.x_only_public_key().0,
+        };
+        let secp = Secp256k1::verification_only();
+        return Ok(ScriptBuf::new_p2tr(&secp, internal_key, None));
+    }
+    if let Some(addr) = strip_function(body, "addr") {
+        let address = Address::from_str(addr)
+            .and_then(|a| a.require_network(network))
+            .map_err(|e| descriptor_error(descriptor, format!("アドレス形式エラーまたはネットワーク不整合: {}", e)))?;
+        return Ok(address.script_pubkey());
+    }
+    if let Some(script_hex) = strip_function(body, "raw") {
+        let bytes = hex::decode(script_hex)
+            .map_err(|e| descriptor_error(descriptor, format!("raw() のhexデコード失敗: {}", e)))?;
+        return Ok(ScriptBuf::from_bytes(bytes));
+    }
+
+    Err(descriptor_error(descriptor, "未対応のディスクリプタ形式です"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP86 の最初の受け取り鍵 (m/86'/0'/0'/0/0) の内部鍵と、調整後の出力鍵の scriptPubKey・アドレス
+    const BIP86_INTERNAL_KEY: &str = "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115";
+    const BIP86_SCRIPT_HEX: &str = "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c";
+    const BIP86_ADDRESS: &str = "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr";
+
+    fn resolve(descriptor: &str) -> Result<String, AppError> {
+        descriptor_to_script_pubkey(descriptor, BitcoinNetwork::Bitcoin).map(|script| script.to_hex_string())
+    }
+
+    #[test]
+    fn checksum_follows_bip380_vectors() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").as_deref(), Some("89f8spxm"));
+        assert_eq!(resolve("raw(deadbeef)#89f8spxm").unwrap(), "deadbeef");
+        assert_eq!(resolve("raw(deadbeef)").unwrap(), "deadbeef");
+        for invalid in [
+            "raw(deadbeef)#",          // チェックサムが空
+            "raw(deadbeef)#89f8spxmx", // 9文字
+            "raw(deadbeef)#89f8spx",   // 7文字
+            "raw(deedbeef)#89f8spxm",  // 本体の誤り
+            "raw(deadbeef)##9f8spxm",  // チェックサムの誤り
+            "raw(Ü)#00000000",         // 文字集合外の文字
+        ] {
+            assert!(matches!(resolve(invalid), Err(AppError::InvalidDescriptor { .. })), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn key_descriptors_resolve_to_known_script_pubkeys() {
+        for (descriptor, script_hex) in [
+            ("wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)", "00147dd65592d0ab2fe0d0257d571abf032cd9db93dc"),
+            ("sh(wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556))", "a914cc6ffbc0bf31af759451068f90ba7a0272b6b33287"),
+            ("pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)", "76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac"),
+        ] {
+            assert_eq!(resolve(descriptor).unwrap(), script_hex, "{}", descriptor);
+        }
+        // tr() は x-only と圧縮公開鍵のどちらでも同じ BIP86 の出力鍵になる
+        assert_eq!(resolve(&format!("tr({})", BIP86_INTERNAL_KEY)).unwrap(), BIP86_SCRIPT_HEX);
+        assert_eq!(resolve(&format!("tr(02{})", BIP86_INTERNAL_KEY)).unwrap(), BIP86_SCRIPT_HEX);
+    }
+
+    #[test]
+    fn tr_with_script_tree_is_rejected() {
+        let descriptor = format!(
+            "tr({},{{pk(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5),pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556)}})",
+            BIP86_INTERNAL_KEY
+        );
+        match resolve(&descriptor) {
+            Err(AppError::InvalidDescriptor { reason, .. }) => assert!(reason.contains("スクリプトツリー"), "{}", reason),
+            other => panic!("tr() のスクリプトツリーが拒否されません: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn addr_must_match_the_network() {
+        let descriptor = format!("addr({})", BIP86_ADDRESS);
+        assert_eq!(resolve(&descriptor).unwrap(), BIP86_SCRIPT_HEX);
+        assert!(matches!(
+            descriptor_to_script_pubkey(&descriptor, BitcoinNetwork::Testnet),
+            Err(AppError::InvalidDescriptor { .. })
+        ));
+    }
+}
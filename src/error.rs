@@ -27,24 +27,35 @@ pub enum AppError {
     #[error("Bitcoin秘密鍵(WIF)処理エラー: {0}")]
     BitcoinKey(#[from] BitcoinKeyError),
 
-    #[error("Sighash計算エラー (入力インデックス {input_index}): {source}")]
+    #[error("Sighash計算エラー (入力インデックス {input_index}{}): {source}", label_suffix(.label.as_deref()))]
     SighashError{
         input_index: usize,
+        label: Option<String>,
         #[source]
         source: BitcoinSighashError,
     },
     
-    #[error("Sighash計算エラー (入力インデックス {input_index}): {source}")]
+    #[error("Sighash計算エラー (入力インデックス {input_index}{}): {source}", label_suffix(.label.as_deref()))]
+    TaprootSighashError {
+        input_index: usize,
+        label: Option<String>,
+        #[source]
+        source: bitcoin::sighash::TaprootError,
+    },
+
+    #[error("Sighash計算エラー (入力インデックス {input_index}{}): {source}", label_suffix(.label.as_deref()))]
     IndexError{
         input_index: usize,
+        label: Option<String>,
         #[source]
         source: BitcoinIndexError,
     },
 
 
-    #[error("署名エラー (入力インデックス {input_index}): {source}")]
+    #[error("署名エラー (入力インデックス {input_index}{}): {source}", label_suffix(.label.as_deref()))]
     SignatureError {
         input_index: usize,
+        label: Option<String>,
         #[source]
         source: bitcoin::ecdsa::Error, // secp256k1のエラー型
     },
@@ -55,9 +66,45 @@ pub enum AppError {
         inferred_network: String,
     },
 
+    #[error("ネットワーク不整合: ネットワーク {network} と一致しない鍵・アドレスがあります:\n{outliers}")]
+    NetworkInconsistency {
+        network: String,
+        outliers: String,
+    },
+
+    #[error("ネットワーク不整合: CLI指定 ({cli_network}) vs 設定ファイル ({config_network})")]
+    ConfigNetworkMismatch {
+        cli_network: String,
+        config_network: String,
+    },
+
+    #[error("--safe により中断しました: {0}")]
+    SafeModeViolation(String),
+
+    #[error("{context} の送金先 {destination} は許可リスト (--allowlist-file) にありません")]
+    RecipientNotAllowed { context: String, destination: String },
+
+    #[error("入力 {input_index}{} はP2WPKHですが、WIFが非圧縮公開鍵を示しています (SegWit v0 は圧縮公開鍵のみ。圧縮形式のWIFを指定してください)", label_suffix(.label.as_deref()))]
+    UncompressedKeyForSegwit { input_index: usize, label: Option<String> },
+
+    #[error("{context} がプリフライト確認に通りません: {failures}")]
+    PreflightFailed { context: String, failures: String },
+
+    #[error("changeAddress の {count} 件のアドレスはすべて {state_file:?} に使用済みとして記録されています (アドレスを追加してください)")]
+    ChangeAddressesExhausted { count: usize, state_file: PathBuf },
+
+    #[error("トランザクション {txid} が設定ファイルと一致しません: {failures}")]
+    TransactionMismatch { txid: String, failures: String },
+
+    #[error("設定エラー ({path}): {reason}")]
+    FieldValidation { path: String, reason: String },
+
     #[error("入力検証エラー: {0}")]
     InputValidation(String),
 
+    #[error("手数料を支払う入力 utxos[{input_index}] の金額 {value_sats} sats では手数料 {fee_sats} sats を賄えません")]
+    FeeInputInsufficient { input_index: usize, value_sats: u64, fee_sats: u64 },
+
     #[error("資金不足: 利用可能な総額 {available} sats, 要求額 {required} sats (手数料 {fee} sats を含む)")]
     InsufficientFunds {
         available: u64,
@@ -65,12 +112,100 @@ pub enum AppError {
         fee: u64,
     },
 
+    #[error("入力 {input_index} のscriptPubKeyはスクリプトとして解釈できません: {reason}")]
+    InvalidScript { input_index: usize, reason: String },
+
+    #[error("nLockTime の単位が一致しません: {first} と {second} を同時に満たすことはできません")]
+    IncompatibleLockTimes { first: String, second: String },
+
+    #[error("入力 {input_index}{} ({script_type}) の{location}が空です。署名されるべき入力が署名されていません (署名処理の不具合の可能性があります)", label_suffix(.label.as_deref()))]
+    MissingSignature { input_index: usize, label: Option<String>, script_type: String, location: &'static str },
+
+    #[error("入力 {input_index} の署名後のwitnessが不正です: {reason}")]
+    MalformedWitness { input_index: usize, reason: String },
+
+    #[error("coinbase出力 {input} は未成熟です: 確認数 {confirmations} (100 以上必要)")]
+    ImmatureCoinbase { input: String, confirmations: u32 },
+
+    #[error("出力合計が入力合計を超えています (手数料を除いても不足): 入力合計 {inputs} sats, 出力合計 {outputs} sats")]
+    OutputsExceedInputs { inputs: u64, outputs: u64 },
+
+    #[error("メインネットで手数料率 0 sats/vB は指定できません (意図的な場合は --allow-low-fee を指定してください)")]
+    ZeroFeeRateOnMainnet,
+
+    #[error("手数料 {fee} sats が最低リレー手数料 {min} sats を下回っています")]
+    FeeBelowMinRelay { fee: u64, min: u64 },
+
+    #[error("手数料率 {fee_rate_sats_per_vb} sats/vB が上限 {ceiling} sats/vB を超えています (sat/kvB と sat/vB の取り違えではありませんか?)")]
+    FeeRateTooHigh { fee_rate_sats_per_vb: crate::fee_rate::FeeRate, ceiling: u64 },
+
+    #[error("手数料が期待値と一致しません: 期待値 {expected} sats, 実際 {actual} sats")]
+    FeeMismatch { expected: u64, actual: u64 },
+
+    #[error("入力数が上限を超えています: {count} 件 (上限 {max} 件)")]
+    TooManyInputs { count: usize, max: usize },
+
+    #[error("出力数が上限を超えています: {count} 件 (上限 {max} 件)")]
+    TooManyOutputs { count: usize, max: usize },
+
+    #[error("入力数 {inputs} 件と出力数 {outputs} 件の比が上限 {max_ratio} (--max-io-ratio) を超えています。設定ファイルの生成を誤っていませんか?")]
+    InputOutputRatioExceeded { inputs: usize, outputs: usize, max_ratio: f64 },
+
+    #[error("トランザクションの推定ウェイト {weight} WU が上限 {max} WU を超えています")]
+    TransactionTooHeavy { weight: u64, max: u64 },
+
+    #[error("スイープ出力がダスト閾値未満になります: 利用可能な総額 {available} sats, 手数料 {fee} sats。{suggestion}")]
+    SweepOutputBelowDust {
+        available: u64,
+        fee: u64,
+        suggestion: String,
+    },
+
     #[error("おつりアドレスの導出に失敗しました: {0}")]
     ChangeAddressDerivation(String),
 
+    #[error("ディスクリプタエラー ({descriptor}): {reason}")]
+    InvalidDescriptor {
+        descriptor: String,
+        reason: String,
+    },
+
+    #[error("設定ファイルを {url} から取得できません: {reason}")]
+    ConfigFetch { url: String, reason: String },
+
+    #[error("手数料率ソースエラー ({location}): {reason}")]
+    FeeSource {
+        location: String,
+        reason: String,
+    },
+
+    #[cfg(feature = "silent-payments")]
+    #[error("サイレントペイメントエラー: {0}")]
+    SilentPayment(String),
+
+    #[error("PSBTエラー: {0}")]
+    Psbt(String),
+
     #[error("不明なスクリプトタイプ: {script_hex}")]
     UnknownScriptType { script_hex: String },
 
+    #[error("未対応のwitnessバージョン v{version} のため署名できません (プログラム: {program_hex})。他者が署名済みの場合は skipSigning を指定してください")]
+    UnsupportedWitnessVersion { version: u8, program_hex: String },
+
+    #[error("strictモード違反: {0}")]
+    StrictModeViolation(String),
+
     #[error("内部エラー: {0}")]
     Internal(String),
 }
+
+// 設定ファイル内の位置 (例: parent "utxos[2]", field "scriptPubkeyHex" -> "utxos[2].scriptPubkeyHex") を示す検証エラー
+pub fn field_error(parent: &str, field: &str, reason: impl Into<String>) -> AppError {
+    let path = if parent.is_empty() { field.to_string() } else { format!("{}.{}", parent, field) };
+    AppError::FieldValidation { path, reason: reason.into() }
+}
+
+// ラベル付きの入力・出力をログやエラーで "入力 2 (cold-storage-1)" のように表示するための接尾辞
+pub fn label_suffix(label: Option<&str>) -> String {
+    label.map(|label| format!(" ({})", label)).unwrap_or_default()
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use std::str::FromStr;
+
+use schemars::{r#gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+// 小数点以下は 0.001 sat/vB (= 1 sat/kvB) まで扱う
+const FRACTION_DIGITS: usize = 3;
+const SCALE: u64 = 1000;
+
+/// 手数料率 (sats/vB)。1.5 のような小数を浮動小数点の誤差なく扱うため、sat/kvB (1/1000 sat/vB) 単位の整数で保持する。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    pub const fn from_sat_per_vb(sat_per_vb: u64) -> FeeRate {
+        FeeRate(sat_per_vb.saturating_mul(SCALE))
+    }
+
+    /// vsize (vB) に対する手数料 (sats)。端数は手数料率を下回らないよう切り上げる。オーバーフロー時は None
+    pub fn fee_for_vsize(self, vsize: u64) -> Option<u64> {
+        let fee_sat_per_kvb = u128::from(vsize) * u128::from(self.0);
+        u64::try_from(fee_sat_per_kvb.div_ceil(u128::from(SCALE))).ok()
+    }
+
+    /// 手数料とvsizeから求めた実効の手数料率 (0.001 sat/vB 未満は切り捨て)
+    pub fn from_fee_and_vsize(fee_sats: u64, vsize: u64) -> FeeRate {
+        let rate = u128::from(fee_sats) * u128::from(SCALE) / u128::from(vsize.max(1));
+        FeeRate(u64::try_from(rate).unwrap_or(u64::MAX))
+    }
+
+    /// 外部の推定値 (浮動小数点) から変換する。0.001 sat/vB 未満の端数は目標を下回らないよう切り上げる
+    pub fn from_f64_ceil(sat_per_vb: f64) -> Option<FeeRate> {
+        if !sat_per_vb.is_finite() || sat_per_vb < 0.0 {
+            return None;
+        }
+        // Display は元の値に戻る最短の10進表記になるため、2進数の誤差 (1.1 -> 1.1000000000000000888...) を持ち込まない
+        parse_decimal(&sat_per_vb.to_string(), true).ok()
+    }
+}
+
+// "12" や "1.5" を sat/kvB に変換する。round_up でない場合、小数点以下4桁目以降に0以外があればエラー
+fn parse_decimal(text: &str, round_up: bool) -> Result<FeeRate, String> {
+    let (integer_part, fraction_part) = text.split_once('.').unwrap_or((text, ""));
+    let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if integer_part.is_empty() || !is_digits(integer_part) || !is_digits(fraction_part) {
+        return Err(format!("手数料率 {} は非負の10進数ではありません", text));
+    }
+    let (kept, rest) = fraction_part.split_at(fraction_part.len().min(FRACTION_DIGITS));
+    let has_remainder = rest.bytes().any(|byte| byte != b'0');
+    if has_remainder && !round_up {
+        return Err(format!("手数料率 {} は小数点以下 {} 桁 (0.001 sat/vB) までで指定してください", text, FRACTION_DIGITS));
+    }
+    let overflow = || format!("手数料率 {} が大きすぎます", text);
+    let integer: u64 = integer_part.parse().map_err(|_| overflow())?;
+    let fraction: u64 = format!("{:0<width$}", kept, width = FRACTION_DIGITS).parse().map_err(|_| overflow())?;
+    integer
+        .checked_mul(SCALE)
+        .and_then(|sat_per_kvb| sat_per_kvb.checked_add(fraction))
+        .and_then(|sat_per_kvb| sat_per_kvb.checked_add(u64::from(has_remainder)))
+        .map(FeeRate)
+        .ok_or_else(overflow)
+}
+
+impl FromStr for FeeRate {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<FeeRate, String> {
+        parse_decimal(text.trim(), false)
+    }
+}
+
+// 整数の手数料率は "2"、小数は末尾の0を除いて "1.5" のように表示する
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (integer, fraction) = (self.0 / SCALE, self.0 % SCALE);
+        if fraction == 0 {
+            write!(f, "{}", integer)
+        } else {
+            let fraction = format!("{:0width$}", fraction, width = FRACTION_DIGITS);
+            write!(f, "{}.{}", integer, fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+// JSON出力では整数の手数料率は従来どおり整数、小数を含む場合のみ小数で出力する
+impl Serialize for FeeRate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_multiple_of(SCALE) {
+            serializer.serialize_u64(self.0 / SCALE)
+        } else {
+            serializer.serialize_f64(self.0 as f64 / SCALE as f64)
+        }
+    }
+}
+
+// 設定ファイルでは数値 (2, 1.5) と文字列 ("1.5") のどちらも受け付ける
+impl<'de> Deserialize<'de> for FeeRate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FeeRate, D::Error> {
+        struct FeeRateVisitor;
+
+        impl de::Visitor<'_> for FeeRateVisitor {
+            type Value = FeeRate;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("非負の手数料率 (sats/vB、小数点以下3桁まで)")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<FeeRate, E> {
+                value.checked_mul(SCALE).map(FeeRate).ok_or_else(|| E::custom(format!("手数料率 {} が大きすぎます", value)))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<FeeRate, E> {
+                let value = u64::try_from(value).map_err(|_| E::custom(format!("手数料率 {} が負の値です", value)))?;
+                self.visit_u64(value)
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<FeeRate, E> {
+                if !value.is_finite() || value < 0.0 {
+                    return Err(E::custom(format!("手数料率 {} は非負の有限な数値である必要があります", value)));
+                }
+                parse_decimal(&value.to_string(), false).map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<FeeRate, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(FeeRateVisitor)
+    }
+}
+
+impl JsonSchema for FeeRate {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        "FeeRate".to_string()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        f64::json_schema(generator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimal_rates_are_parsed_exactly_and_integers_keep_working() {
+        assert_eq!("2".parse::<FeeRate>(), Ok(FeeRate::from_sat_per_vb(2)));
+        assert_eq!("1.5".parse::<FeeRate>(), Ok(FeeRate(1_500)));
+        assert_eq!(" 0.001 ".parse::<FeeRate>(), Ok(FeeRate(1)));
+        assert_eq!("1.2500".parse::<FeeRate>(), Ok(FeeRate(1_250)));
+        // 小数点以下4桁目以降の0以外は丸めずにエラーにする
+        for invalid in ["1.0005", "-1", ".5", "1e3", "abc", "18446744073709552"] {
+            assert!(invalid.parse::<FeeRate>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn fee_is_rounded_up_so_the_rate_is_never_undershot() {
+        let rate: FeeRate = "1.5".parse().unwrap();
+        assert_eq!(rate.fee_for_vsize(141), Some(212));
+        assert_eq!(rate.fee_for_vsize(140), Some(210));
+        assert_eq!(FeeRate::from_fee_and_vsize(212, 141), FeeRate(1_503));
+        assert_eq!(FeeRate(u64::MAX).fee_for_vsize(u64::MAX), None);
+    }
+
+    #[test]
+    fn json_accepts_integers_decimals_and_strings_and_writes_integers_back_as_integers() {
+        // 1.1 は2進数では正確に表せないが、0.001 sat/vB 単位では誤差なく 1.1 になる
+        assert_eq!(serde_json::from_str::<FeeRate>("1.1").unwrap(), FeeRate(1_100));
+        assert_eq!(serde_json::from_str::<FeeRate>("2").unwrap(), FeeRate::from_sat_per_vb(2));
+        assert_eq!(serde_json::from_str::<FeeRate>("\"1.5\"").unwrap(), FeeRate(1_500));
+        assert!(serde_json::from_str::<FeeRate>("-1").is_err());
+        assert!(serde_json::from_str::<FeeRate>("1.0001").is_err());
+
+        assert_eq!(serde_json::to_string(&FeeRate::from_sat_per_vb(2)).unwrap(), "2");
+        assert_eq!(serde_json::to_string(&FeeRate(1_500)).unwrap(), "1.5");
+        assert_eq!(FeeRate(1_050).to_string(), "1.05");
+        assert_eq!(FeeRate::from_f64_ceil(1.0001), Some(FeeRate(1_001)));
+        assert_eq!(FeeRate::from_f64_ceil(f64::NAN), None);
+    }
+}
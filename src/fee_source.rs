@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::AppError;
+use crate::fee_rate::FeeRate;
+
+fn fee_source_error(location: &str, reason: impl Into<String>) -> AppError {
+    AppError::FeeSource {
+        location: location.to_string(),
+        reason: reason.into(),
+    }
+}
+
+#[cfg(feature = "http")]
+fn fetch_url(url: &str) -> Result<String, AppError> {
+    ureq::get(url)
+        .call()
+        .map_err(|e| fee_source_error(url, format!("HTTPリクエスト失敗: {}", e)))?
+        .into_string()
+        .map_err(|e| fee_source_error(url, format!("レスポンスの読み込み失敗: {}", e)))
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch_url(url: &str) -> Result<String, AppError> {
+    Err(fee_source_error(url, "URLからの取得には `http` フィーチャーを有効にしてビルドしてください"))
+}
+
+/// ファイルパスまたはURLのJSONドキュメントから手数料率 (sats/vB) を読み取る。
+///
+/// `field_path` はドット区切りのフィールドパス (例: mempool.spaceの `fastestFee`)。
+/// 0.001 sat/vB 未満の端数は目標を下回らないよう切り上げる。
+pub fn load_fee_rate(location: &str, field_path: &str) -> Result<FeeRate, AppError> {
+    let body = if location.starts_with("http://") || location.starts_with("https://") {
+        fetch_url(location)?
+    } else {
+        fs::read_to_string(Path::new(location))
+            .map_err(|e| fee_source_error(location, format!("ファイルの読み込み失敗: {}", e)))?
+    };
+
+    let document: serde_json::Value = serde_json::from_str(&body)
+        .map_err(|e| fee_source_error(location, format!("JSONパース失敗: {}", e)))?;
+
+    let mut current = &document;
+    for segment in field_path.split('.') {
+        current = match current {
+            serde_json::Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            other => other.get(segment),
+        }
+        .ok_or_else(|| fee_source_error(location, format!("フィールド `{}` が見つかりません", field_path)))?;
+    }
+
+    current
+        .as_f64()
+        .and_then(FeeRate::from_f64_ceil)
+        .ok_or_else(|| fee_source_error(location, format!("フィールド `{}` が非負の数値ではありません: {}", field_path, current)))
+}
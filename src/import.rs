@@ -0,0 +1,253 @@
+#[cfg(feature = "electrum-import")]
+use std::collections::HashMap;
+
+#[cfg(feature = "electrum-import")]
+use bitcoin::{Amount, Denomination};
+
+use crate::{config::UtxoInput, error::AppError, redact::Redacted};
+
+#[cfg(feature = "electrum-import")]
+// 取り込み時に解釈するElectrumのフィールド。height/coinbase は署名に不要なため読み捨てる
+const ELECTRUM_KNOWN_FIELDS: [&str; 8] =
+    ["prevout_hash", "prevout_n", "address", "value", "privkey", "nsequence", "height", "coinbase"];
+
+#[cfg(feature = "electrum-import")]
+// Electrumの秘密鍵エクスポートは "p2wpkh:<WIF>" のようにスクリプト種別を前置するため取り除く
+fn strip_electrum_key_prefix(key: &str) -> &str {
+    key.split_once(':').map_or(key, |(_, wif)| wif)
+}
+
+#[cfg(feature = "electrum-import")]
+fn electrum_value_to_sats(value: &serde_json::Value) -> Result<u64, String> {
+    let btc_str = match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
+        other => return Err(format!("value がBTC表記の文字列または数値ではありません: {}", other)),
+    };
+    Amount::from_str_in(&btc_str, Denomination::Bitcoin)
+        .map(Amount::to_sat)
+        .map_err(|e| format!("value ({}) のBTC→sats変換失敗: {}", btc_str, e))
+}
+
+#[cfg(feature = "electrum-import")]
+/// Electrumの `listunspent` (coins) 形式のJSONを `UtxoInput` の一覧に変換する。
+///
+/// トップレベルは配列、または `coins` 配列を持つオブジェクト。秘密鍵はエントリの `privkey`、
+/// なければ `keys` (アドレス→WIF、Electrumの秘密鍵エクスポート形式) から補う。
+/// 変換できないエントリがあれば黙って捨てず、全件の理由をまとめてエラーにする。
+pub fn import_electrum_coins(json: &str, keys: &HashMap<String, String>) -> Result<Vec<UtxoInput>, AppError> {
+    let document: serde_json::Value = serde_json::from_str(json)
+        .map_err(|e| AppError::InputValidation(format!("Electrum coins JSONのパース失敗: {}", e)))?;
+    let entries = match &document {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Object(map) => map.get("coins").and_then(|c| c.as_array()).ok_or_else(|| {
+            AppError::InputValidation("Electrum coins JSONに `coins` 配列がありません".to_string())
+        })?,
+        _ => return Err(AppError::InputValidation("Electrum coins JSONは配列またはオブジェクトである必要があります".to_string())),
+    };
+
+    let mut utxos = Vec::new();
+    let mut problems = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let Some(fields) = entry.as_object() else {
+            problems.push(format!("エントリ {}: オブジェクトではありません", index));
+            continue;
+        };
+        for key in fields.keys().filter(|k| !ELECTRUM_KNOWN_FIELDS.contains(&k.as_str())) {
+            log::warn!("Electrumエントリ {}: 未対応のフィールド `{}` を無視します", index, key);
+        }
+
+        let txid = fields.get("prevout_hash").and_then(|v| v.as_str());
+        let vout = fields.get("prevout_n").and_then(|v| v.as_u64()).and_then(|n| u32::try_from(n).ok());
+        let address = fields.get("address").and_then(|v| v.as_str());
+        let (Some(txid), Some(vout), Some(address)) = (txid, vout, address) else {
+            problems.push(format!("エントリ {}: prevout_hash / prevout_n / address のいずれかが欠落または不正です", index));
+            continue;
+        };
+        let value_sats = match fields.get("value").map(electrum_value_to_sats) {
+            Some(Ok(sats)) => sats,
+            Some(Err(reason)) => {
+                problems.push(format!("エントリ {} ({}:{}): {}", index, txid, vout, reason));
+                continue;
+            }
+            None => {
+                problems.push(format!("エントリ {} ({}:{}): value がありません", index, txid, vout));
+                continue;
+            }
+        };
+        let private_key = fields.get("privkey").and_then(|v| v.as_str()).or_else(|| keys.get(address).map(String::as_str));
+        let Some(private_key) = private_key else {
+            problems.push(format!("エントリ {} ({}:{}): アドレス {} の秘密鍵がありません", index, txid, vout, address));
+            continue;
+        };
+        let sequence = fields.get("nsequence").and_then(|v| v.as_u64()).and_then(|n| u32::try_from(n).ok());
+
+        utxos.push(UtxoInput {
+            txid: txid.to_string(),
+            vout,
+            script_pubkey_hex: None,
+            script_pubkey_address: Some(address.to_string()),
+            value_sats,
+            private_key_wif: Some(Redacted(strip_electrum_key_prefix(private_key).to_string())),
+            public_key_hex: None,
+            sequence,
+            sighash_type: None,
+            skip_signing: false,
+            script_sig_hex: None,
+            witness_hex: Vec::new(),
+            label: fields.get("label").and_then(|v| v.as_str()).map(str::to_string),
+            bip32_derivation: None,
+            script_code_hex: None,
+            private_key_wifs: Vec::new(),
+            prev_tx_hex: None,
+            is_coinbase: false,
+            confirmations: None,
+            requires_locktime: None,
+        });
+    }
+
+    if !problems.is_empty() {
+        return Err(AppError::InputValidation(format!(
+            "Electrum coinsの取り込みに失敗したエントリがあります:\n{}", problems.join("\n"))));
+    }
+    log::info!("Electrum coinsから {} 件のUTXOを取り込みました。", utxos.len());
+    Ok(utxos)
+}
+
+// CSVの1行をフィールドに分割する。ダブルクォートで囲んだフィールド (内部の "" は " を表す) に対応する
+#[cfg(feature = "csv-import")]
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut in_quotes = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("ダブルクォートが閉じられていません".to_string());
+    }
+    fields.push(field);
+    Ok(fields.into_iter().map(|f| f.trim().to_string()).collect())
+}
+
+// 列名 (ヘッダーまたは --csv-columns) を正規化する。大文字小文字と _ - を区別しない
+#[cfg(feature = "csv-import")]
+fn csv_column_name(name: &str) -> Option<&'static str> {
+    match name.trim().to_ascii_lowercase().replace(['_', '-'], "").as_str() {
+        "txid" => Some("txid"),
+        "vout" => Some("vout"),
+        "value" | "valuesats" => Some("value"),
+        "scriptpubkey" | "scriptpubkeyhex" => Some("scriptPubKey"),
+        "address" => Some("address"),
+        "wif" | "privatekeywif" => Some("wif"),
+        _ => None,
+    }
+}
+
+/// CSVを `UtxoInput` の一覧に変換する (value は sats 単位の整数)。
+///
+/// `columns` 指定時はヘッダー行なしとみなし、その順で列を割り当てる。未指定時は1行目のヘッダー名で割り当てる。
+/// 不正な行は黙って捨てず、行番号付きで全件の理由をまとめてエラーにする。
+#[cfg(feature = "csv-import")]
+pub fn import_csv_utxos(csv: &str, columns: Option<&[String]>) -> Result<Vec<UtxoInput>, AppError> {
+    let mut lines = csv.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let column_names: Vec<Option<&'static str>> = match columns {
+        Some(columns) => columns
+            .iter()
+            .map(|name| csv_column_name(name)
+                .map(Some)
+                .ok_or_else(|| AppError::InputValidation(format!("--csv-columns の列名 {} は不明です", name))))
+            .collect::<Result<_, _>>()?,
+        None => {
+            let (_, header) = lines.next().ok_or_else(|| AppError::InputValidation("CSVが空です".to_string()))?;
+            let header_fields = split_csv_line(header)
+                .map_err(|reason| AppError::InputValidation(format!("CSVのヘッダー行: {}", reason)))?;
+            header_fields
+                .iter()
+                .map(|name| {
+                    let column = csv_column_name(name);
+                    if column.is_none() {
+                        log::warn!("CSVの列 {} は不明なため無視します。", name);
+                    }
+                    column
+                })
+                .collect()
+        }
+    };
+    for required in ["txid", "vout", "value"] {
+        if !column_names.contains(&Some(required)) {
+            return Err(AppError::InputValidation(format!("CSVに {} 列がありません", required)));
+        }
+    }
+    if !column_names.contains(&Some("scriptPubKey")) && !column_names.contains(&Some("address")) {
+        return Err(AppError::InputValidation("CSVに scriptPubKey 列か address 列が必要です".to_string()));
+    }
+
+    let mut utxos = Vec::new();
+    let mut problems = Vec::new();
+    for (line_index, line) in lines {
+        let line_number = line_index + 1;
+        let fields = match split_csv_line(line) {
+            Ok(fields) if fields.len() == column_names.len() => fields,
+            Ok(fields) => {
+                problems.push(format!("{} 行目: 列数 {} が期待値 {} と一致しません", line_number, fields.len(), column_names.len()));
+                continue;
+            }
+            Err(reason) => {
+                problems.push(format!("{} 行目: {}", line_number, reason));
+                continue;
+            }
+        };
+        let field = |name: &str| {
+            column_names
+                .iter()
+                .position(|column| *column == Some(name))
+                .map(|index| fields[index].as_str())
+                .filter(|value| !value.is_empty())
+        };
+        let vout = field("vout").map(str::parse::<u32>);
+        let value_sats = field("value").map(str::parse::<u64>);
+        let (Some(txid), Some(Ok(vout)), Some(Ok(value_sats))) = (field("txid"), vout, value_sats) else {
+            problems.push(format!("{} 行目: txid / vout / value のいずれかが欠落または不正です (value は sats 単位の整数)", line_number));
+            continue;
+        };
+        utxos.push(UtxoInput {
+            txid: txid.to_string(),
+            vout,
+            script_pubkey_hex: field("scriptPubKey").map(str::to_string),
+            script_pubkey_address: field("address").map(str::to_string),
+            value_sats,
+            private_key_wif: field("wif").map(|wif| Redacted(wif.to_string())),
+            public_key_hex: None,
+            sequence: None,
+            sighash_type: None,
+            skip_signing: false,
+            script_sig_hex: None,
+            witness_hex: Vec::new(),
+            label: None,
+            bip32_derivation: None,
+            script_code_hex: None,
+            private_key_wifs: Vec::new(),
+            prev_tx_hex: None,
+            is_coinbase: false,
+            confirmations: None,
+            requires_locktime: None,
+        });
+    }
+
+    if !problems.is_empty() {
+        return Err(AppError::InputValidation(format!(
+            "CSVの取り込みに失敗した行があります:\n{}", problems.join("\n"))));
+    }
+    log::info!("CSVから {} 件のUTXOを取り込みました。", utxos.len());
+    Ok(utxos)
+}
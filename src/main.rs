@@ -1,66 +1,1419 @@
+use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use bitcoin::{Address, Network as BitcoinNetwork, ScriptBuf, Transaction};
 use bitcoin::consensus::encode;
-use bitcoin::secp256k1::Secp256k1; // All context を使う場合は secp256k1::All が必要
-use bitcoin::secp256k1::All as AllContext; // エイリアス
+use bitcoin::sighash::TapSighashType;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{Secp256k1, Signing};
 use clap::Parser;
 
+mod change_state;
 mod config;
 mod transaction;
 mod error;
 mod types;
 mod cli;
+mod consolidate;
+mod descriptor;
+mod fee_rate;
+mod fee_source;
+mod psbt;
+mod preflight;
+mod redact;
+mod verify;
+#[cfg(feature = "silent-payments")]
+mod silent_payment;
+#[cfg(feature = "bbqr")]
+mod bbqr;
+#[cfg(any(feature = "electrum-import", feature = "csv-import"))]
+mod import;
+#[cfg(all(test, feature = "regtest"))]
+mod regtest;
 
-use config::InputConfig;
+use config::{ChangeAddressDef, InputConfig, UtxoInput};
 use error::AppError;
-use cli::{CliArgs, parse_network};
+use fee_rate::FeeRate;
+use cli::{CliArgs, Command, detect_network_from_wif, parse_extra_input_type, parse_network};
+use transaction::KeyCache;
+use types::{SafeModePolicy, ScriptType, SignedInput, SignedTransaction, SigningOptions};
 
-fn main() -> Result<(), AppError> {
-    env_logger::init();
+// URLから取得する設定ファイルの上限サイズ。コーディネーターの誤設定で巨大なレスポンスを読み込まないようにする
+#[cfg(feature = "http")]
+const MAX_CONFIG_FETCH_BYTES: u64 = 16 * 1024 * 1024;
 
-    let args = CliArgs::parse();
-    log::info!("アプリケーションを開始します。引数: {:?}", args);
+// 設定ファイルをURLから取得する。取得の失敗は ConfigFetch、取得後のパースの失敗は JsonParse として区別する
+#[cfg(feature = "http")]
+fn fetch_config(url: &str) -> Result<Vec<u8>, AppError> {
+    use std::io::Read;
 
-    let cli_network = parse_network(&args.network)?;
-    log::info!("指定されたネットワーク: {:?}", cli_network);
+    let fetch_error = |reason: String| AppError::ConfigFetch { url: url.to_string(), reason };
+    let response = ureq::get(url)
+        .timeout(std::time::Duration::from_secs(30))
+        .call()
+        .map_err(|e| fetch_error(format!("HTTPリクエスト失敗: {}", e)))?;
+    // application/json と application/*+json を受け付ける (charset などのパラメータは content_type() で除かれる)
+    let content_type = response.content_type().to_ascii_lowercase();
+    if content_type != "application/json" && !(content_type.starts_with("application/") && content_type.ends_with("+json")) {
+        return Err(fetch_error(format!("Content-Type が {} です (application/json である必要があります)", content_type)));
+    }
+    if let Some(length) = response.header("Content-Length").and_then(|length| length.parse::<u64>().ok())
+        && length > MAX_CONFIG_FETCH_BYTES
+    {
+        return Err(fetch_error(format!("サイズ {} バイトが上限 {} バイトを超えています", length, MAX_CONFIG_FETCH_BYTES)));
+    }
+    // Content-Length がない (chunked) 場合に備え、上限を1バイト超えて読めたらエラーにする
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_CONFIG_FETCH_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| fetch_error(format!("レスポンスの読み込み失敗: {}", e)))?;
+    if body.len() as u64 > MAX_CONFIG_FETCH_BYTES {
+        return Err(fetch_error(format!("サイズが上限 {} バイトを超えています", MAX_CONFIG_FETCH_BYTES)));
+    }
+    log::info!("設定ファイルを {} から取得しました ({} バイト)。", url, body.len());
+    Ok(body)
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch_config(url: &str) -> Result<Vec<u8>, AppError> {
+    Err(AppError::ConfigFetch {
+        url: url.to_string(),
+        reason: "URLからの取得には `http` フィーチャーを有効にしてビルドしてください".to_string(),
+    })
+}
+
+// --input-file が http:// または https:// で始まる場合はURLとして扱う
+fn config_url(input_file: &Path) -> Option<&str> {
+    input_file.to_str().filter(|location| location.starts_with("http://") || location.starts_with("https://"))
+}
+
+// ファイル全体を文字列として読み込まず、バッファ経由で直接パースする (URLの場合は取得したレスポンスをパースする)。
+// ストリーミングではなく、パース結果 (全UTXO) はすべてメモリ上に保持される。省けるのは元のJSON文字列の分のみ。
+// UTXOを1件ずつ処理するには --utxos-jsonl を使う (for_each_jsonl_utxo)
+fn load_config(input_file: &Path) -> Result<InputConfig, AppError> {
+    if let Some(url) = config_url(input_file) {
+        let body = fetch_config(url)?;
+        let config: InputConfig = serde_json::from_slice(&body).map_err(|e| {
+            log::error!("{} から取得した設定ファイルのパースに失敗しました。", url);
+            AppError::JsonParse {
+                file_path: input_file.to_path_buf(),
+                source: e,
+            }
+        })?;
+        log::debug!("入力設定ファイルのパース成功: {:?}", config.redacted());
+        return Ok(config);
+    }
 
-    let input_file_content = fs::read_to_string(&args.input_file).map_err(|e| {
-        log::error!("入力ファイルの読み込みに失敗しました: {:?}", args.input_file);
+    let input_file_handle = File::open(input_file).map_err(|e| {
+        log::error!("入力ファイルの読み込みに失敗しました: {:?}", input_file);
         AppError::Io(e)
     })?;
 
-    let config: InputConfig = serde_json::from_str(&input_file_content).map_err(|e| {
+    let config: InputConfig = serde_json::from_reader(BufReader::new(input_file_handle)).map_err(|e| {
         log::error!("入力JSONのパースに失敗しました。");
         AppError::JsonParse {
-            file_path: args.input_file.clone(),
+            file_path: input_file.to_path_buf(),
             source: e,
         }
     })?;
-    log::debug!("入力設定ファイルのパース成功: {:?}", config);
+    log::debug!("入力設定ファイルのパース成功: {:?}", config.redacted());
+    Ok(config)
+}
+
+// 1行1UTXOのJSON Linesファイルを1行ずつパースし、1件ずつ visit に渡す。空行は無視する。
+// 渡したUTXOはここでは保持しないため、読み込み中に確保するのは1行分のみ。読み込んだ件数を返す
+fn for_each_jsonl_utxo(path: &Path, mut visit: impl FnMut(UtxoInput) -> Result<(), AppError>) -> Result<usize, AppError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut count = 0;
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let utxo: UtxoInput = serde_json::from_str(&line).map_err(|e| {
+            log::error!("{:?} の {} 行目のパースに失敗しました。", path, line_index + 1);
+            AppError::JsonParse {
+                file_path: path.to_path_buf(),
+                source: e,
+            }
+        })?;
+        visit(utxo)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// 署名には全入力が必要なため、JSON LinesのUTXOを utxos の末尾にすべて追加する。
+// 先に行数を数えて一度だけ確保し、配列の拡張で古い領域と新しい領域を同時に持たないようにする
+fn load_utxos_jsonl(path: &Path, utxos: &mut Vec<UtxoInput>) -> Result<(), AppError> {
+    let line_count = BufReader::new(File::open(path)?).lines().count();
+    utxos.reserve_exact(line_count);
+    let count = for_each_jsonl_utxo(path, |utxo| {
+        utxos.push(utxo);
+        Ok(())
+    })?;
+    log::info!("{:?} から {} 件のUTXOを読み込みました。", path, count);
+    Ok(())
+}
+
+// 署名済みトランザクションを出力ファイルに書き込む。binary 指定時はconsensus serializeしたバイト列をそのまま書き込む
+fn write_transaction_file(output_file: &Path, tx: &Transaction, binary: bool) -> Result<(), AppError> {
+    let mut output_handle = File::create(output_file).map_err(|e| {
+        log::error!("出力ファイルの作成に失敗しました: {:?}", output_file);
+        AppError::Io(e)
+    })?;
+    let output_bytes = if binary {
+        encode::serialize(tx)
+    } else {
+        encode::serialize_hex(tx).into_bytes()
+    };
+    output_handle.write_all(&output_bytes).map_err(|e| {
+        log::error!("出力ファイルへの書き込みに失敗しました。");
+        AppError::Io(e)
+    })?;
+    log::info!("Raw transactionを {:?} に保存しました。", output_file);
+    Ok(())
+}
+
+// ファイル名に使えない文字を _ に置き換える。パス区切りや先頭の "." (".." や隠しファイル) も取り除く
+fn sanitize_filename(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    match sanitized.trim_start_matches('.') {
+        "" => "_".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+// --output-name-template を展開して --output-dir 内の出力パスを決める
+fn templated_output_path(
+    output_dir: &Path,
+    template: &str,
+    tx: &Transaction,
+    fee_rate_sats_per_vb: FeeRate,
+    input_file: &Path,
+    network: BitcoinNetwork,
+) -> PathBuf {
+    let first_address = tx
+        .output
+        .first()
+        .and_then(|output| Address::from_script(&output.script_pubkey, network).ok())
+        .map_or_else(|| "unknown".to_string(), |address| address.to_string());
+    let input_stem = input_file.file_stem().map_or_else(|| "input".to_string(), |stem| stem.to_string_lossy().into_owned());
+    let filename = template
+        .replace("{txid}", &tx.compute_txid().to_string())
+        .replace("{first_address}", &first_address)
+        .replace("{input}", &input_stem)
+        .replace("{fee_rate}", &fee_rate_sats_per_vb.to_string());
+    output_dir.join(sanitize_filename(&filename))
+}
+
+// --manifest: 署名の判断を後から監査・再現するための記録。秘密鍵は含めない
+fn signing_manifest(result: &SignedTransaction, network: BitcoinNetwork) -> serde_json::Value {
+    let transaction = &result.transaction;
+    let inputs: Vec<serde_json::Value> = transaction
+        .input
+        .iter()
+        .zip(&result.inputs)
+        .map(|(tx_in, signed_input)| {
+            let script_type = ScriptType::from_script_buf(&signed_input.spent_output.script_pubkey)
+                .map_or_else(|_| "Unknown".to_string(), |script_type| format!("{:?}", script_type));
+            serde_json::json!({
+                "outpoint": tx_in.previous_output.to_string(),
+                "value_sats": signed_input.spent_output.value.to_sat(),
+                "script_pubkey": signed_input.spent_output.script_pubkey.to_hex_string(),
+                "script_type": script_type,
+                "sighash_type": sighash_type_name(signed_input),
+                "sequence": tx_in.sequence.0,
+                "public_key": signed_input.public_key.map(|public_key| public_key.to_string()),
+                "signed": signed_input.signature.is_some() || signed_input.taproot_signature.is_some(),
+            })
+        })
+        .collect();
+    let outputs: Vec<serde_json::Value> = transaction
+        .output
+        .iter()
+        .zip(&result.output_memos)
+        .map(|(tx_out, memo)| serde_json::json!({
+            "value_sats": tx_out.value.to_sat(),
+            "script_pubkey": tx_out.script_pubkey.to_hex_string(),
+            "memo": memo,
+        }))
+        .collect();
+    serde_json::json!({
+        "network": network.to_string(),
+        "txid": transaction.compute_txid().to_string(),
+        "wtxid": transaction.compute_wtxid().to_string(),
+        "version": transaction.version.0,
+        "lock_time": transaction.lock_time.to_consensus_u32(),
+        "fee_sats": result.fee_sats,
+        "fee_rate_sats_per_vb": result.fee_rate_sats_per_vb,
+        "vsize": transaction::transaction_vsize(transaction),
+        "inputs": inputs,
+        "outputs": outputs,
+        "warnings": result.warnings.iter().map(ToString::to_string).collect::<Vec<_>>(),
+    })
+}
+
+// --warnings-json: 監視システム向けに、警告を安定したコードとメッセージの組で出力する
+fn warnings_json(result: &SignedTransaction) -> serde_json::Value {
+    let warnings: Vec<serde_json::Value> = result
+        .warnings
+        .iter()
+        .map(|warning| serde_json::json!({ "code": warning.code(), "message": warning.to_string() }))
+        .collect();
+    serde_json::json!({ "txid": result.transaction.compute_txid().to_string(), "warnings": warnings })
+}
+
+// --review-package: 署名済みトランザクションと、その内容を第三者が独立に確認するための前提データ
+// (消費する出力の scriptPubKey と金額)。手数料・txid は hex と入力データから再計算して照合できる
+fn review_package(result: &SignedTransaction, network: BitcoinNetwork) -> serde_json::Value {
+    let transaction = &result.transaction;
+    let address_of = |script_pubkey: &ScriptBuf| Address::from_script(script_pubkey, network).ok().map(|address| address.to_string());
+    let inputs: Vec<serde_json::Value> = transaction
+        .input
+        .iter()
+        .zip(&result.inputs)
+        .map(|(tx_in, signed_input)| serde_json::json!({
+            "outpoint": tx_in.previous_output.to_string(),
+            "value_sats": signed_input.spent_output.value.to_sat(),
+            "script_pubkey": signed_input.spent_output.script_pubkey.to_hex_string(),
+            "address": address_of(&signed_input.spent_output.script_pubkey),
+        }))
+        .collect();
+    let outputs: Vec<serde_json::Value> = transaction
+        .output
+        .iter()
+        .zip(&result.output_memos)
+        .enumerate()
+        .map(|(output_index, (tx_out, memo))| serde_json::json!({
+            "index": output_index,
+            "value_sats": tx_out.value.to_sat(),
+            "script_pubkey": tx_out.script_pubkey.to_hex_string(),
+            "address": address_of(&tx_out.script_pubkey),
+            "is_change": result.change_index == Some(output_index),
+            "memo": memo,
+        }))
+        .collect();
+    let total_input_sats: u64 = result.inputs.iter().map(|input| input.spent_output.value.to_sat()).sum();
+    let total_output_sats: u64 = transaction.output.iter().map(|output| output.value.to_sat()).sum();
+    serde_json::json!({
+        "network": network.to_string(),
+        "hex": encode::serialize_hex(transaction),
+        "txid": transaction.compute_txid().to_string(),
+        "wtxid": transaction.compute_wtxid().to_string(),
+        "vsize": transaction::transaction_vsize(transaction),
+        "total_input_sats": total_input_sats,
+        "total_output_sats": total_output_sats,
+        "fee_sats": result.fee_sats,
+        "inputs": inputs,
+        "outputs": outputs,
+    })
+}
+
+// --preflight の結果をログに記録し、必要なら標準エラー出力にも表示する
+fn log_preflight_report(report: &preflight::PreflightReport, print: bool) {
+    for check in &report.checks {
+        let line = format!("[{}] {}: {}", if check.passed { "OK" } else { "NG" }, check.name, check.detail);
+        if check.passed { log::info!("{}", line) } else { log::warn!("{}", line) }
+        if print {
+            eprintln!("{}", line);
+        }
+    }
+    let verdict = if report.likely_accepted() {
+        "プリフライト: ノードに受理される見込みです (チェーンの状態に依存する確認は含みません)。".to_string()
+    } else {
+        let names: Vec<&str> = report.failures().map(|check| check.name).collect();
+        format!("プリフライト: ノードに拒否される可能性があります (NG: {})。", names.join(", "))
+    };
+    log::info!("{}", verdict);
+    if print {
+        eprintln!("{}", verdict);
+    }
+}
+
+// witnessを除いたシリアライズ (BIP144以前の形式)。txidはこのバイト列のハッシュ
+fn stripped_serialize_hex(tx: &Transaction) -> String {
+    let mut stripped = tx.clone();
+    for input in &mut stripped.input {
+        input.witness.clear();
+    }
+    // witnessがすべて空ならマーカー・フラグなしの形式でシリアライズされる
+    encode::serialize_hex(&stripped)
+}
+
+// 許可リストファイル (1行に1アドレス。空行と # で始まる行は無視) を読み込み、scriptPubKeyの集合にする
+fn load_address_allowlist(allowlist_path: &Path, network: BitcoinNetwork) -> Result<HashSet<ScriptBuf>, AppError> {
+    let mut allowed_script_pubkeys = HashSet::new();
+    for (line_index, line) in fs::read_to_string(allowlist_path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let address = Address::from_str(line)
+            .and_then(|address| address.require_network(network))
+            .map_err(|e| AppError::InputValidation(format!("{:?} の {} 行目 ({}) が不正です: {}", allowlist_path, line_index + 1, line, e)))?;
+        allowed_script_pubkeys.insert(address.script_pubkey());
+    }
+    Ok(allowed_script_pubkeys)
+}
+
+// --safe-allowlist のアドレス一覧を読み込み、--safe の検査条件にする
+fn load_safe_mode_policy(args: &CliArgs, network: BitcoinNetwork) -> Result<Option<SafeModePolicy>, AppError> {
+    let Some(allowlist_path) = args.safe_allowlist.as_ref().filter(|_| args.safe) else {
+        return Ok(None);
+    };
+    let allowed_script_pubkeys = load_address_allowlist(allowlist_path, network)?;
+    log::info!("--safe: 許可リストから {} 件の送金先を読み込みました。", allowed_script_pubkeys.len());
+    Ok(Some(SafeModePolicy { allowed_script_pubkeys, max_fee_percent: args.safe_max_fee_percent }))
+}
+
+// CLI引数から create_and_sign_transaction の動作オプションを組み立てる
+fn signing_options(args: &CliArgs, network: BitcoinNetwork) -> Result<SigningOptions, AppError> {
+    if let Some(ratio) = args.max_io_ratio
+        && !(ratio.is_finite() && ratio >= 1.0)
+    {
+        return Err(AppError::InputValidation(format!("--max-io-ratio は 1 以上の数値で指定してください: {}", ratio)));
+    }
+    Ok(SigningOptions {
+        // --safe は --strict を含む
+        strict: args.strict || args.safe,
+        // --quiet では標準エラー出力への表示 (入力アドレス・メトリクス) も行わない
+        show_input_addresses: args.show_input_addresses && !args.quiet,
+        metrics: args.metrics && !args.quiet,
+        fee_rate_ceiling_sats_per_vb: Some(args.fee_rate_ceiling),
+        sighash_only: false,
+        expected_fee_sats: args.exact_fee,
+        round_up_fee: args.round_up_fee,
+        audit_dir: args.audit_dir.clone(),
+        allow_low_fee: args.allow_low_fee,
+        allow_address_reuse: args.allow_address_reuse,
+        force_change: args.force_change,
+        sign_inputs: args.sign_inputs.clone(),
+        max_fee_ratio: Some(args.max_fee_ratio),
+        max_io_ratio: args.max_io_ratio,
+        safe_mode: load_safe_mode_policy(args, network)?,
+        recipient_allowlist: args
+            .allowlist_file
+            .as_ref()
+            .map(|path| load_address_allowlist(path, network))
+            .transpose()?,
+    })
+}
+
+// --network と設定ファイルの network を突き合わせる。どちらも未指定なら testnet
+fn resolve_network(args: &CliArgs, config: &InputConfig) -> Result<BitcoinNetwork, AppError> {
+    let cli_network = args.network.as_deref().map(parse_network).transpose()?;
+    match (cli_network, config.network) {
+        (Some(cli_network), Some(config_network)) if cli_network != config_network => Err(AppError::ConfigNetworkMismatch {
+            cli_network: format!("{:?}", cli_network),
+            config_network: format!("{:?}", config_network),
+        }),
+        (Some(network), _) | (None, Some(network)) => Ok(network),
+        (None, None) => Ok(BitcoinNetwork::Testnet),
+    }
+}
+
+// --dump-effective-config: 実際に署名に使う設定 (秘密鍵は伏せる) を表示する。network は判定後の値
+fn dump_effective_config(config: InputConfig, network: BitcoinNetwork) -> Result<(), AppError> {
+    println!("{}", effective_config_json(config, network)?);
+    Ok(())
+}
+
+fn effective_config_json(mut config: InputConfig, network: BitcoinNetwork) -> Result<String, AppError> {
+    config.network = Some(network);
+    serde_json::to_string_pretty(&config.redacted())
+        .map_err(|e| AppError::Internal(format!("設定のシリアライズに失敗しました: {}", e)))
+}
+
+// consolidate サブコマンド: 経済的なUTXOのみを集約先へスイープする
+fn run_consolidate<C: Signing>(
+    args: &CliArgs,
+    input_file: &Path,
+    output_file: &Path,
+    target_address: Option<&str>,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+) -> Result<(), AppError> {
+    let mut config = load_config(input_file)?;
+    let cli_network = resolve_network(args, &config)?;
+    if let Some(target) = target_address {
+        config.change_address = Some(ChangeAddressDef::Single(target.to_string()));
+        config.change_descriptor = None;
+    }
+    // 受信者出力なし = 全額 (手数料を除く) を集約先へ送るスイープ
+    config.outputs.clear();
+
+    let utxos = std::mem::take(&mut config.utxos);
+    let total_before: u64 = utxos.iter().map(|u| u.value_sats).sum();
+    // 入力ごとの手数料を見積もるため、集約では手数料率での指定が必要
+    let fee_rate_sats_per_vb = config.fee_rate_sats_per_vb.ok_or_else(|| {
+        AppError::InputValidation("consolidate には feeRateSatsPerVb の指定が必要です".to_string())
+    })?;
+    let plan = consolidate::plan_consolidation(utxos, fee_rate_sats_per_vb, config.default_sequence, cli_network, secp, key_cache)?;
+    if plan.kept.is_empty() {
+        return Err(AppError::InputValidation(format!(
+            "手数料率 {} sats/vB では経済的に集約できるUTXOがありません (除外 {} 件)",
+            fee_rate_sats_per_vb, plan.dropped.len())));
+    }
+    let kept_count = plan.kept.len();
+    let kept_total: u64 = plan.kept.iter().map(|u| u.value_sats).sum();
+    config.utxos = plan.kept;
+    let dropped = plan.dropped;
+
+    let options = signing_options(args, cli_network)?;
+    if let Some(fee_rates) = &args.fee_rate_variants {
+        return run_fee_rate_variants(args, config, fee_rates, cli_network, &options, input_file, secp, key_cache);
+    }
+
+    let result = transaction::create_and_sign_transaction(config, cli_network, secp, key_cache, &options, |_| {})?;
+    let preserved: u64 = result.transaction.output.iter().map(|o| o.value.to_sat()).sum();
+
+    if !args.quiet {
+        for dropped_utxo in &dropped {
+            eprintln!("除外: {}:{} ({} sats) は入力手数料 {} sats 以下のため集約しません",
+                dropped_utxo.utxo.txid, dropped_utxo.utxo.vout, dropped_utxo.utxo.value_sats, dropped_utxo.marginal_fee_sats);
+        }
+        eprintln!("集約対象: {} 件 ({} sats), 除外: {} 件 ({} sats)",
+            kept_count, kept_total, dropped.len(), total_before - kept_total);
+        eprintln!("手数料: {} sats, 集約後に保持される金額: {} sats", kept_total - preserved, preserved);
+        println!("{}", encode::serialize_hex(&result.transaction));
+    }
+    write_transaction_file(output_file, &result.transaction, args.binary)
+}
+
+// analyze-consolidation サブコマンド: 純資産の大きい順に並べ、累積値からどこで打ち切るべきかを判断できるようにする
+fn run_analyze_consolidation<C: Signing>(
+    args: &CliArgs,
+    input_file: &Path,
+    fee_rate: Option<FeeRate>,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+) -> Result<(), AppError> {
+    let config = load_config(input_file)?;
+    let cli_network = resolve_network(args, &config)?;
+    let fee_rate_sats_per_vb = fee_rate.or(config.fee_rate_sats_per_vb).ok_or_else(|| {
+        AppError::InputValidation("analyze-consolidation には feeRateSatsPerVb か --fee-rate の指定が必要です".to_string())
+    })?;
+    let mut costs = consolidate::estimate_input_costs(&config.utxos, fee_rate_sats_per_vb, config.default_sequence, cli_network, secp, key_cache)?;
+    // --utxos-jsonl のUTXOは1件ずつ見積もって破棄し、見積もり結果のみを保持する
+    if let Some(utxos_jsonl) = &args.utxos_jsonl {
+        for_each_jsonl_utxo(utxos_jsonl, |utxo| {
+            // 設定ファイルのUTXOに続く utxos のインデックス
+            let utxo_index = costs.len();
+            costs.push(consolidate::estimate_input_cost(utxo_index, &utxo, fee_rate_sats_per_vb, config.default_sequence, cli_network, secp, key_cache)?);
+            Ok(())
+        })?;
+    }
+    costs.sort_by_key(|cost| std::cmp::Reverse(cost.net_sats())); // 安定ソート
+
+    let mut cumulative_value_sats = 0u64;
+    let mut cumulative_fee_sats = 0u64;
+    let mut rows = Vec::with_capacity(costs.len());
+    if !args.json {
+        println!("手数料率 {} sats/vB", fee_rate_sats_per_vb);
+        println!("{:<68} {:>14} {:>6} {:>10} {:>14} {:>16}", "outpoint", "value", "vsize", "fee", "net", "cumulative net");
+    }
+    for cost in &costs {
+        cumulative_value_sats += cost.value_sats;
+        cumulative_fee_sats += cost.marginal_fee_sats;
+        let cumulative_net_sats = cumulative_value_sats as i64 - cumulative_fee_sats as i64;
+        if args.json {
+            rows.push(serde_json::json!({
+                "outpoint": cost.outpoint,
+                "label": cost.label,
+                "value_sats": cost.value_sats,
+                "vsize": cost.vsize,
+                "marginal_fee_sats": cost.marginal_fee_sats,
+                "net_sats": cost.net_sats(),
+                "cumulative_value_sats": cumulative_value_sats,
+                "cumulative_fee_sats": cumulative_fee_sats,
+                "cumulative_net_sats": cumulative_net_sats,
+            }));
+        } else {
+            println!("{:<68} {:>14} {:>6} {:>10} {:>14} {:>16}",
+                cost.outpoint, cost.value_sats, cost.vsize, cost.marginal_fee_sats, cost.net_sats(), cumulative_net_sats);
+        }
+    }
+    let economical_count = costs.iter().filter(|cost| cost.net_sats() > 0).count();
+    if args.json {
+        println!("{}", serde_json::json!({
+            "fee_rate_sats_per_vb": fee_rate_sats_per_vb,
+            "inputs": rows,
+            "economical_count": economical_count,
+        }));
+    } else {
+        println!("経済的な入力: {} / {} 件 (上位 {} 件で累積の純資産が最大)", economical_count, costs.len(), economical_count);
+    }
+    Ok(())
+}
+
+// JSON出力用の sighash type。P2TR の入力は sighashType の設定によらず SIGHASH_DEFAULT で署名する
+fn sighash_type_name(signed_input: &SignedInput) -> String {
+    if signed_input.spent_output.script_pubkey.is_p2tr() {
+        TapSighashType::Default.to_string()
+    } else {
+        signed_input.sighash_type.to_string()
+    }
+}
+
+// compute-sighashes の1トランザクション分の出力 (未署名のhexと各入力の署名ハッシュ)
+fn sighashes_json(result: &SignedTransaction) -> serde_json::Value {
+    let inputs: Vec<serde_json::Value> = result
+        .inputs
+        .iter()
+        .zip(&result.unsigned_transaction.input)
+        .enumerate()
+        .filter_map(|(input_index, (signed_input, tx_in))| {
+            // skipSigning の入力は署名済みのため含めない
+            let (sighash, public_key) = (signed_input.sighash?, signed_input.public_key?);
+            Some(serde_json::json!({
+                "input_index": input_index,
+                "outpoint": tx_in.previous_output.to_string(),
+                "sighash": sighash.to_string(),
+                "sighash_type": sighash_type_name(signed_input),
+                "public_key": public_key.to_string(),
+            }))
+        })
+        .collect();
+    serde_json::json!({
+        "unsigned_hex": encode::serialize_hex(&result.unsigned_transaction),
+        "inputs": inputs,
+    })
+}
+
+// compute-sighashes で --fee-rate-variants を指定した場合: 手数料率ごとにおつりを計算し直した未署名トランザクションと
+// 署名ハッシュを出力する。署名は外部で行うため、プリフライトとファイルへの保存は行わない
+fn sighash_variants_json<C: Signing>(
+    config: InputConfig,
+    fee_rates: &[FeeRate],
+    network: BitcoinNetwork,
+    options: &SigningOptions,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+) -> Result<serde_json::Value, AppError> {
+    check_fee_rate_variants_config(&config)?;
+    let mut variants = Vec::new();
+    for &fee_rate in fee_rates {
+        let mut variant_config = config.clone();
+        variant_config.fee_rate_sats_per_vb = Some(fee_rate);
+        let result = transaction::create_and_sign_transaction(variant_config, network, secp, key_cache, options, |_| {})?;
+        let mut variant = sighashes_json(&result);
+        variant["fee_rate_sats_per_vb"] = serde_json::json!(result.fee_rate_sats_per_vb);
+        variant["fee_sats"] = serde_json::json!(result.fee_sats);
+        variants.push(variant);
+    }
+    Ok(serde_json::json!({ "variants": variants }))
+}
+
+// compute-sighashes サブコマンド: 署名せずに各入力の署名ハッシュをJSONで出力する
+fn run_compute_sighashes<C: Signing>(args: &CliArgs, input_file: &Path, secp: &Secp256k1<C>, key_cache: &mut KeyCache) -> Result<(), AppError> {
+    let config = load_config(input_file)?;
+    let cli_network = resolve_network(args, &config)?;
+    let options = SigningOptions { sighash_only: true, ..signing_options(args, cli_network)? };
+    let json_output = match &args.fee_rate_variants {
+        Some(fee_rates) => sighash_variants_json(config, fee_rates, cli_network, &options, secp, key_cache)?,
+        None => sighashes_json(&transaction::create_and_sign_transaction(config, cli_network, secp, key_cache, &options, |_| {})?),
+    };
+    println!("{}", json_output);
+    Ok(())
+}
+
+fn run_verify_tx(args: &CliArgs, input_file: &Path, tx_hex: Option<&str>, tx_file: Option<&Path>) -> Result<(), AppError> {
+    let config = load_config(input_file)?;
+    let cli_network = resolve_network(args, &config)?;
+    let tx_hex = match (tx_hex, tx_file) {
+        (Some(tx_hex), _) => tx_hex.to_string(),
+        (None, Some(tx_file)) => fs::read_to_string(tx_file)?,
+        (None, None) => return Err(AppError::InputValidation("--tx-hex か --tx-file を指定してください".to_string())),
+    };
+    let tx_bytes = hex::decode(tx_hex.trim())
+        .map_err(|e| AppError::InputValidation(format!("トランザクションのhexをデコードできません: {}", e)))?;
+    let tx: Transaction = encode::deserialize(&tx_bytes)?;
+    let txid = tx.compute_txid();
+
+    let report = verify::verify_transaction(&tx, &config, cli_network)?;
+    for check in &report.checks {
+        let line = format!("[{}] {}: {}", if check.passed { "OK" } else { "NG" }, check.name, check.detail);
+        if check.passed { log::info!("{}", line) } else { log::warn!("{}", line) }
+        if !args.json && !args.quiet {
+            eprintln!("{}", line);
+        }
+    }
+    if args.json {
+        let mut json_output = report.to_json();
+        json_output["txid"] = serde_json::json!(txid.to_string());
+        println!("{}", json_output);
+    }
+    if !report.matches_config() {
+        let failures: Vec<String> = report.failures().map(|check| format!("{}: {}", check.name, check.detail)).collect();
+        return Err(AppError::TransactionMismatch { txid: txid.to_string(), failures: failures.join("; ") });
+    }
+    log::info!("トランザクション {} は設定ファイルと一致します。", txid);
+    if !args.json && !args.quiet {
+        eprintln!("トランザクション {} は設定ファイルと一致します。", txid);
+    }
+    Ok(())
+}
+
+// ログの各行からWIFの形をした語を伏せる (設定値を含むメッセージからの漏洩を防ぐ)。書式は env_logger の既定と同じ
+fn init_logger() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            let level_style = buf.default_level_style(record.level());
+            writeln!(buf, "[{} {level_style}{:<5}{level_style:#} {}] {}",
+                buf.timestamp(), record.level(), record.target(), redact::redact_wifs(&record.args().to_string()))
+        })
+        .init();
+}
+
+// エラーは標準の main の戻り値と同じ "Error: {:?}" の形式で表示するが、WIFの形をした語は伏せる
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", redact::redact_wifs(&format!("{:?}", e)));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn try_main() -> Result<(), AppError> {
+    let args = CliArgs::parse();
+    init_logger();
+    // --quiet では RUST_LOG の指定にかかわらずエラーのみ記録する
+    if args.quiet {
+        log::set_max_level(log::LevelFilter::Error);
+    }
+    log::info!("アプリケーションを開始します。引数: {:?}", args);
+
+    if let Some(Command::JsonSchema) = &args.command {
+        let schema = schemars::schema_for!(InputConfig);
+        let schema_json = serde_json::to_string_pretty(&schema)
+            .map_err(|e| AppError::Internal(format!("JSON Schemaのシリアライズに失敗しました: {}", e)))?;
+        println!("{}", schema_json);
+        return Ok(());
+    }
+
+    // 署名と公開鍵の導出には署名用コンテキストのみで足りる
+    if args.secp_signing_only {
+        run(&args, &Secp256k1::signing_only())
+    } else {
+        run(&args, &Secp256k1::new())
+    }
+}
+
+// --output-file に手数料率を付けたファイル名 (例: tx.hex -> tx-5satvb.hex)。--output-dir ではテンプレートを展開する
+fn variant_output_path(args: &CliArgs, result: &SignedTransaction, input_file: &Path, network: BitcoinNetwork) -> Result<PathBuf, AppError> {
+    let fee_rate = result.fee_rate_sats_per_vb;
+    match (&args.output_file, &args.output_dir) {
+        (Some(output_file), _) => {
+            let stem = output_file.file_stem().map_or_else(|| "tx".to_string(), |stem| stem.to_string_lossy().into_owned());
+            let extension = output_file.extension().map_or_else(String::new, |ext| format!(".{}", ext.to_string_lossy()));
+            Ok(output_file.with_file_name(format!("{}-{}satvb{}", stem, fee_rate, extension)))
+        }
+        (None, Some(output_dir)) => {
+            fs::create_dir_all(output_dir)?;
+            Ok(templated_output_path(output_dir, &args.output_name_template, &result.transaction, fee_rate, input_file, network))
+        }
+        (None, None) => Err(AppError::InputValidation("--output-file か --output-dir を指定してください".to_string())),
+    }
+}
+
+// 手数料率を変種ごとに差し替えるため、手数料をパーセントで指定する設定とは併用できない
+fn check_fee_rate_variants_config(config: &InputConfig) -> Result<(), AppError> {
+    if config.fee_percent.is_some() {
+        return Err(AppError::InputValidation("feePercent と --fee-rate-variants は同時に指定できません".to_string()));
+    }
+    Ok(())
+}
+
+// --fee-rate-variants: 手数料率ごとにおつりを計算し直して署名し、それぞれ別のファイルに保存する
+#[allow(clippy::too_many_arguments)]
+fn run_fee_rate_variants<C: Signing>(
+    args: &CliArgs,
+    config: InputConfig,
+    fee_rates: &[FeeRate],
+    network: BitcoinNetwork,
+    options: &SigningOptions,
+    input_file: &Path,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+) -> Result<(), AppError> {
+    check_fee_rate_variants_config(&config)?;
+    let mut written_paths = HashSet::new();
+    let mut variants = Vec::new();
+    for &fee_rate in fee_rates {
+        let mut variant_config = config.clone();
+        variant_config.fee_rate_sats_per_vb = Some(fee_rate);
+        let result = transaction::create_and_sign_transaction(variant_config, network, secp, key_cache, options, |_| {})?;
+        // どの変種を選んでもブロードキャストできるよう、それぞれ単独で署名と標準ルールを確認する
+        let report = preflight::run_preflight(&result);
+        if !report.likely_accepted() {
+            let failures: Vec<String> = report.failures().map(|check| format!("{}: {}", check.name, check.detail)).collect();
+            return Err(AppError::PreflightFailed { context: format!("手数料率 {} sats/vB の変種", fee_rate), failures: failures.join("; ") });
+        }
+        let output_file = variant_output_path(args, &result, input_file, network)?;
+        if !written_paths.insert(output_file.clone()) {
+            return Err(AppError::InputValidation(format!(
+                "手数料率 {} sats/vB の変種の出力先 {:?} が他の変種と重複しています (--output-name-template に {{fee_rate}} を含めてください)", fee_rate, output_file)));
+        }
+        write_transaction_file(&output_file, &result.transaction, args.binary)?;
+        let txid = result.transaction.compute_txid();
+        log::info!("手数料率 {} sats/vB: 手数料 {} sats, TXID {} を {:?} に保存しました。", fee_rate, result.fee_sats, txid, output_file);
+        variants.push((result, txid, output_file));
+    }
+
+    if args.quiet {
+        // 結果は出力ファイルのみ
+    } else if args.json {
+        let json_variants: Vec<serde_json::Value> = variants
+            .iter()
+            .map(|(result, txid, output_file)| serde_json::json!({
+                "fee_rate_sats_per_vb": result.fee_rate_sats_per_vb,
+                "fee_sats": result.fee_sats,
+                "txid": txid.to_string(),
+                "signed_hex": encode::serialize_hex(&result.transaction),
+                "output_file": output_file,
+                "warnings": result.warnings.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            }))
+            .collect();
+        println!("{}", serde_json::json!({ "variants": json_variants }));
+    } else {
+        for (result, txid, output_file) in &variants {
+            eprintln!("手数料率 {} sats/vB: 手数料 {} sats, TXID {} -> {:?}", result.fee_rate_sats_per_vb, result.fee_sats, txid, output_file);
+            println!("{}", encode::serialize_hex(&result.transaction));
+        }
+    }
+    log::info!("処理が正常に完了しました。");
+    Ok(())
+}
+
+fn run<C: Signing>(args: &CliArgs, secp: &Secp256k1<C>) -> Result<(), AppError> {
+    // WIFのデコードと公開鍵の導出結果は、この実行で作成・見積もりするすべてのトランザクションで共有する
+    let mut key_cache = KeyCache::default();
+    match &args.command {
+        Some(Command::JsonSchema) => {} // main で処理済み
+        Some(Command::Consolidate { input_file, output_file, target_address }) => {
+            return run_consolidate(args, input_file, output_file, target_address.as_deref(), secp, &mut key_cache);
+        }
+        Some(Command::AnalyzeConsolidation { input_file, fee_rate }) => {
+            return run_analyze_consolidation(args, input_file, *fee_rate, secp, &mut key_cache);
+        }
+        Some(Command::ComputeSighashes { input_file }) => {
+            return run_compute_sighashes(args, input_file, secp, &mut key_cache);
+        }
+        Some(Command::VerifyTx { input_file, tx_hex, tx_file }) => {
+            return run_verify_tx(args, input_file, tx_hex.as_deref(), tx_file.as_deref());
+        }
+        None => {}
+    }
+
+    // サブコマンドなしの場合、clap により input_file と output_file / output_dir のどちらかは必須
+    let Some(input_file) = &args.input_file else {
+        return Err(AppError::InputValidation("--input-file を指定してください".to_string()));
+    };
+
+    let mut config = load_config(input_file)?;
+    let mut cli_network = resolve_network(args, &config)?;
+    log::info!("指定されたネットワーク: {:?}", cli_network);
 
-    let secp: Secp256k1<AllContext> = Secp256k1::new(); // 明示的に AllContext を指定
+    if let Some(utxos_jsonl) = &args.utxos_jsonl {
+        load_utxos_jsonl(utxos_jsonl, &mut config.utxos)?;
+    }
 
-    let signed_tx = transaction::create_and_sign_transaction(config, cli_network, &secp)?;
+    #[cfg(feature = "csv-import")]
+    if let Some(csv_path) = &args.utxos_csv {
+        let imported = import::import_csv_utxos(&fs::read_to_string(csv_path)?, args.csv_columns.as_deref())?;
+        config.utxos.extend(imported);
+    }
+
+    #[cfg(feature = "electrum-import")]
+    if let Some(coins_path) = &args.electrum_coins {
+        let keys: std::collections::HashMap<String, String> = match &args.electrum_keys {
+            Some(keys_path) => serde_json::from_str(&fs::read_to_string(keys_path)?).map_err(|e| AppError::JsonParse {
+                file_path: keys_path.clone(),
+                source: e,
+            })?,
+            None => Default::default(),
+        };
+        let imported = import::import_electrum_coins(&fs::read_to_string(coins_path)?, &keys)?;
+        config.utxos.extend(imported);
+    }
+
+    if args.wif_network_autodetect {
+        // 2つ目以降の鍵やアドレスは create_and_sign_transaction 内でこのネットワークに対して検証される
+        let first_wif = config.utxos.iter().find_map(|u| u.private_key_wif.as_deref()).ok_or_else(|| {
+            AppError::InputValidation("--wif-network-autodetect にはWIFを持つUTXOが1件以上必要です".to_string())
+        })?;
+        cli_network = detect_network_from_wif(first_wif, cli_network)?;
+        log::info!("最初のWIFからネットワークを判定しました: {:?}", cli_network);
+    }
+
+    if let Some(fee_source) = &args.fee_source {
+        if config.fee_percent.is_some() {
+            return Err(AppError::InputValidation("feePercent と --fee-source は同時に指定できません".to_string()));
+        }
+        let fee_rate = fee_source::load_fee_rate(fee_source, &args.fee_source_field)?;
+        log::info!("手数料率を {} から取得しました: {} sats/vB (設定ファイルの値 {} を上書き)",
+            fee_source, fee_rate, config.fee_rate_sats_per_vb.map_or_else(|| "未指定".to_string(), |rate| format!("{} sats/vB", rate)));
+        config.fee_rate_sats_per_vb = Some(fee_rate);
+    }
+
+    // おつり先のローテーション: 状態ファイルで未使用の最初のアドレスを使い、保存後に使用済みとして記録する
+    let rotated_change_address = match (&config.change_address, &args.change_state_file) {
+        (Some(ChangeAddressDef::Rotation(addresses)), Some(state_file)) => {
+            let address = change_state::next_change_address(addresses, state_file)?;
+            log::info!("おつり先に {} を使用します ({} 件中、状態ファイル {:?})。", address, addresses.len(), state_file);
+            config.change_address = Some(ChangeAddressDef::Single(address.clone()));
+            Some((state_file, address))
+        }
+        (_, Some(_)) => {
+            log::warn!("changeAddress がリストではないため、--change-state-file は使用しません。");
+            None
+        }
+        (_, None) => None,
+    };
+
+    if args.dump_effective_config {
+        return dump_effective_config(config, cli_network);
+    }
+
+    let options = signing_options(args, cli_network)?;
+
+    if let Some(fee_rates) = &args.fee_rate_variants {
+        run_fee_rate_variants(args, config, fee_rates, cli_network, &options, input_file, secp, &mut key_cache)?;
+        // どの変種も同じおつり先を使う
+        if let Some((state_file, address)) = &rotated_change_address {
+            change_state::record_used_change_address(state_file, address)?;
+        }
+        return Ok(());
+    }
+
+    let result = transaction::create_and_sign_transaction(config, cli_network, secp, &mut key_cache, &options, |_| {})?;
+    let signed_tx = &result.transaction;
     log::info!("署名済みトランザクションの生成に成功しました。");
 
     // トランザクションのシリアライズ (16進数形式)
     // bitcoin 0.32 では serialize_hex は consensus::encode::hex::encode かもしれない
     // -> 確認したところ、bitcoin::consensus::encode::serialize_hex で引き続き利用可能
-    let serialized_tx = encode::serialize_hex(&signed_tx);
+    let serialized_tx = encode::serialize_hex(signed_tx);
     log::info!("Raw transaction hex: {}", serialized_tx);
+    // Txid の Display は内部バイト列を逆順にした表示順 (RPC・ブロックエクスプローラーと同じ)
+    let txid = signed_tx.compute_txid();
+    log::info!("TXID (表示順/RPC): {}", txid);
+    let txid_internal_hex = args.txid_byte_orders.then(|| hex::encode(txid.to_byte_array()));
+    // 入力の並び替え後の順序 (トランザクション内の順序) で列挙する
+    let spent_outpoints: Vec<String> = signed_tx.input.iter().map(|input| input.previous_output.to_string()).collect();
+
+    let unsigned_hex = args.also_unsigned.then(|| encode::serialize_hex(&result.unsigned_transaction));
+    let stripped_hex = args.stripped_hex.then(|| stripped_serialize_hex(signed_tx));
+    // BIP125: いずれかの入力の sequence が 0xfffffffe 未満なら置き換え可能
+    let replaceable = args.replaceability_check.then(|| signed_tx.is_explicitly_rbf());
+    if let Some(replaceable) = replaceable {
+        let message = if replaceable {
+            "RBF: このトランザクションはBIP125の置き換え可能 (replaceable) を通知しています。"
+        } else {
+            "RBF: このトランザクションは置き換え可能を通知していません (後から手数料を引き上げられません)。"
+        };
+        log::info!("{}", message);
+        if !args.json && !args.quiet {
+            eprintln!("{}", message);
+        }
+    }
+    // --extra-inputs: 追加入力を含めた場合の見積もり (vsize, 手数料)
+    let extra_inputs_projection = match args.extra_inputs {
+        Some(count) => {
+            let input_type = parse_extra_input_type(&args.extra_input_type)?;
+            let vsize = transaction::projected_vsize_with_extra_inputs(signed_tx, count, input_type)?;
+            let fee_sats = result.fee_rate_sats_per_vb.fee_for_vsize(vsize).unwrap_or(u64::MAX);
+            let message = format!("見積もり: {:?} 入力を {} 個追加すると vsize {} vB、手数料 {} sats ({} sats/vB) になります。",
+                input_type, count, vsize, fee_sats, result.fee_rate_sats_per_vb);
+            log::info!("{}", message);
+            if !args.json && !args.quiet {
+                eprintln!("{}", message);
+            }
+            Some(serde_json::json!({
+                "count": count,
+                "input_type": args.extra_input_type.to_lowercase(),
+                "vsize": vsize,
+                "fee_sats": fee_sats,
+            }))
+        }
+        None => None,
+    };
+    let preflight_report = args.preflight.then(|| preflight::run_preflight(&result));
+    if let Some(report) = &preflight_report {
+        log_preflight_report(report, !args.json && !args.quiet);
+    }
+    if args.quiet {
+        // 結果は出力ファイルのみ。成功は終了コードで伝える
+    } else if args.json {
+        let warnings: Vec<String> = result.warnings.iter().map(ToString::to_string).collect();
+        let mut json_output = serde_json::json!({
+            "signed_hex": serialized_tx,
+            "txid": txid.to_string(),
+            "spent_outpoints": spent_outpoints,
+            "output_memos": result.output_memos,
+            "warnings": warnings,
+        });
+        if let Some(txid_internal_hex) = &txid_internal_hex {
+            json_output["txid_internal_hex"] = serde_json::Value::String(txid_internal_hex.clone());
+        }
+        if let Some(unsigned_hex) = &unsigned_hex {
+            json_output["unsigned_hex"] = serde_json::Value::String(unsigned_hex.clone());
+        }
+        if let Some(stripped_hex) = &stripped_hex {
+            json_output["stripped_hex"] = serde_json::Value::String(stripped_hex.clone());
+        }
+        if let Some(replaceable) = replaceable {
+            json_output["replaceable"] = serde_json::Value::Bool(replaceable);
+        }
+        if let Some(report) = &preflight_report {
+            json_output["preflight"] = report.to_json();
+        }
+        if let Some(projection) = &extra_inputs_projection {
+            json_output["extra_inputs_projection"] = projection.clone();
+        }
+        println!("{}", json_output);
+    } else {
+        println!("{}", serialized_tx);
+        if let Some(unsigned_hex) = &unsigned_hex {
+            eprintln!("unsigned: {}", unsigned_hex);
+        }
+        if let Some(stripped_hex) = &stripped_hex {
+            eprintln!("stripped (witnessなし): {}", stripped_hex);
+        }
+        if let Some(txid_internal_hex) = &txid_internal_hex {
+            eprintln!("TXID (表示順/RPC・エクスプローラー): {}", txid);
+            eprintln!("TXID (内部バイト順): {}", txid_internal_hex);
+        }
+    }
 
-    println!("{}", serialized_tx);
+    let output_file = match (&args.output_file, &args.output_dir) {
+        (Some(output_file), _) => output_file.clone(),
+        (None, Some(output_dir)) => {
+            fs::create_dir_all(output_dir)?;
+            templated_output_path(output_dir, &args.output_name_template, signed_tx, result.fee_rate_sats_per_vb, input_file, cli_network)
+        }
+        (None, None) => return Err(AppError::InputValidation("--output-file か --output-dir を指定してください".to_string())),
+    };
+    write_transaction_file(&output_file, signed_tx, args.binary)?;
 
-    let mut output_file = File::create(&args.output_file).map_err(|e| {
-        log::error!("出力ファイルの作成に失敗しました: {:?}", args.output_file);
-        AppError::Io(e)
-    })?;
-    output_file.write_all(serialized_tx.as_bytes()).map_err(|e| {
-        log::error!("出力ファイルへの書き込みに失敗しました。");
-        AppError::Io(e)
-    })?;
-    log::info!("Raw transactionを {:?} に保存しました。", args.output_file);
+    if let Some((state_file, address)) = &rotated_change_address {
+        change_state::record_used_change_address(state_file, address)?;
+        log::info!("おつり先 {} を使用済みとして {:?} に記録しました。", address, state_file);
+    }
+
+    if let Some(manifest_path) = &args.manifest {
+        let manifest = serde_json::to_string_pretty(&signing_manifest(&result, cli_network))
+            .map_err(|e| AppError::Internal(format!("マニフェストのシリアライズに失敗しました: {}", e)))?;
+        fs::write(manifest_path, manifest)?;
+        log::info!("署名マニフェストを {:?} に保存しました。", manifest_path);
+    }
+
+    if let Some(warnings_path) = &args.warnings_json {
+        let contents = serde_json::to_string_pretty(&warnings_json(&result))
+            .map_err(|e| AppError::Internal(format!("警告のシリアライズに失敗しました: {}", e)))?;
+        fs::write(warnings_path, contents)?;
+        log::info!("警告 {} 件を {:?} に保存しました。", result.warnings.len(), warnings_path);
+    }
+
+    if let Some(review_path) = &args.review_package {
+        let package = serde_json::to_string_pretty(&review_package(&result, cli_network))
+            .map_err(|e| AppError::Internal(format!("レビュー用パッケージのシリアライズに失敗しました: {}", e)))?;
+        fs::write(review_path, package)?;
+        log::info!("レビュー用パッケージを {:?} に保存しました。", review_path);
+    }
+
+    if let Some(spent_outpoints_path) = &args.spent_outpoints_file {
+        let mut contents = spent_outpoints.join("\n");
+        contents.push('\n');
+        fs::write(spent_outpoints_path, contents)?;
+        log::info!("消費するアウトポイント {} 件を {:?} に保存しました。", spent_outpoints.len(), spent_outpoints_path);
+    }
+
+    #[cfg(feature = "bbqr")]
+    if let Some(bbqr_path) = &args.bbqr_out {
+        let (data, file_type) = if args.bbqr_psbt {
+            (psbt::export_psbt_bytes(&result)?, bbqr::BbqrFileType::Psbt)
+        } else {
+            (encode::serialize(signed_tx), bbqr::BbqrFileType::Transaction)
+        };
+        let frames = bbqr::encode_frames(&data, file_type, args.bbqr_frame_chars)?;
+        let mut contents = frames.join("\n");
+        contents.push('\n');
+        fs::write(bbqr_path, contents)?;
+        log::info!("BBQrフレーム {} 件を {:?} に保存しました。", frames.len(), bbqr_path);
+    }
+
+    if let Some(psbt_path) = &args.psbt_out {
+        let psbt_base64 = psbt::export_psbt_base64(&result)?;
+        fs::write(psbt_path, psbt_base64)?;
+        log::info!("PSBTを {:?} に保存しました。", psbt_path);
+    }
 
     log::info!("処理が正常に完了しました。");
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    // ベンチマーク用に、ヒープの確保量の現在値と最大値を記録するアロケーター
+    struct PeakAllocator;
+
+    static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+    static PEAK_ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe impl GlobalAlloc for PeakAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = unsafe { System.alloc(layout) };
+            if !ptr.is_null() {
+                let allocated = ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+                PEAK_ALLOCATED_BYTES.fetch_max(allocated, Ordering::Relaxed);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) };
+            ALLOCATED_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+    // f の実行中のヒープ確保量の最大値 (実行前からの増分) を返す。並行して動く他のテストの確保も含まれる
+    fn peak_heap_bytes<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let base = ALLOCATED_BYTES.load(Ordering::Relaxed);
+        PEAK_ALLOCATED_BYTES.store(base, Ordering::Relaxed);
+        let value = f();
+        (value, PEAK_ALLOCATED_BYTES.load(Ordering::Relaxed).saturating_sub(base))
+    }
+
+    fn bench_utxo_json(index: usize) -> String {
+        format!(
+            r#"{{"txid": "{:064x}", "vout": 0, "scriptPubkeyHex": "0014751e76e8199196d454941c45d1b3a323f1433bd6", "valueSats": 100000, "privateKeyWif": "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA"}}"#,
+            index + 1,
+        )
+    }
+
+    // 50,000 UTXO の設定ファイルの読み込み方法ごとのヒープ使用量の最大値と所要時間を比較する。
+    // 他のテストの確保が混ざらないよう単独で実行する:
+    // cargo test --release -- --ignored bench_load_config_peak_heap --nocapture
+    #[test]
+    #[ignore]
+    fn bench_load_config_peak_heap() {
+        const UTXO_COUNT: usize = 50_000;
+        let dir = std::env::temp_dir().join(format!("signer-bench-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let utxos: Vec<String> = (0..UTXO_COUNT).map(bench_utxo_json).collect();
+        let rest = r#""outputs": [{"address": "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r", "valueSats": 60000}], "feeRateSatsPerVb": 1"#;
+        let config_path = dir.join("config.json");
+        fs::write(&config_path, format!(r#"{{"utxos": [{}], {}}}"#, utxos.join(","), rest)).unwrap();
+        let jsonl_path = dir.join("utxos.jsonl");
+        fs::write(&jsonl_path, utxos.join("\n")).unwrap();
+        drop(utxos);
+        println!("設定ファイル: {} UTXO, {} bytes", UTXO_COUNT, fs::metadata(&config_path).unwrap().len());
+
+        let start = Instant::now();
+        let (config, read_to_string_peak) = peak_heap_bytes(|| {
+            serde_json::from_str::<InputConfig>(&fs::read_to_string(&config_path).unwrap()).unwrap()
+        });
+        println!("read_to_string + from_str: 最大 {} bytes, {:?}", read_to_string_peak, start.elapsed());
+        assert_eq!(config.utxos.len(), UTXO_COUNT);
+        drop(config);
+
+        let start = Instant::now();
+        let (config, from_reader_peak) = peak_heap_bytes(|| load_config(&config_path).unwrap());
+        println!("load_config (from_reader): 最大 {} bytes, {:?}", from_reader_peak, start.elapsed());
+        assert_eq!(config.utxos.len(), UTXO_COUNT);
+        drop(config);
+
+        let start = Instant::now();
+        let (utxos, jsonl_peak) = peak_heap_bytes(|| {
+            let mut utxos = Vec::new();
+            load_utxos_jsonl(&jsonl_path, &mut utxos).unwrap();
+            utxos
+        });
+        println!("--utxos-jsonl (全UTXOを保持): 最大 {} bytes, {:?}", jsonl_peak, start.elapsed());
+        assert_eq!(utxos.len(), UTXO_COUNT);
+        drop(utxos);
+
+        // analyze-consolidation と同じく、1件ずつ見積もってUTXOを破棄する
+        let secp = Secp256k1::new();
+        let mut key_cache = KeyCache::default();
+        let fee_rate = FeeRate::from_sat_per_vb(1);
+        let start = Instant::now();
+        let (costs, streaming_peak) = peak_heap_bytes(|| {
+            let mut costs = Vec::new();
+            for_each_jsonl_utxo(&jsonl_path, |utxo| {
+                costs.push(consolidate::estimate_input_cost(costs.len(), &utxo, fee_rate, None, BitcoinNetwork::Testnet, &secp, &mut key_cache)?);
+                Ok(())
+            })
+            .unwrap();
+            costs
+        });
+        println!("--utxos-jsonl (1件ずつ見積もり): 最大 {} bytes, {:?}", streaming_peak, start.elapsed());
+        assert_eq!(costs.len(), UTXO_COUNT);
+
+        fs::remove_dir_all(&dir).unwrap();
+        // 元のJSON文字列を保持しない分だけ少ない
+        assert!(from_reader_peak < read_to_string_peak);
+        // 行数分を一度だけ確保するため、配列の拡張時の一時的な二重確保もない
+        assert!(jsonl_peak < read_to_string_peak * 2 / 3, "{} / {}", jsonl_peak, read_to_string_peak);
+        // UTXOを保持しない場合は見積もり結果の分のみ
+        assert!(streaming_peak < read_to_string_peak / 4, "{} / {}", streaming_peak, read_to_string_peak);
+    }
+
+    #[test]
+    fn quiet_disables_metrics_and_input_addresses() {
+        let args = CliArgs::try_parse_from(["signer", "-i", "tx.json", "-o", "tx.hex", "--metrics", "--show-input-addresses", "--quiet"]).unwrap();
+        let options = signing_options(&args, BitcoinNetwork::Testnet).unwrap();
+        assert!(!options.metrics);
+        assert!(!options.show_input_addresses);
+
+        let args = CliArgs::try_parse_from(["signer", "-i", "tx.json", "-o", "tx.hex", "--metrics", "--show-input-addresses"]).unwrap();
+        let options = signing_options(&args, BitcoinNetwork::Testnet).unwrap();
+        assert!(options.metrics);
+        assert!(options.show_input_addresses);
+    }
+
+    #[test]
+    fn compute_sighashes_emits_one_entry_per_fee_rate_variant() {
+        let config: InputConfig = serde_json::from_str(r#"{
+            "utxos": [{"txid": "0000000000000000000000000000000000000000000000000000000000000001", "vout": 0,
+                "scriptPubkeyHex": "0014751e76e8199196d454941c45d1b3a323f1433bd6", "valueSats": 100000,
+                "publicKeyHex": "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"}],
+            "outputs": [{"address": "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r", "valueSats": 60000}],
+            "changeAddress": "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+        }"#).unwrap();
+        let args = CliArgs::try_parse_from(["signer", "--fee-rate-variants", "2,5", "compute-sighashes", "-i", "tx.json"]).unwrap();
+        let options = SigningOptions { sighash_only: true, ..signing_options(&args, BitcoinNetwork::Testnet).unwrap() };
+        let fee_rates = args.fee_rate_variants.as_deref().unwrap();
+
+        let output = sighash_variants_json(config, fee_rates, BitcoinNetwork::Testnet, &options, &Secp256k1::new(), &mut KeyCache::default()).unwrap();
+        let variants = output["variants"].as_array().unwrap();
+        assert_eq!(variants.len(), 2);
+        assert!(variants[0]["fee_sats"].as_u64().unwrap() < variants[1]["fee_sats"].as_u64().unwrap());
+        // おつりが変わるため、変種ごとに未署名トランザクションと署名ハッシュが異なる
+        assert_ne!(variants[0]["unsigned_hex"], variants[1]["unsigned_hex"]);
+        assert_ne!(variants[0]["inputs"][0]["sighash"], variants[1]["inputs"][0]["sighash"]);
+        assert_eq!(variants[1]["inputs"][0]["public_key"], "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+    }
+
+    // P2WPKH と P2PKH の2入力に署名する設定
+    fn two_input_config() -> InputConfig {
+        serde_json::from_str(r#"{
+            "utxos": [
+                {"txid": "0000000000000000000000000000000000000000000000000000000000000001", "vout": 0,
+                    "scriptPubkeyHex": "0014751e76e8199196d454941c45d1b3a323f1433bd6", "valueSats": 100000,
+                    "privateKeyWif": "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA"},
+                {"txid": "0000000000000000000000000000000000000000000000000000000000000002", "vout": 1,
+                    "scriptPubkeyHex": "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac", "valueSats": 50000,
+                    "privateKeyWif": "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA"}
+            ],
+            "outputs": [{"address": "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r", "valueSats": 60000}],
+            "feeRateSatsPerVb": 2,
+            "changeAddress": "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+        }"#).unwrap()
+    }
+
+    fn sign_two_input_config<C: Signing>(secp: &Secp256k1<C>) -> Transaction {
+        transaction::create_and_sign_transaction(two_input_config(), BitcoinNetwork::Testnet, secp, &mut KeyCache::default(), &SigningOptions::default(), |_| {})
+            .unwrap()
+            .transaction
+    }
+
+    #[test]
+    fn signing_only_context_produces_the_same_transaction() {
+        // 署名は RFC6979 で決定的なため、コンテキストの種類によらず同じバイト列になる
+        assert_eq!(sign_two_input_config(&Secp256k1::signing_only()), sign_two_input_config(&Secp256k1::new()));
+    }
+
+    #[test]
+    fn effective_config_redacts_wifs_and_records_the_resolved_network() {
+        let mut config = two_input_config();
+        config.fee_bump_utxos.push(config.utxos[0].clone());
+        let wif = config.utxos[0].private_key_wif.as_deref().unwrap().to_string();
+
+        let config_json = effective_config_json(config, BitcoinNetwork::Regtest).unwrap();
+        assert!(!config_json.contains(&wif));
+        let dumped: serde_json::Value = serde_json::from_str(&config_json).unwrap();
+        assert_eq!(dumped["network"], "regtest");
+        for utxo in dumped["utxos"].as_array().unwrap().iter().chain(dumped["feeBumpUtxos"].as_array().unwrap()) {
+            assert_eq!(utxo["privateKeyWif"], redact::REDACTED);
+        }
+        // 秘密鍵以外はそのまま出力する
+        assert_eq!(dumped["utxos"][1]["valueSats"], 50_000);
+        assert_eq!(dumped["outputs"][0]["address"], "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r");
+    }
+
+    #[test]
+    fn review_package_lets_a_reviewer_recompute_txid_and_fee_from_the_hex() {
+        let result = transaction::create_and_sign_transaction(
+            two_input_config(), BitcoinNetwork::Testnet, &Secp256k1::new(), &mut KeyCache::default(), &SigningOptions::default(), |_| {})
+            .unwrap();
+        let package = review_package(&result, BitcoinNetwork::Testnet);
+
+        // 第三者の手順: hex をデコードし、txid と手数料を入力データから再計算して申告値と照合する
+        let decoded: Transaction = encode::deserialize_hex(package["hex"].as_str().unwrap()).unwrap();
+        assert_eq!(package["txid"], decoded.compute_txid().to_string());
+        let inputs = package["inputs"].as_array().unwrap();
+        assert_eq!(inputs.len(), decoded.input.len());
+        for (input, tx_in) in inputs.iter().zip(&decoded.input) {
+            assert_eq!(input["outpoint"], tx_in.previous_output.to_string());
+        }
+        let total_input_sats: u64 = inputs.iter().map(|input| input["value_sats"].as_u64().unwrap()).sum();
+        let total_output_sats: u64 = decoded.output.iter().map(|output| output.value.to_sat()).sum();
+        assert_eq!(total_input_sats, 150_000);
+        assert_eq!(package["fee_sats"], total_input_sats - total_output_sats);
+        assert_eq!(package["vsize"], transaction::transaction_vsize(&decoded));
+        assert_eq!(inputs[1]["script_pubkey"], "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac");
+        assert_eq!(inputs[0]["address"], "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx");
+
+        let outputs = package["outputs"].as_array().unwrap();
+        let recipient = outputs.iter().find(|output| output["address"] == "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r").unwrap();
+        assert_eq!(recipient["value_sats"], 60_000);
+        assert_eq!(recipient["is_change"], false);
+        let change: Vec<_> = outputs.iter().filter(|output| output["is_change"] == true).collect();
+        assert_eq!(change.len(), 1);
+        assert_eq!(change[0]["address"], "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx");
+    }
+
+    #[test]
+    fn max_io_ratio_below_one_or_not_finite_is_rejected() {
+        for ratio in ["0.5", "NaN", "inf"] {
+            let args = CliArgs::try_parse_from(["signer", "--max-io-ratio", ratio, "-i", "tx.json", "-o", "tx.hex"]).unwrap();
+            assert!(matches!(signing_options(&args, BitcoinNetwork::Testnet), Err(AppError::InputValidation(_))), "{}", ratio);
+        }
+        let args = CliArgs::try_parse_from(["signer", "--max-io-ratio", "1", "-i", "tx.json", "-o", "tx.hex"]).unwrap();
+        assert_eq!(signing_options(&args, BitcoinNetwork::Testnet).unwrap().max_io_ratio, Some(1.0));
+    }
+
+    #[test]
+    fn warnings_json_lists_each_warning_with_its_stable_code() {
+        // P2PKH 入力は圧縮鍵 (script-type-mismatch)、受信者はその入力と同じアドレス (self-send)。ダスト未満の出力も加える
+        let mut config = two_input_config();
+        let mut dust_output = config.outputs[0].clone();
+        dust_output.value_sats = 100;
+        config.outputs.push(dust_output);
+        let result = transaction::create_and_sign_transaction(
+            config, BitcoinNetwork::Testnet, &Secp256k1::new(), &mut KeyCache::default(), &SigningOptions::default(), |_| {})
+            .unwrap();
+
+        let output = warnings_json(&result);
+        assert_eq!(output["txid"], result.transaction.compute_txid().to_string());
+        let warnings = output["warnings"].as_array().unwrap();
+        let mut codes: Vec<&str> = warnings.iter().map(|warning| warning["code"].as_str().unwrap()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes, ["address-reuse", "dust-output", "script-type-mismatch", "self-send"]);
+        assert_eq!(warnings.len(), result.warnings.len());
+        for (warning, signing_warning) in warnings.iter().zip(&result.warnings) {
+            assert_eq!(warning["message"], signing_warning.to_string());
+        }
+    }
+
+    #[test]
+    fn only_http_and_https_input_files_are_treated_as_urls() {
+        assert_eq!(config_url(Path::new("https://coordinator.example/tx.json")), Some("https://coordinator.example/tx.json"));
+        assert_eq!(config_url(Path::new("http://127.0.0.1:8080/tx.json")), Some("http://127.0.0.1:8080/tx.json"));
+        for path in ["tx.json", "./http/tx.json", "/srv/https/tx.json", "ftp://coordinator.example/tx.json", "file:///tx.json"] {
+            assert_eq!(config_url(Path::new(path)), None, "{}", path);
+        }
+    }
+
+    #[cfg(not(feature = "http"))]
+    #[test]
+    fn url_input_without_the_http_feature_is_a_fetch_error() {
+        match load_config(Path::new("https://coordinator.example/tx.json")) {
+            Err(AppError::ConfigFetch { url, reason }) => {
+                assert_eq!(url, "https://coordinator.example/tx.json");
+                assert!(reason.contains("http"), "{}", reason);
+            }
+            other => panic!("ConfigFetch になりませんでした: {:?}", other.map(|_| ())),
+        }
+    }
+
+    // 接続ごとに responses を順に1つ返すHTTPサーバーを起動し、そのURLを返す
+    #[cfg(feature = "http")]
+    fn serve_responses(responses: Vec<String>) -> String {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/tx.json", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for (response, stream) in responses.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn fetched_config_distinguishes_fetch_failures_from_parse_failures() {
+        let response = |content_type: &str, body: &str| format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", content_type, body.len(), body);
+        let config = r#"{"utxos": [], "outputs": [{"address": "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r", "valueSats": 60000}]}"#;
+        let url = serve_responses(vec![
+            response("application/json; charset=utf-8", config),
+            response("text/html", config),
+            response("application/json", "{not json"),
+            format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", MAX_CONFIG_FETCH_BYTES + 1),
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        ]);
+        let input_file = PathBuf::from(&url);
+
+        assert_eq!(load_config(&input_file).unwrap().outputs[0].value_sats, 60_000);
+        assert!(matches!(load_config(&input_file), Err(AppError::ConfigFetch { reason, .. }) if reason.contains("Content-Type")));
+        assert!(matches!(load_config(&input_file), Err(AppError::JsonParse { .. })));
+        assert!(matches!(load_config(&input_file), Err(AppError::ConfigFetch { reason, .. }) if reason.contains("上限")));
+        assert!(matches!(load_config(&input_file), Err(AppError::ConfigFetch { .. })));
+    }
+
+    // --secp-signing-only の効果を測る。コンテキスト作成時のヒープ確保量と、2入力の設定の署名にかかる時間を比較する:
+    // cargo test --release -- --ignored bench_secp_context --nocapture
+    #[test]
+    #[ignore]
+    fn bench_secp_context() {
+        const RUNS: u32 = 200;
+        let (all, all_heap) = peak_heap_bytes(Secp256k1::new);
+        let (signing_only, signing_only_heap) = peak_heap_bytes(Secp256k1::signing_only);
+        println!("コンテキスト作成時のヒープ確保量: 兼用 {} bytes, 署名専用 {} bytes", all_heap, signing_only_heap);
+
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            sign_two_input_config(&all);
+        }
+        println!("兼用コンテキスト: {:?}/回", start.elapsed() / RUNS);
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            sign_two_input_config(&signing_only);
+        }
+        println!("署名専用コンテキスト: {:?}/回", start.elapsed() / RUNS);
+        assert!(signing_only_heap <= all_heap);
+    }
+}
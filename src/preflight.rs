@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+
+use bitcoin::{key::XOnlyPublicKey, secp256k1::Secp256k1, Transaction};
+
+use crate::{
+    transaction::{dust_threshold_sats, transaction_vsize, MAX_STANDARD_TX_WEIGHT_WU, MIN_RELAY_FEE_RATE_SATS_PER_VB},
+    types::SignedTransaction,
+};
+
+// Bitcoin Core の標準ルールのうち、チェーンの状態なしで確認できる上限値
+// 非witness部分の最小サイズ (MIN_STANDARD_TX_NONWITNESS_SIZE)
+const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+// script_sig の最大サイズ (MAX_STANDARD_SCRIPTSIG_SIZE)
+const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+// OP_RETURN 出力のscriptPubKeyの最大サイズ (-datacarriersize の既定値 80 + OP_RETURN とプッシュ命令)
+const MAX_OP_RETURN_RELAY: usize = 83;
+// ベアマルチシグ出力で許可される公開鍵の最大数
+const MAX_BARE_MULTISIG_KEYS: usize = 3;
+
+// 1項目分の確認結果
+#[derive(Debug)]
+pub struct PreflightCheck {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+// testmempoolaccept 相当の確認をローカルで行った結果。未確認のUTXOやロックタイムの成立など、
+// チェーンの状態が必要な項目は含まない
+#[derive(Debug)]
+pub struct PreflightReport {
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    // すべての確認に通った場合のみ受理される見込みとする
+    pub fn likely_accepted(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &PreflightCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let checks: Vec<serde_json::Value> = self
+            .checks
+            .iter()
+            .map(|check| serde_json::json!({ "name": check.name, "passed": check.passed, "detail": check.detail }))
+            .collect();
+        serde_json::json!({ "likely_accepted": self.likely_accepted(), "checks": checks })
+    }
+}
+
+/// 署名済みトランザクションに対して、ノードが標準ルール・コンセンサスルールで拒否しそうな点をローカルで確認する。
+pub fn run_preflight(result: &SignedTransaction) -> PreflightReport {
+    let tx = &result.transaction;
+    let checks = vec![
+        check_version(tx),
+        check_size(tx),
+        check_fee_rate(result),
+        check_duplicate_inputs(tx),
+        check_script_sigs(tx),
+        check_output_scripts(tx),
+        check_dust(result),
+        check_signatures(result),
+    ];
+    PreflightReport { checks }
+}
+
+pub(crate) fn check(name: &'static str, problems: Vec<String>, ok_detail: String) -> PreflightCheck {
+    if problems.is_empty() {
+        PreflightCheck { name, passed: true, detail: ok_detail }
+    } else {
+        PreflightCheck { name, passed: false, detail: problems.join("; ") }
+    }
+}
+
+fn check_version(tx: &Transaction) -> PreflightCheck {
+    let problems = if tx.version.is_standard() {
+        Vec::new()
+    } else {
+        vec![format!("バージョン {} は標準ではありません (1 または 2)", tx.version)]
+    };
+    check("version", problems, format!("バージョン {}", tx.version))
+}
+
+fn check_size(tx: &Transaction) -> PreflightCheck {
+    let weight = tx.weight().to_wu();
+    let base_size = tx.base_size();
+    let mut problems = Vec::new();
+    if weight > MAX_STANDARD_TX_WEIGHT_WU {
+        problems.push(format!("ウェイト {} WU が上限 {} WU を超えています", weight, MAX_STANDARD_TX_WEIGHT_WU));
+    }
+    if base_size < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        problems.push(format!("witnessを除いたサイズ {} B が下限 {} B 未満です", base_size, MIN_STANDARD_TX_NONWITNESS_SIZE));
+    }
+    check("size", problems, format!("ウェイト {} WU, witnessを除いたサイズ {} B", weight, base_size))
+}
+
+fn check_fee_rate(result: &SignedTransaction) -> PreflightCheck {
+    let vsize = transaction_vsize(&result.transaction);
+    let min_fee_sats = vsize.saturating_mul(MIN_RELAY_FEE_RATE_SATS_PER_VB);
+    let problems = if result.fee_sats < min_fee_sats {
+        vec![format!("手数料 {} sats が最低リレー手数料 {} sats ({} vB) を下回っています", result.fee_sats, min_fee_sats, vsize)]
+    } else {
+        Vec::new()
+    };
+    check("fee-rate", problems, format!("手数料 {} sats / {} vB", result.fee_sats, vsize))
+}
+
+fn check_duplicate_inputs(tx: &Transaction) -> PreflightCheck {
+    let mut seen = HashSet::new();
+    let problems = tx
+        .input
+        .iter()
+        .enumerate()
+        .filter(|(_, input)| !seen.insert(input.previous_output))
+        .map(|(index, input)| format!("入力 {} のアウトポイント {} が重複しています", index, input.previous_output))
+        .collect();
+    check("duplicate-inputs", problems, format!("入力 {} 件", tx.input.len()))
+}
+
+fn check_script_sigs(tx: &Transaction) -> PreflightCheck {
+    let mut problems = Vec::new();
+    for (index, input) in tx.input.iter().enumerate() {
+        if input.script_sig.is_empty() && input.witness.is_empty() {
+            problems.push(format!("入力 {} は未署名です", index));
+        }
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            problems.push(format!("入力 {} の script_sig が {} B で上限 {} B を超えています", index, input.script_sig.len(), MAX_STANDARD_SCRIPTSIG_SIZE));
+        }
+        if !input.script_sig.is_push_only() {
+            problems.push(format!("入力 {} の script_sig がプッシュ命令のみではありません", index));
+        }
+    }
+    check("script-sig", problems, "すべての入力に署名があり、script_sig は標準的です".to_string())
+}
+
+fn check_output_scripts(tx: &Transaction) -> PreflightCheck {
+    let mut problems = Vec::new();
+    let mut op_return_count = 0;
+    for (index, output) in tx.output.iter().enumerate() {
+        let script = &output.script_pubkey;
+        if script.is_op_return() {
+            op_return_count += 1;
+            if script.len() > MAX_OP_RETURN_RELAY {
+                problems.push(format!("出力 {} の OP_RETURN が {} B で上限 {} B を超えています", index, script.len(), MAX_OP_RETURN_RELAY));
+            }
+        } else if script.is_multisig() {
+            let keys = script.instructions().filter(|instruction| {
+                matches!(instruction, Ok(bitcoin::script::Instruction::PushBytes(bytes)) if bytes.len() == 33 || bytes.len() == 65)
+            }).count();
+            if keys > MAX_BARE_MULTISIG_KEYS {
+                problems.push(format!("出力 {} のベアマルチシグの公開鍵が {} 個で上限 {} 個を超えています", index, keys, MAX_BARE_MULTISIG_KEYS));
+            }
+        } else if !(script.is_p2pk() || script.is_p2pkh() || script.is_p2sh() || script.is_witness_program()) {
+            problems.push(format!("出力 {} のscriptPubKeyは標準的な形式ではありません", index));
+        }
+    }
+    if op_return_count > 1 {
+        problems.push(format!("OP_RETURN 出力が {} 個あります (標準では1個まで)", op_return_count));
+    }
+    check("output-scripts", problems, format!("出力 {} 件はすべて標準的な形式です", tx.output.len()))
+}
+
+fn check_dust(result: &SignedTransaction) -> PreflightCheck {
+    let problems = result
+        .transaction
+        .output
+        .iter()
+        .enumerate()
+        .filter_map(|(index, output)| {
+            let threshold_sats = dust_threshold_sats(&output.script_pubkey, result.dust_relay_fee_sats_per_kvb);
+            (output.value.to_sat() < threshold_sats)
+                .then(|| format!("出力 {} の金額 {} sats がダスト閾値 {} sats 未満です", index, output.value.to_sat(), threshold_sats))
+        })
+        .collect();
+    check("dust", problems, format!("ダストリレー手数料率 {} sats/kvB でダスト出力はありません", result.dust_relay_fee_sats_per_kvb))
+}
+
+// 署名ループで作成した署名を、記録した署名ハッシュと公開鍵で検証し直す (skipSigning の入力は対象外)
+fn check_signatures(result: &SignedTransaction) -> PreflightCheck {
+    let secp = Secp256k1::verification_only();
+    let mut verified = 0;
+    let mut problems = Vec::new();
+    for (index, input) in result.inputs.iter().enumerate() {
+        // P2TR は scriptPubKey の出力鍵 (OP_1 に続く32バイト) で検証する
+        if let (Some(signature), Some(sighash)) = (&input.taproot_signature, &input.sighash) {
+            let output_key = XOnlyPublicKey::from_slice(&input.spent_output.script_pubkey.as_bytes()[2..]);
+            match output_key.map(|output_key| secp.verify_schnorr(&signature.signature, sighash, &output_key)) {
+                Ok(Ok(())) => verified += 1,
+                Ok(Err(e)) | Err(e) => problems.push(format!("入力 {} のSchnorr署名を検証できません: {}", index, e)),
+            }
+            continue;
+        }
+        let (Some(signature), Some(sighash), Some(public_key)) = (&input.signature, &input.sighash, &input.public_key) else {
+            continue;
+        };
+        // ベアマルチシグでは2つ目以降の鍵の署名も同じ署名ハッシュで検証する
+        let signatures = std::iter::once((public_key, signature))
+            .chain(input.cosigner_signatures.iter().map(|(public_key, signature)| (public_key, signature)));
+        for (public_key, signature) in signatures {
+            match secp.verify_ecdsa(sighash, &signature.signature, &public_key.inner) {
+                Ok(()) => verified += 1,
+                Err(e) => problems.push(format!("入力 {} の公開鍵 {} の署名を検証できません: {}", index, public_key, e)),
+            }
+        }
+    }
+    check("signatures", problems, format!("署名 {} 件を検証しました", verified))
+}
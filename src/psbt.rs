@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use bitcoin::{
+    bip32::{DerivationPath, Fingerprint},
+    key::XOnlyPublicKey,
+    psbt::{raw::ProprietaryKey, Psbt, PsbtSighashType},
+    sighash::TapSighashType,
+    PublicKey, Transaction,
+};
+
+use crate::{config::Bip32DerivationDef, error::{field_error, AppError}, types::{Bip32Derivation, SignedTransaction}};
+
+// 出力メモを格納する独自キー (PSBT_OUT_PROPRIETARY) の識別子とサブタイプ
+const PROPRIETARY_PREFIX: &[u8] = b"txsigner";
+const PROPRIETARY_SUBTYPE_MEMO: u8 = 0x00;
+
+fn psbt_error(reason: impl Into<String>) -> AppError {
+    AppError::Psbt(reason.into())
+}
+
+/// 設定のBIP32導出情報を検証して変換する。`publicKeyHex` 省略時は default_public_key (入力のWIFの公開鍵) を使う。
+/// field_path は設定ファイル内の位置 (例: "utxos[0].bip32Derivation") で、エラーの場所の表示に使う。
+pub fn parse_bip32_derivation(
+    derivation: &Bip32DerivationDef,
+    default_public_key: Option<PublicKey>,
+    field_path: &str,
+) -> Result<Bip32Derivation, AppError> {
+    let fingerprint = Fingerprint::from_str(&derivation.master_fingerprint)
+        .map_err(|e| field_error(field_path, "masterFingerprint", format!("{} が不正です: {}", derivation.master_fingerprint, e)))?;
+    let path = DerivationPath::from_str(&derivation.path)
+        .map_err(|e| field_error(field_path, "path", format!("{} が不正です: {}", derivation.path, e)))?;
+    let public_key = match (&derivation.public_key_hex, default_public_key) {
+        (Some(public_key_hex), _) => PublicKey::from_str(public_key_hex)
+            .map_err(|e| field_error(field_path, "publicKeyHex", format!("パース失敗: {}", e)))?,
+        (None, Some(public_key)) => public_key,
+        (None, None) => return Err(field_error(field_path, "publicKeyHex", "指定してください")),
+    };
+    if let Some(default_public_key) = default_public_key.filter(|key| *key != public_key) {
+        return Err(field_error(field_path, "publicKeyHex", format!("{} がWIFの公開鍵 {} と一致しません", public_key, default_public_key)));
+    }
+    Ok((public_key.inner, (fingerprint, path)))
+}
+
+/// 署名結果から、各入力の sighash type と部分署名を含むPSBTを構築する。
+///
+/// SegWit入力には witness_utxo を設定する。non_witness_utxo は前トランザクション全体 (prevTxHex) が指定された入力にのみ設定する。
+/// 設定にBIP32導出情報がある入力・出力には bip32_derivation を設定する。
+pub fn build_psbt(result: &SignedTransaction) -> Result<Psbt, AppError> {
+    let mut psbt = Psbt::from_unsigned_tx(result.unsigned_transaction.clone())
+        .map_err(|e| psbt_error(format!("未署名トランザクションからのPSBT作成失敗: {}", e)))?;
+    for ((psbt_input, signed_input), tx_input) in psbt.inputs.iter_mut().zip(&result.inputs).zip(&result.transaction.input) {
+        if signed_input.spent_output.script_pubkey.is_witness_program() {
+            psbt_input.witness_utxo = Some(signed_input.spent_output.clone());
+        }
+        psbt_input.non_witness_utxo = signed_input.previous_transaction.clone();
+        // P2TR の鍵パスの署名は tap_key_sig に、BIP86 の内部鍵 (WIFの公開鍵) は tap_internal_key に格納する
+        if signed_input.spent_output.script_pubkey.is_p2tr() {
+            psbt_input.sighash_type = Some(PsbtSighashType::from(TapSighashType::Default));
+            psbt_input.tap_internal_key = signed_input.public_key.map(XOnlyPublicKey::from);
+            psbt_input.tap_key_sig = signed_input.taproot_signature;
+        } else {
+            psbt_input.sighash_type = Some(PsbtSighashType::from(signed_input.sighash_type));
+        }
+        if let Some((public_key, key_source)) = &signed_input.bip32_derivation {
+            psbt_input.bip32_derivation.insert(*public_key, key_source.clone());
+        }
+        match (signed_input.signature, signed_input.public_key) {
+            (Some(signature), Some(public_key)) => {
+                psbt_input.partial_sigs.insert(public_key, signature);
+                psbt_input.partial_sigs.extend(signed_input.cosigner_signatures.iter().copied());
+            }
+            // skipSigning の入力は他者の署名が確定済みのため、最終化済みの入力として格納する
+            (_, None) => {
+                psbt_input.final_script_sig = Some(tx_input.script_sig.clone()).filter(|s| !s.is_empty());
+                psbt_input.final_script_witness = Some(tx_input.witness.clone()).filter(|w| !w.is_empty());
+            }
+            _ => {}
+        }
+    }
+    for ((psbt_output, derivation), memo) in psbt.outputs.iter_mut().zip(&result.output_bip32_derivations).zip(&result.output_memos) {
+        if let Some((public_key, key_source)) = derivation {
+            psbt_output.bip32_derivation.insert(*public_key, key_source.clone());
+        }
+        if let Some(memo) = memo {
+            let key = ProprietaryKey { prefix: PROPRIETARY_PREFIX.to_vec(), subtype: PROPRIETARY_SUBTYPE_MEMO, key: Vec::new() };
+            psbt_output.proprietary.insert(key, memo.as_bytes().to_vec());
+        }
+    }
+    Ok(psbt)
+}
+
+/// PSBTの各入力の sighash_type と部分署名のsighashバイトが、設定で宣言された sighash type と一致することを確認する。
+pub fn verify_psbt_sighash_types(psbt: &Psbt, result: &SignedTransaction) -> Result<(), AppError> {
+    if psbt.inputs.len() != result.inputs.len() {
+        return Err(psbt_error(format!("PSBTの入力数 {} が署名結果の入力数 {} と一致しません", psbt.inputs.len(), result.inputs.len())));
+    }
+    for (input_index, (psbt_input, signed_input)) in psbt.inputs.iter().zip(&result.inputs).enumerate() {
+        if signed_input.spent_output.script_pubkey.is_p2tr() {
+            let psbt_type = psbt_input
+                .sighash_type
+                .ok_or_else(|| psbt_error(format!("入力 {} に sighash_type がありません", input_index)))?
+                .taproot_hash_ty()
+                .map_err(|e| psbt_error(format!("入力 {} の sighash_type がTaproot用ではありません: {}", input_index, e)))?;
+            let signature_type = psbt_input.tap_key_sig.map(|signature| signature.sighash_type);
+            if psbt_type != TapSighashType::Default || signature_type.is_some_and(|ty| ty != TapSighashType::Default) {
+                return Err(psbt_error(format!("入力 {} (P2TR) の sighash type が SIGHASH_DEFAULT ではありません", input_index)));
+            }
+            continue;
+        }
+        let declared = signed_input.sighash_type;
+        let psbt_type = psbt_input
+            .sighash_type
+            .ok_or_else(|| psbt_error(format!("入力 {} に sighash_type がありません", input_index)))?
+            .ecdsa_hash_ty()
+            .map_err(|e| psbt_error(format!("入力 {} の sighash_type がECDSA用ではありません: {}", input_index, e)))?;
+        if psbt_type != declared {
+            return Err(psbt_error(format!("入力 {} の sighash_type {} が宣言された {} と一致しません", input_index, psbt_type, declared)));
+        }
+        if let Some(signature) = psbt_input.partial_sigs.values().find(|sig| sig.sighash_type != declared) {
+            return Err(psbt_error(format!("入力 {} の部分署名の sighash type {} が宣言された {} と一致しません",
+                input_index, signature.sighash_type, declared)));
+        }
+    }
+    Ok(())
+}
+
+// PSBTをbase64にシリアライズし、読み戻して sighash type と未署名トランザクションが保持されていることを確認する
+fn checked_psbt(result: &SignedTransaction) -> Result<(Psbt, String), AppError> {
+    let psbt = build_psbt(result)?;
+    let encoded = psbt.to_string();
+    let decoded = Psbt::from_str(&encoded).map_err(|e| psbt_error(format!("PSBTの読み戻し失敗: {}", e)))?;
+    verify_psbt_sighash_types(&decoded, result)?;
+    check_unsigned_tx(&decoded.unsigned_tx, &result.unsigned_transaction)?;
+    Ok((psbt, encoded))
+}
+
+/// PSBTをbase64にシリアライズし、読み戻して sighash type が保持されていることを確認した上で返す。
+pub fn export_psbt_base64(result: &SignedTransaction) -> Result<String, AppError> {
+    Ok(checked_psbt(result)?.1)
+}
+
+/// export_psbt_base64 と同じ確認を行ったPSBTをバイナリ (BIP174のシリアライズ) で返す。
+#[cfg(feature = "bbqr")]
+pub fn export_psbt_bytes(result: &SignedTransaction) -> Result<Vec<u8>, AppError> {
+    Ok(checked_psbt(result)?.0.serialize())
+}
+
+fn check_unsigned_tx(decoded: &Transaction, expected: &Transaction) -> Result<(), AppError> {
+    if decoded != expected {
+        return Err(psbt_error("読み戻したPSBTの未署名トランザクションが元のトランザクションと一致しません"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{secp256k1::Secp256k1, sighash::{EcdsaSighashType, SighashCache}, Network, Witness};
+
+    use crate::{config::InputConfig, transaction::{create_and_sign_transaction, KeyCache}, types::SigningOptions};
+
+    // 3つの P2WPKH 入力にそれぞれ異なる sighash type を宣言した設定
+    fn mixed_sighash_config() -> InputConfig {
+        let utxos: Vec<serde_json::Value> = ["SINGLE", "NONE|ANYONECANPAY", "ALL"]
+            .iter()
+            .enumerate()
+            .map(|(index, sighash_type)| serde_json::json!({
+                "txid": format!("{:064x}", index + 1),
+                "vout": 0,
+                "scriptPubkeyHex": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                "valueSats": 40_000,
+                "privateKeyWif": "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA",
+                "sighashType": sighash_type,
+            }))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "utxos": utxos,
+            "outputs": [{"address": "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r", "valueSats": 60_000}],
+            "feeRateSatsPerVb": 1,
+            "changeAddress": "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn per_input_sighash_types_survive_psbt_round_trip() {
+        let secp = Secp256k1::new();
+        let result = create_and_sign_transaction(mixed_sighash_config(), Network::Testnet, &secp, &mut KeyCache::default(), &SigningOptions::default(), |_| {}).unwrap();
+        let mut psbt = Psbt::from_str(&export_psbt_base64(&result).unwrap()).unwrap();
+
+        let declared = [EcdsaSighashType::Single, EcdsaSighashType::NonePlusAnyoneCanPay, EcdsaSighashType::All];
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        for (input_index, expected) in declared.into_iter().enumerate() {
+            assert_eq!(psbt.inputs[input_index].sighash_type, Some(PsbtSighashType::from(expected)));
+            // 読み戻したPSBTの sighash_type から計算した署名ハッシュで、部分署名が検証できる
+            let (message, sighash_type) = psbt.sighash_ecdsa(input_index, &mut cache).unwrap();
+            assert_eq!(sighash_type, expected);
+            let (public_key, signature) = psbt.inputs[input_index].partial_sigs.iter().next().unwrap();
+            assert_eq!(signature.sighash_type, expected);
+            secp.verify_ecdsa(&message, &signature.signature, &public_key.inner).unwrap();
+        }
+
+        // 部分署名から最終化したトランザクションは署名結果と一致する
+        for psbt_input in &mut psbt.inputs {
+            let (public_key, signature) = psbt_input.partial_sigs.pop_first().unwrap();
+            psbt_input.final_script_witness = Some(Witness::p2wpkh(&signature, &public_key.inner));
+        }
+        assert_eq!(psbt.extract_tx_unchecked_fee_rate(), result.transaction);
+    }
+}
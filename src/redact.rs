@@ -0,0 +1,135 @@
+use std::fmt;
+use std::ops::Deref;
+
+use schemars::{r#gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// 表示用の置換文字列。秘密鍵・WIFそのものは出力しない
+pub const REDACTED: &str = "<redacted>";
+
+// Base58の文字 (0, O, I, l を除く英数字)
+const BASE58_CHARS: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Debug / Display で中身を表示しないラッパー。秘密鍵やWIFを保持するフィールドに使い、
+/// `{:?}` で構造体ごとログやエラーに出力しても秘密が含まれないようにする。シリアライズは中身をそのまま扱う
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Redacted<T>(pub T);
+
+impl<T> Deref for Redacted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T: Serialize> Serialize for Redacted<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Redacted<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Redacted<T>, D::Error> {
+        T::deserialize(deserializer).map(Redacted)
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Redacted<T> {
+    fn is_referenceable() -> bool {
+        T::is_referenceable()
+    }
+
+    fn schema_name() -> String {
+        T::schema_name()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        T::json_schema(generator)
+    }
+}
+
+// WIFの形をした語か。チェックサムは確認しない (1文字違いのWIFもほぼ秘密鍵そのもののため伏せる)。
+// 非圧縮は51文字 (メインネット 5、テスト系 9)、圧縮は52文字 (メインネット K/L、テスト系 c) で始まる。
+// 16進数だけの語 (txidの一部など) はWIFではないとみなす
+fn looks_like_wif(word: &str) -> bool {
+    let prefixes: &[char] = match word.len() {
+        51 => &['5', '9'],
+        52 => &['K', 'L', 'c'],
+        _ => return false,
+    };
+    word.starts_with(prefixes) && !word.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// 文字列中のWIFの形をした語 (Base58の文字の連続) を `<redacted>` に置き換える。
+/// エラーメッセージが設定値をそのまま含む場合 (型の不一致など) に備え、ログとエラーの出力直前に適用する
+pub fn redact_wifs(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let word_len = rest.find(|c: char| !BASE58_CHARS.contains(c)).unwrap_or(rest.len());
+        if word_len == 0 {
+            let separator_len = rest.chars().next().map_or(1, char::len_utf8);
+            redacted.push_str(&rest[..separator_len]);
+            rest = &rest[separator_len..];
+            continue;
+        }
+        let (word, remainder) = rest.split_at(word_len);
+        redacted.push_str(if looks_like_wif(word) { REDACTED } else { word });
+        rest = remainder;
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 秘密鍵 1 のテストネットWIF
+    const WIF: &str = "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA";
+
+    #[test]
+    fn redacted_debug_and_display_hide_the_value() {
+        let wif = Redacted(WIF.to_string());
+        assert_eq!(format!("{:?}", wif), REDACTED);
+        assert_eq!(format!("{}", wif), REDACTED);
+        // 構造体ごと {:?} で出力しても中身は含まれない
+        let nested = format!("{:?}", Some(&wif));
+        assert!(!nested.contains(WIF), "{}", nested);
+        assert_eq!(*wif, WIF);
+    }
+
+    #[test]
+    fn redact_wifs_replaces_a_real_wif() {
+        let message = format!("invalid type: string \"{}\", expected u64 at line 3", WIF);
+        let redacted = redact_wifs(&message);
+        assert!(!redacted.contains(WIF), "{}", redacted);
+        assert_eq!(redacted, format!("invalid type: string \"{}\", expected u64 at line 3", REDACTED));
+    }
+
+    #[test]
+    fn looks_like_wif_rejects_non_wif_words() {
+        assert!(looks_like_wif(WIF));
+        // 長さが合わない (1文字欠けたWIF)
+        assert!(!looks_like_wif(&WIF[1..]));
+        // 52文字だが圧縮WIFの先頭文字 (K/L/c) ではない
+        assert!(!looks_like_wif(&format!("x{}", &WIF[1..])));
+        // 16進数だけの語 (txidの一部など) はWIFとみなさない
+        assert!(!looks_like_wif(&"c".repeat(52)));
+        // アドレスは書き換えない
+        let address = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        assert_eq!(redact_wifs(address), address);
+    }
+}
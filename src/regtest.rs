@@ -0,0 +1,184 @@
+// bitcoind (regtest) を使った結合テスト。署名したトランザクションを sendrawtransaction に渡し、ノードに受理されることを確認する。
+// 接続先は環境変数で指定し、`regtest` フィーチャーを有効にして無視指定のテストとして実行する:
+//
+//   REGTEST_RPC_URL=http://127.0.0.1:18443 REGTEST_RPC_USER=user REGTEST_RPC_PASSWORD=pass \
+//     cargo test --features regtest -- --ignored regtest
+//
+// ユーザー名とパスワードの代わりに REGTEST_RPC_COOKIE に .cookie ファイルのパスを指定してもよい。
+// ウォレットは使わず、ブロック報酬をテスト用の鍵に送って資金にする。
+// Bitcoin Core 28 以降はベアマルチシグ出力の作成が既定で非標準のため、bitcoind を -permitbaremultisig=1 で起動する
+use std::env;
+use std::fs;
+
+use bitcoin::{
+    base64::{engine::general_purpose::STANDARD as BASE64, Engine},
+    blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_2},
+    consensus::encode,
+    key::{CompressedPublicKey, PrivateKey, XOnlyPublicKey},
+    network::Network as BitcoinNetwork,
+    secp256k1::{Secp256k1, SecretKey},
+    Address, KnownHrp, ScriptBuf, Transaction,
+};
+use serde_json::{json, Value};
+
+use crate::{config::InputConfig, transaction::{create_and_sign_transaction, KeyCache}, types::SigningOptions};
+
+// 各スクリプトタイプの出力に送る金額
+const FUNDING_VALUE_SATS: u64 = 1_000_000;
+
+struct Rpc {
+    url: String,
+    authorization: String,
+}
+
+impl Rpc {
+    fn from_env() -> Rpc {
+        let url = env::var("REGTEST_RPC_URL").expect("REGTEST_RPC_URL に bitcoind (regtest) のRPCのURLを指定してください");
+        let credentials = match env::var("REGTEST_RPC_COOKIE") {
+            Ok(cookie_path) => fs::read_to_string(&cookie_path)
+                .unwrap_or_else(|e| panic!("REGTEST_RPC_COOKIE {} を読み込めません: {}", cookie_path, e))
+                .trim()
+                .to_string(),
+            Err(_) => format!(
+                "{}:{}",
+                env::var("REGTEST_RPC_USER").expect("REGTEST_RPC_USER (または REGTEST_RPC_COOKIE) を指定してください"),
+                env::var("REGTEST_RPC_PASSWORD").expect("REGTEST_RPC_PASSWORD (または REGTEST_RPC_COOKIE) を指定してください"),
+            ),
+        };
+        Rpc { url, authorization: format!("Basic {}", BASE64.encode(credentials)) }
+    }
+
+    // RPCのエラーはHTTP 500で返るため、ステータスによらず本文の error を確認する
+    fn call(&self, method: &str, params: Value) -> Value {
+        let request = json!({"jsonrpc": "1.0", "id": "regtest", "method": method, "params": params});
+        let response = match ureq::post(&self.url)
+            .set("Authorization", &self.authorization)
+            .set("Content-Type", "application/json")
+            .send_string(&request.to_string())
+        {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("{} の呼び出しに失敗しました: {}", method, e),
+        };
+        let body: Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        if !body["error"].is_null() {
+            panic!("{} がエラーを返しました: {}", method, body["error"]);
+        }
+        body["result"].clone()
+    }
+
+    fn mine(&self, blocks: u32, address: &Address) -> Vec<String> {
+        let hashes = self.call("generatetoaddress", json!([blocks, address.to_string()]));
+        hashes.as_array().unwrap().iter().map(|hash| hash.as_str().unwrap().to_string()).collect()
+    }
+
+    // 署名済みトランザクションを送信し、ノードが返すtxidが手元で計算したものと一致することを確認する
+    fn send(&self, tx: &Transaction) {
+        let txid = self.call("sendrawtransaction", json!([encode::serialize_hex(tx)]));
+        assert_eq!(txid.as_str(), Some(tx.compute_txid().to_string().as_str()));
+    }
+}
+
+// 秘密鍵 n のWIF (テスト系のネットワークバイト) と圧縮公開鍵
+fn test_key(n: u8) -> (String, CompressedPublicKey) {
+    let mut secret = [0u8; 32];
+    secret[31] = n;
+    let private_key = PrivateKey::new(SecretKey::from_slice(&secret).unwrap(), BitcoinNetwork::Regtest);
+    let public_key = CompressedPublicKey::from_private_key(&Secp256k1::signing_only(), &private_key).unwrap();
+    (private_key.to_wif(), public_key)
+}
+
+fn sign(config: Value) -> Transaction {
+    let config: InputConfig = serde_json::from_value(config).unwrap();
+    create_and_sign_transaction(config, BitcoinNetwork::Regtest, &Secp256k1::new(), &mut KeyCache::default(), &SigningOptions::default(), |_| {})
+        .unwrap()
+        .transaction
+}
+
+#[test]
+#[ignore]
+fn regtest_each_script_type_is_accepted_by_sendrawtransaction() {
+    let rpc = Rpc::from_env();
+    let secp = Secp256k1::verification_only();
+    let (funder_wif, funder_key) = test_key(1);
+    let (spender_wif, spender_key) = test_key(2);
+    let (_, cosigner_key) = test_key(3);
+    let funder_address = Address::p2wpkh(&funder_key, KnownHrp::Regtest);
+
+    // ブロック報酬をテスト用の鍵の P2WPKH に送り、100ブロックの成熟を待つ
+    let block_hashes = rpc.mine(101, &funder_address);
+    let block = rpc.call("getblock", json!([block_hashes[0], 2]));
+    let coinbase = &block["tx"][0];
+    let funder_script_hex = funder_address.script_pubkey().to_hex_string();
+    let (vout, coinbase_output) = coinbase["vout"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .find(|(_, output)| output["scriptPubKey"]["hex"].as_str() == Some(funder_script_hex.as_str()))
+        .expect("ブロック報酬の出力が見つかりません");
+    let coinbase_value_sats = (coinbase_output["value"].as_f64().unwrap() * 100_000_000.0).round() as u64;
+
+    // 対応するスクリプトタイプごとの出力 (spender の鍵、ベアマルチシグは spender と cosigner の 1-of-2)
+    let spender_public_key = spender_key.to_string();
+    let bare_multisig_script = bitcoin::script::Builder::new()
+        .push_opcode(OP_PUSHNUM_1)
+        .push_key(&spender_key.into())
+        .push_key(&cosigner_key.into())
+        .push_opcode(OP_PUSHNUM_2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script();
+    let p2tr_script = ScriptBuf::new_p2tr(&secp, XOnlyPublicKey::from(spender_key.0), None);
+    let script_types = [
+        ("P2PKH", json!({"descriptor": format!("pkh({})", spender_public_key)})),
+        ("P2WPKH", json!({"descriptor": format!("wpkh({})", spender_public_key)})),
+        ("P2PK", json!({"payToPubkeyHex": spender_public_key})),
+        ("BareMultisig", json!({"descriptor": format!("raw({})", bare_multisig_script.to_hex_string())})),
+        ("P2TR", json!({"descriptor": format!("tr({})", spender_public_key)})),
+    ];
+    let outputs: Vec<Value> = script_types
+        .iter()
+        .map(|(label, output)| {
+            let mut output = output.clone();
+            output["valueSats"] = json!(FUNDING_VALUE_SATS);
+            output["label"] = json!(label);
+            output
+        })
+        .collect();
+    let funding = sign(json!({
+        "utxos": [{
+            "txid": coinbase["txid"], "vout": vout, "scriptPubkeyHex": funder_script_hex, "valueSats": coinbase_value_sats,
+            "privateKeyWif": funder_wif, "isCoinbase": true, "confirmations": 101,
+        }],
+        "outputs": outputs,
+        "feeRateSatsPerVb": 2,
+        "changeAddress": funder_address.to_string(),
+    }));
+    rpc.send(&funding);
+    rpc.mine(1, &funder_address);
+
+    // 各出力を1つずつ使うトランザクションに署名して送信する (入力の署名方式をスクリプトタイプごとに確認する)
+    for (vout, (label, _)) in script_types.iter().enumerate() {
+        let funded_output = &funding.output[vout];
+        let mut utxo = json!({
+            "txid": funding.compute_txid().to_string(), "vout": vout, "scriptPubkeyHex": funded_output.script_pubkey.to_hex_string(),
+            "valueSats": funded_output.value.to_sat(), "label": label,
+        });
+        if *label == "BareMultisig" {
+            assert_eq!(funded_output.script_pubkey, bare_multisig_script);
+            utxo["privateKeyWifs"] = json!([spender_wif]);
+        } else {
+            utxo["privateKeyWif"] = json!(spender_wif);
+        }
+        if *label == "P2TR" {
+            assert_eq!(funded_output.script_pubkey, p2tr_script);
+        }
+        let spend = sign(json!({
+            "utxos": [utxo],
+            "outputs": [{"address": funder_address.to_string(), "valueSats": FUNDING_VALUE_SATS / 2}],
+            "feeRateSatsPerVb": 2,
+            "changeAddress": funder_address.to_string(),
+        }));
+        rpc.send(&spend);
+    }
+    rpc.mine(1, &funder_address);
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use bitcoin::{
+    bech32::{primitives::decode::CheckedHrpstring, Bech32m},
+    consensus::encode,
+    hashes::{sha256, Hash, HashEngine},
+    key::TweakedPublicKey,
+    network::Network as BitcoinNetwork,
+    script::ScriptBuf,
+    secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey, Signing},
+};
+
+use crate::{error::AppError, types::{InputSigning, ProcessedUtxo, ScriptType}};
+
+fn silent_payment_error(reason: impl Into<String>) -> AppError {
+    AppError::SilentPayment(reason.into())
+}
+
+// BIP340 形式のタグ付きハッシュ: SHA256(SHA256(tag) || SHA256(tag) || msg)
+fn tagged_hash(tag: &str, msg: &[u8]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_ref());
+    engine.input(tag_hash.as_ref());
+    engine.input(msg);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn expected_hrp(network: BitcoinNetwork) -> &'static str {
+    match network {
+        BitcoinNetwork::Bitcoin => "sp",
+        BitcoinNetwork::Regtest => "sprt",
+        _ => "tsp",
+    }
+}
+
+// サイレントペイメントアドレスを (B_scan, B_spend) にデコードする
+fn decode_address(address: &str, network: BitcoinNetwork) -> Result<(PublicKey, PublicKey), AppError> {
+    // 通常のbech32mの90文字制限を超えるため、長さ制限のないデコーダを使う
+    let mut checked = CheckedHrpstring::new::<Bech32m>(address)
+        .map_err(|e| silent_payment_error(format!("アドレス {} のデコード失敗: {}", address, e)))?;
+    if checked.hrp().as_str() != expected_hrp(network) {
+        return Err(silent_payment_error(format!(
+            "アドレス {} のHRP {} がネットワーク {:?} ({}) と一致しません", address, checked.hrp(), network, expected_hrp(network))));
+    }
+    let version = checked
+        .remove_witness_version()
+        .ok_or_else(|| silent_payment_error(format!("アドレス {} にバージョンがありません", address)))?
+        .to_u8();
+    let payload: Vec<u8> = checked.byte_iter().collect();
+    // v0 はちょうど66バイト。v1-v30 は前方互換のため先頭66バイトのみを使う。v31 は無効
+    let valid_length = match version {
+        0 => payload.len() == 66,
+        1..=30 => payload.len() >= 66,
+        _ => false,
+    };
+    if !valid_length {
+        return Err(silent_payment_error(format!("アドレス {} のバージョン {} とペイロード長 {} が不正です", address, version, payload.len())));
+    }
+    let scan = PublicKey::from_slice(&payload[..33])
+        .map_err(|e| silent_payment_error(format!("スキャン公開鍵が不正です ({}): {}", address, e)))?;
+    let spend = PublicKey::from_slice(&payload[33..66])
+        .map_err(|e| silent_payment_error(format!("支払い公開鍵が不正です ({}): {}", address, e)))?;
+    Ok((scan, spend))
+}
+
+/// 受信者のサイレントペイメントアドレス (出現順) から、各出力のP2TR scriptPubKeyを導出する (BIP352)。
+///
+/// 送金者側の計算には、適格な入力 (P2PKH/P2WPKH の圧縮公開鍵) の秘密鍵の合計が必要になる。
+/// 非圧縮鍵のP2PKHやP2PK入力は鍵の合計から除外されるが、最小アウトポイントの選択には全入力が使われる。
+/// 秘密鍵のない (skipSigning など) P2PKH/P2WPKH入力を含む場合はエラーになる。
+pub fn derive_silent_payment_scripts<C: Signing>(
+    addresses: &[&str],
+    inputs: &[ProcessedUtxo],
+    network: BitcoinNetwork,
+    secp: &Secp256k1<C>,
+) -> Result<Vec<ScriptBuf>, AppError> {
+    let mut eligible_keys: Vec<SecretKey> = Vec::new();
+    // BIP352 では P2TR 入力の鍵も合計に含める (出力鍵のY座標に応じて符号を反転する) が、この実装は対応していない。
+    // 除外して導出すると受信者が出力を見つけられないため、エラーにする
+    if let Some(pu) = inputs.iter().find(|pu| pu.script_type == ScriptType::P2TR) {
+        return Err(silent_payment_error(format!("P2TR の入力 {} を含むトランザクションではサイレントペイメント出力を導出できません", pu.out_point)));
+    }
+    for pu in inputs.iter().filter(|pu| matches!(pu.script_type, ScriptType::P2PKH | ScriptType::P2WPKH)) {
+        match &pu.signing {
+            InputSigning::Key { private_key, .. } if private_key.compressed => eligible_keys.push(private_key.inner),
+            InputSigning::Key { .. } => {}
+            // 秘密鍵を持たない入力は共有秘密の計算に使えない
+            InputSigning::Multisig { .. } | InputSigning::External { .. } | InputSigning::Presigned { .. } => {
+                return Err(silent_payment_error(format!("入力 {} の秘密鍵がないためサイレントペイメント出力を導出できません", pu.out_point)));
+            }
+        }
+    }
+    let (first_key, rest_keys) = eligible_keys
+        .split_first()
+        .ok_or_else(|| silent_payment_error("サイレントペイメントには圧縮公開鍵のP2PKH/P2WPKH入力が1つ以上必要です"))?;
+    let mut input_key_sum = *first_key;
+    for key in rest_keys {
+        input_key_sum = input_key_sum
+            .add_tweak(&Scalar::from(*key))
+            .map_err(|_| silent_payment_error("入力秘密鍵の合計が0になりました"))?;
+    }
+    let input_pubkey_sum = input_key_sum.public_key(secp);
+
+    let smallest_outpoint = inputs
+        .iter()
+        .map(|pu| encode::serialize(&pu.out_point))
+        .min()
+        .ok_or_else(|| silent_payment_error("入力がありません"))?;
+    let mut input_hash_msg = smallest_outpoint;
+    input_hash_msg.extend_from_slice(&input_pubkey_sum.serialize());
+    let input_hash = Scalar::from_be_bytes(tagged_hash("BIP0352/Inputs", &input_hash_msg))
+        .map_err(|_| silent_payment_error("input_hash が曲線の位数以上です"))?;
+    let tweaked_input_key = input_key_sum
+        .mul_tweak(&input_hash)
+        .map_err(|_| silent_payment_error("input_hash による秘密鍵の調整に失敗しました"))?;
+
+    // 公開鍵の調整には検証用コンテキストが必要 (呼び出し元は署名専用コンテキストの場合がある)
+    let verification_secp = Secp256k1::verification_only();
+    // 同じスキャン鍵への出力には k = 0, 1, ... を出現順に割り当てる
+    let mut next_k: HashMap<PublicKey, u32> = HashMap::new();
+    let mut scripts = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let (scan_key, spend_key) = decode_address(address, network)?;
+        let shared_secret = scan_key
+            .mul_tweak(&verification_secp, &Scalar::from(tweaked_input_key))
+            .map_err(|_| silent_payment_error("共有秘密の計算に失敗しました"))?;
+        let k = next_k.entry(scan_key).or_insert(0);
+        let mut t_msg = shared_secret.serialize().to_vec();
+        t_msg.extend_from_slice(&k.to_be_bytes());
+        *k += 1;
+        let t_k = Scalar::from_be_bytes(tagged_hash("BIP0352/SharedSecret", &t_msg))
+            .map_err(|_| silent_payment_error("t_k が曲線の位数以上です"))?;
+        let output_key = spend_key
+            .add_exp_tweak(&verification_secp, &t_k)
+            .map_err(|_| silent_payment_error("出力公開鍵の導出に失敗しました"))?;
+        // 導出した鍵をそのまま (Taprootの追加調整なしで) 出力鍵として使う
+        let (x_only, _parity) = output_key.x_only_public_key();
+        scripts.push(ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(x_only)));
+    }
+    Ok(scripts)
+}
@@ -1,21 +1,44 @@
 use bitcoin::{
-    absolute::LockTime, network::Network as BitcoinNetwork, 
-    script::{Builder, PushBytesBuf, ScriptBuf}, 
-    opcodes::all::*,
-    secp256k1::{All, Message, Secp256k1}, 
-    sighash::{EcdsaSighashType, SighashCache}, 
-    Address, Amount, OutPoint, PrivateKey, PublicKey, Sequence, Transaction, TxIn, TxOut, Txid, 
+    absolute::LockTime, network::Network as BitcoinNetwork, opcodes::OP_0,
+    script::{PushBytesBuf, ScriptBuf}, 
+    key::{Keypair, TapTweak, XOnlyPublicKey},
+    secp256k1::{self, Message, Secp256k1, Signing},
+    sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType}, 
+    Address, Amount, OutPoint, PrivateKey, PublicKey, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
 };
+use std::collections::HashMap;
+use std::fmt;
 use std::str::FromStr;
+use std::time::Instant;
 
 use crate::{
-    config::InputConfig,
-    error::{AppError, },
-    types::{ProcessedUtxo, ScriptType}, 
+    config::{ChangeAddressDef, ChangeDustPolicy, InputConfig, InputOrder, TransactionOutputDef, UtxoInput},
+    descriptor::descriptor_to_script_pubkey,
+    fee_rate::FeeRate,
+    psbt::parse_bip32_derivation,
+    redact::Redacted,
+    error::{field_error, label_suffix, AppError},
+    types::{bare_multisig_keys, InputSigning, ProcessedUtxo, SafeModePolicy, ScriptType, SignatureScheme, SignedInput, SignedTransaction, SigningOptions, SigningProgress, SigningWarning}, 
 };
 
-// Bitcoin Coreのデフォルトダスト閾値 (P2PKH/P2WPKH出力に対して)
-const DUST_THRESHOLD_SATS: u64 = 546;
+// Bitcoin Coreの既定のダストリレー手数料率 (-dustrelayfee)。P2PKH出力で 546 sats、P2TR出力で 330 sats になる
+const DEFAULT_DUST_RELAY_FEE_SATS_PER_KVB: u64 = 3000;
+
+// Bitcoin Coreのデフォルト最低リレー手数料率
+pub(crate) const MIN_RELAY_FEE_RATE_SATS_PER_VB: u64 = 1;
+const MIN_RELAY_FEE_RATE: FeeRate = FeeRate::from_sat_per_vb(MIN_RELAY_FEE_RATE_SATS_PER_VB);
+
+// 総供給量 (21,000,000 BTC) をsatsで表した上限。これを超える金額は入力として受け付けない
+const MAX_MONEY_SATS: u64 = 21_000_000 * 100_000_000;
+
+// 標準トランザクションの最大ウェイト (Bitcoin Core の MAX_STANDARD_TX_WEIGHT)
+pub(crate) const MAX_STANDARD_TX_WEIGHT_WU: u64 = 400_000;
+
+// 作成するトランザクションのバージョン。ノードは 1, 2 以外をリレーしないため (標準ルール)、設定では変更できない
+const TX_VERSION: bitcoin::transaction::Version = bitcoin::transaction::Version::TWO;
+
+// coinbase出力が使用可能になるまでの確認数 (COINBASE_MATURITY)
+const COINBASE_MATURITY: u32 = 100;
 
 // トランザクションサイズの推定に使用するダミーデータ
 const DUMMY_SIGNATURE_LEN: usize = 72;
@@ -24,156 +47,1497 @@ const DUMMY_SIGNATURE_LEN: usize = 72;
 struct SigningInfo {
     input_index: usize,
     sighash_message: Message,
-    private_key: PrivateKey, // bitcoin::PrivateKey は Clone を実装
+    private_key: Redacted<PrivateKey>, // ログやエラーに出力されないよう Redacted で保持する
     public_key: PublicKey,   // bitcoin::PublicKey は Copy (かつ Clone) を実装
     script_type: ScriptType, // ScriptType が Copy または Clone を実装している必要あり
+    sighash_type: EcdsaSighashType,
+    label: Option<String>,
 }
 
-pub fn create_and_sign_transaction(
-    config: InputConfig,
+// address / descriptor のどちらか一方から出力の scriptPubKey を解決する
+pub(crate) fn resolve_output_script(
+    address: Option<&str>,
+    descriptor: Option<&str>,
+    network: BitcoinNetwork,
+) -> Result<ScriptBuf, AppError> {
+    match (address, descriptor) {
+        (Some(addr), None) => Address::from_str(addr)
+            .and_then(|a| a.require_network(network))
+            .map(|a| a.script_pubkey())
+            .map_err(|e| AppError::InputValidation(format!("アドレス形式エラーまたはネットワーク不整合 ({}): {}", addr, e))),
+        (None, Some(desc)) => descriptor_to_script_pubkey(desc, network),
+        (Some(_), Some(_)) => Err(AppError::InputValidation("address と descriptor は同時に指定できません".to_string())),
+        (None, None) => Err(AppError::InputValidation("address か descriptor のどちらかを指定してください".to_string())),
+    }
+}
+
+// payToPubkeyHex から P2PK の scriptPubKey (<pubkey> OP_CHECKSIG) を作る。圧縮・非圧縮どちらの公開鍵も受け付ける
+fn pay_to_pubkey_script(pubkey_hex: &str) -> Result<ScriptBuf, AppError> {
+    let public_key = PublicKey::from_str(pubkey_hex)
+        .map_err(|e| AppError::InputValidation(format!("payToPubkeyHex が有効な公開鍵ではありません ({}): {}", pubkey_hex, e)))?;
+    Ok(bitcoin::script::Builder::new()
+        .push_key(&public_key)
+        .push_opcode(bitcoin::opcodes::all::OP_CHECKSIG)
+        .into_script())
+}
+
+// 出力の送金先を指定するフィールド名 (エラーの位置の表示用)。未指定の場合は address とする
+fn output_def_field(output_def: &TransactionOutputDef) -> &'static str {
+    if output_def.pay_to_pubkey_hex.is_some() {
+        "payToPubkeyHex"
+    } else if output_def.descriptor.is_some() && output_def.address.is_none() {
+        "descriptor"
+    } else {
+        "address"
+    }
+}
+
+// 受信者出力・追加出力の scriptPubKey を payToPubkeyHex / address / descriptor のいずれか1つから解決する
+pub(crate) fn resolve_output_def_script(output_def: &TransactionOutputDef, network: BitcoinNetwork) -> Result<ScriptBuf, AppError> {
+    match &output_def.pay_to_pubkey_hex {
+        Some(_) if output_def.address.is_some() || output_def.descriptor.is_some() => Err(AppError::InputValidation(
+            "payToPubkeyHex は address / descriptor と同時に指定できません".to_string())),
+        Some(pubkey_hex) => pay_to_pubkey_script(pubkey_hex),
+        None => resolve_output_script(output_def.address.as_deref(), output_def.descriptor.as_deref(), network),
+    }
+}
+
+// --allowlist-file の指定時、受信者出力の送金先が許可リストにあることを確認する
+fn check_recipient_allowed(
+    options: &SigningOptions,
+    script_pubkey: &ScriptBuf,
+    network: BitcoinNetwork,
+    context: impl FnOnce() -> String,
+) -> Result<(), AppError> {
+    match &options.recipient_allowlist {
+        Some(allowlist) if !allowlist.contains(script_pubkey) => Err(AppError::RecipientNotAllowed {
+            context: context(),
+            destination: Address::from_script(script_pubkey, network)
+                .map(|address| address.to_string())
+                .unwrap_or_else(|_| script_pubkey.to_string()),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// 金額が総供給量以下であることを確認し、累計にオーバーフロー検査付きで加算する
+fn checked_add_amount(total: u64, value_sats: u64, context: &str) -> Result<u64, AppError> {
+    if value_sats > MAX_MONEY_SATS {
+        return Err(AppError::InputValidation(format!(
+            "{}の金額 {} sats が総供給量 {} sats を超えています", context, value_sats, MAX_MONEY_SATS)));
+    }
+    total
+        .checked_add(value_sats)
+        .filter(|sum| *sum <= MAX_MONEY_SATS)
+        .ok_or_else(|| AppError::InputValidation(format!(
+            "{}の合計金額がオーバーフローまたは総供給量を超えました (現在の合計 {} sats + {} sats)", context, total, value_sats)))
+}
+
+// "SIGHASH_SINGLE|SIGHASH_ANYONECANPAY" や "single|anyonecanpay" のような表記を受け付ける
+fn parse_sighash_type(name: &str, field_path: &str) -> Result<EcdsaSighashType, AppError> {
+    let normalized = name
+        .split('|')
+        .map(|part| {
+            let part = part.trim().to_uppercase();
+            if part.starts_with("SIGHASH_") { part } else { format!("SIGHASH_{}", part) }
+        })
+        .collect::<Vec<_>>()
+        .join("|");
+    // rust-bitcoin のエラーは大文字に正規化した値を含み、出力時にWIFとして伏せられないため元の表記のみ表示する
+    EcdsaSighashType::from_str(&normalized).map_err(|_| field_error(field_path, "sighashType", format!(
+        "無効なsighashタイプ ({}): ALL / NONE / SINGLE (それぞれ |ANYONECANPAY を付けられます) のいずれかを指定してください", name)))
+}
+
+// lockTimeHeight / lockTimeTimestamp から nLockTime を構築する。500,000,000 を境に解釈が変わるため、範囲外の値はエラーにする
+fn resolve_lock_time(height: Option<u32>, timestamp: Option<u32>) -> Result<LockTime, AppError> {
+    match (height, timestamp) {
+        (None, None) => Ok(LockTime::ZERO),
+        (Some(height), None) => LockTime::from_height(height)
+            .map_err(|e| field_error("", "lockTimeHeight", format!("{} はブロック高として無効です: {}", height, e))),
+        (None, Some(timestamp)) => LockTime::from_time(timestamp)
+            .map_err(|e| field_error("", "lockTimeTimestamp", format!("{} はタイムスタンプとして無効です: {}", timestamp, e))),
+        (Some(_), Some(_)) => Err(field_error("", "lockTimeTimestamp", "lockTimeHeight と同時に指定できません")),
+    }
+}
+
+fn describe_lock_time(lock_time: LockTime) -> String {
+    match lock_time {
+        LockTime::Blocks(height) => format!("ブロック高 {}", height),
+        LockTime::Seconds(time) => format!("タイムスタンプ {}", time),
+    }
+}
+
+// 入力の requiresLocktime (OP_CHECKLOCKTIMEVERIFY) をすべて満たすよう、設定の nLockTime を必要なら引き上げる。
+// 比較できるのは同じ単位 (ブロック高・タイムスタンプ) どうしのみ
+fn apply_required_lock_times(configured: LockTime, processed_utxos: &[ProcessedUtxo]) -> Result<LockTime, AppError> {
+    // 未指定 (0) の nLockTime は制約にならない
+    let mut current = (configured != LockTime::ZERO).then(|| (configured, "設定の nLockTime".to_string()));
+    for pu in processed_utxos {
+        let Some(required) = pu.required_lock_time else {
+            continue;
+        };
+        let source = format!("UTXO {}{} の requiresLocktime", pu.out_point, label_suffix(pu.label.as_deref()));
+        match &current {
+            Some((lock_time, current_source)) if !lock_time.is_same_unit(required) => {
+                return Err(AppError::IncompatibleLockTimes {
+                    first: format!("{} ({})", current_source, describe_lock_time(*lock_time)),
+                    second: format!("{} ({})", source, describe_lock_time(required)),
+                });
+            }
+            Some((lock_time, _)) if lock_time.to_consensus_u32() >= required.to_consensus_u32() => {}
+            _ => current = Some((required, source)),
+        }
+    }
+    let lock_time = current.map_or(LockTime::ZERO, |(lock_time, source)| {
+        if lock_time != configured {
+            log::info!("nLockTime を {} にします ({})。", describe_lock_time(lock_time), source);
+        }
+        lock_time
+    });
+    Ok(lock_time)
+}
+
+// 警告をログに出力し、呼び出し元へ返すために記録する
+fn record_warning(warnings: &mut Vec<SigningWarning>, warning: SigningWarning) {
+    log::warn!("{}", warning);
+    warnings.push(warning);
+}
+
+// 通常は警告を記録して続行し、strictモードではエラーとして中断する
+fn warn_or_fail(options: &SigningOptions, warnings: &mut Vec<SigningWarning>, warning: SigningWarning) -> Result<(), AppError> {
+    if options.strict {
+        return Err(AppError::StrictModeViolation(warning.to_string()));
+    }
+    record_warning(warnings, warning);
+    Ok(())
+}
+
+// 手数料計算のため、ダミー署名と公開鍵のサイズを script_sig/witness に反映した TxIn を作る
+// skipSigning の入力は署名済みの script_sig / witness をそのまま使う
+fn dummy_signed_txin(pu: &ProcessedUtxo) -> TxIn {
+    let mut tx_in = TxIn {
+        previous_output: pu.out_point,
+        script_sig: ScriptBuf::new(), // 手数料計算時は空の script_sig
+        sequence: pu.sequence,
+        witness: bitcoin::Witness::new(), // 手数料計算時は空の witness
+    };
+    let public_key = match &pu.signing {
+        InputSigning::Key { public_key, .. } | InputSigning::External { public_key } => public_key,
+        // ベアマルチシグの script_sig に公開鍵は含まれない
+        InputSigning::Multisig { keys } => match keys.first() {
+            Some((_, public_key)) => public_key,
+            None => return tx_in,
+        },
+        InputSigning::Presigned { script_sig, witness } => {
+            tx_in.script_sig = script_sig.clone();
+            tx_in.witness = witness.clone();
+            return tx_in;
+        }
+    };
+    push_dummy_signature(&mut tx_in, pu.script_type, public_key);
+    tx_in
+}
+
+fn push_dummy_signature(tx_in: &mut TxIn, script_type: ScriptType, public_key: &PublicKey) {
+    match script_type {
+        ScriptType::P2PKH => {
+            tx_in.script_sig = bitcoin::script::Builder::new()
+                .push_slice([0u8; DUMMY_SIGNATURE_LEN])
+                .push_key(public_key)
+                .into_script();
+        }
+        ScriptType::P2WPKH => {
+            tx_in.witness.push(vec![0u8; DUMMY_SIGNATURE_LEN]);
+            tx_in.witness.push(public_key.to_bytes());
+        }
+        ScriptType::P2PK => {
+            tx_in.script_sig = bitcoin::script::Builder::new()
+                .push_slice([0u8; DUMMY_SIGNATURE_LEN])
+                .into_script();
+        }
+        // SIGHASH_DEFAULT のSchnorr署名は sighash type のバイトを付けない64バイト固定
+        ScriptType::P2TR => tx_in.witness.push([0u8; 64]),
+        ScriptType::BareMultisig { required, .. } => {
+            // OP_CHECKMULTISIG が余分に1要素ポップするため先頭に OP_0 を置く
+            let mut builder = bitcoin::script::Builder::new().push_opcode(OP_0);
+            for _ in 0..required {
+                builder = builder.push_slice([0u8; DUMMY_SIGNATURE_LEN]);
+            }
+            tx_in.script_sig = builder.into_script();
+        }
+        // 署名できないため skipSigning (上で処理済み) 以外では process_utxo_input で拒否される
+        ScriptType::UnknownWitness { .. } => {}
+    }
+}
+
+/// 署名済みトランザクションに、指定タイプの典型的な入力 (圧縮公開鍵) を count 個追加した場合のvsizeを見積もる (計画用)。
+pub fn projected_vsize_with_extra_inputs(tx: &Transaction, count: usize, script_type: ScriptType) -> Result<u64, AppError> {
+    // サイズの見積もりのみに使うため、鍵は任意の有効な圧縮公開鍵でよい (secp256k1 の生成元)
+    let mut generator = [0u8; 33];
+    generator[0] = 0x02;
+    generator[1..].copy_from_slice(&secp256k1::constants::GENERATOR_X);
+    let public_key = PublicKey::from_slice(&generator)
+        .map_err(|e| AppError::Internal(format!("見積もり用の公開鍵の作成に失敗しました: {}", e)))?;
+    let mut projected = tx.clone();
+    for _ in 0..count {
+        let mut tx_in = TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+        push_dummy_signature(&mut tx_in, script_type, &public_key);
+        projected.input.push(tx_in);
+    }
+    Ok(transaction_vsize(&projected))
+}
+
+// ウェイト (WU) からvsizeへの変換。Bitcoin Core の GetVirtualTransactionSize と同じ (weight + 3) / 4 の切り上げ
+// (シグオプ数による補正は標準的なP2PKH/P2WPKHでは効かないため扱わない)。手数料計算のvsizeはすべてここを通す
+pub(crate) fn vsize_from_weight(weight_wu: u64) -> u64 {
+    weight_wu.div_ceil(4)
+}
+
+pub(crate) fn transaction_vsize(tx: &Transaction) -> u64 {
+    vsize_from_weight(tx.weight().to_wu())
+}
+
+// 1入力を追加することで増えるvsize (署名済みを想定、vB単位で切り上げ)
+pub(crate) fn estimated_input_vsize(pu: &ProcessedUtxo) -> u64 {
+    vsize_from_weight(dummy_signed_txin(pu).segwit_weight().to_wu())
+}
+
+// Bitcoin Core の GetDustThreshold と同じ計算: (出力のサイズ + その出力を使う入力のサイズ) × ダストリレー手数料率。
+// 入力のサイズはwitnessプログラムなら 67 vB、それ以外は 148 B (P2WPKH/P2PKHの典型値)。OP_RETURN は常に 0
+pub(crate) fn dust_threshold_sats(script_pubkey: &ScriptBuf, dust_relay_fee_sats_per_kvb: u64) -> u64 {
+    if script_pubkey.is_op_return() {
+        return 0;
+    }
+    let output_size = TxOut { value: Amount::ZERO, script_pubkey: script_pubkey.clone() }.size() as u64;
+    let spend_size: u64 = if script_pubkey.is_witness_program() { 32 + 4 + 1 + 107 / 4 + 4 } else { 32 + 4 + 1 + 107 + 4 };
+    (output_size + spend_size) * dust_relay_fee_sats_per_kvb / 1000
+}
+
+// prevTxHex をデコードし、txidと参照先の出力 (金額・scriptPubKey) が設定と一致することを確認する
+fn decode_previous_transaction(prev_tx_hex: &str, out_point: OutPoint, tx_out: &TxOut, field_path: &str) -> Result<Transaction, AppError> {
+    let bytes = hex::decode(prev_tx_hex)
+        .map_err(|e| field_error(field_path, "prevTxHex", format!("デコード失敗: {}", e)))?;
+    let previous_transaction: Transaction = bitcoin::consensus::encode::deserialize(&bytes)?;
+    let previous_txid = previous_transaction.compute_txid();
+    if previous_txid != out_point.txid {
+        return Err(field_error(field_path, "prevTxHex", format!("txid ({}) が {} と一致しません", previous_txid, out_point.txid)));
+    }
+    let referenced_output = previous_transaction.output.get(out_point.vout as usize).ok_or_else(|| field_error(field_path, "prevTxHex", format!(
+        "出力 {} がありません (出力数 {})", out_point.vout, previous_transaction.output.len())))?;
+    if referenced_output != tx_out {
+        return Err(field_error(field_path, "prevTxHex", format!(
+            "出力 ({} sats, {}) が設定の valueSats / scriptPubKey と一致しません",
+            referenced_output.value.to_sat(), referenced_output.script_pubkey)));
+    }
+    Ok(previous_transaction)
+}
+
+// skipSigning の入力の署名済み script_sig / witness をデコードする
+fn decode_presigned_input(utxo_input: &UtxoInput, field_path: &str) -> Result<InputSigning, AppError> {
+    if utxo_input.script_sig_hex.is_none() && utxo_input.witness_hex.is_empty() {
+        return Err(field_error(field_path, "skipSigning", "scriptSigHex / witnessHex が指定されていません"));
+    }
+    let script_sig = match &utxo_input.script_sig_hex {
+        Some(script_sig_hex) => ScriptBuf::from_bytes(hex::decode(script_sig_hex)
+            .map_err(|e| field_error(field_path, "scriptSigHex", format!("デコード失敗: {}", e)))?),
+        None => ScriptBuf::new(),
+    };
+    let witness_items = utxo_input
+        .witness_hex
+        .iter()
+        .enumerate()
+        .map(|(item_index, item)| hex::decode(item)
+            .map_err(|e| field_error(field_path, &format!("witnessHex[{}]", item_index), format!("デコード失敗: {}", e))))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(InputSigning::Presigned { script_sig, witness: Witness::from_slice(&witness_items) })
+}
+
+// compute-sighashes で秘密鍵のない入力の公開鍵を決定し、scriptPubKey と対応することを確認する
+fn external_signer_input(
+    utxo_input: &UtxoInput,
+    script_pubkey: &ScriptBuf,
+    script_type: ScriptType,
+    field_path: &str,
+) -> Result<InputSigning, AppError> {
+    // 鍵パスの署名には内部鍵を調整 (tweak) した秘密鍵が必要で、署名ハッシュだけを渡しても外部で署名できない
+    if script_type == ScriptType::P2TR {
+        return Err(field_error(field_path, "publicKeyHex", "P2TR の入力は compute-sighashes に対応していません (privateKeyWif を指定してください)"));
+    }
+    let public_key = match (&utxo_input.public_key_hex, script_type) {
+        (Some(public_key_hex), _) => PublicKey::from_str(public_key_hex)
+            .map_err(|e| field_error(field_path, "publicKeyHex", format!("パース失敗: {}", e)))?,
+        (None, ScriptType::P2PK) => script_pubkey
+            .p2pk_public_key()
+            .ok_or_else(|| AppError::Internal(format!("{} のP2PK scriptPubKeyから公開鍵を取得できません", field_path)))?,
+        (None, _) => {
+            return Err(field_error(field_path, "publicKeyHex", "privateKeyWif か publicKeyHex のどちらかが必要です"));
+        }
+    };
+    let matches_script = match script_type {
+        ScriptType::P2PKH => *script_pubkey == ScriptBuf::new_p2pkh(&public_key.pubkey_hash()),
+        ScriptType::P2WPKH => public_key.wpubkey_hash().is_ok_and(|hash| *script_pubkey == ScriptBuf::new_p2wpkh(&hash)),
+        ScriptType::P2PK => script_pubkey.p2pk_public_key() == Some(public_key),
+        ScriptType::BareMultisig { .. } => bare_multisig_keys(script_pubkey).is_some_and(|(_, keys)| keys.contains(&public_key)),
+        ScriptType::P2TR | ScriptType::UnknownWitness { .. } => false,
+    };
+    if !matches_script {
+        return Err(field_error(field_path, "publicKeyHex", format!(
+            "公開鍵 {} が scriptPubKey ({:?}) と対応しません", public_key, script_type)));
+    }
+    Ok(InputSigning::External { public_key })
+}
+
+// 署名するはずの入力 (skipSigning・--sign-inputs の対象外・署名ハッシュのみの場合を除く) に、SegWitなら witness、
+// レガシーなら script_sig が入っていることを確認する。署名の分岐が漏れる不具合で未署名の入力を出力しないための不変条件
+fn check_inputs_signed(transaction: &Transaction, processed_utxos: &[ProcessedUtxo], options: &SigningOptions) -> Result<(), AppError> {
+    if options.sighash_only {
+        return Ok(());
+    }
+    for (input_index, (tx_in, pu)) in transaction.input.iter().zip(processed_utxos).enumerate() {
+        let should_sign = matches!(pu.signing, InputSigning::Key { .. } | InputSigning::Multisig { .. })
+            && options.sign_inputs.as_ref().is_none_or(|indices| indices.contains(&input_index));
+        if !should_sign {
+            continue;
+        }
+        let (location, is_empty) = match pu.script_type {
+            ScriptType::P2WPKH | ScriptType::P2TR | ScriptType::UnknownWitness { .. } => ("witness", tx_in.witness.is_empty()),
+            ScriptType::P2PKH | ScriptType::P2PK | ScriptType::BareMultisig { .. } => ("script_sig", tx_in.script_sig.is_empty()),
+        };
+        if is_empty {
+            return Err(AppError::MissingSignature {
+                input_index,
+                label: pu.label.clone(),
+                script_type: format!("{:?}", pu.script_type),
+                location,
+            });
+        }
+    }
+    Ok(())
+}
+
+// 署名した入力の witness / script_sig の組み立てを確認する。ブロードキャスト前に組み立ての誤りや
+// 第三者が書き換え可能な形 (high-S の署名など) を検出する。skipSigning・未署名の入力は対象外
+fn verify_witnesses(transaction: &Transaction, processed_utxos: &[ProcessedUtxo], signed_inputs: &[SignedInput]) -> Result<(), AppError> {
+    for (input_index, ((tx_in, pu), signed_input)) in transaction.input.iter().zip(processed_utxos).zip(signed_inputs).enumerate() {
+        if signed_input.signature.is_none() && signed_input.taproot_signature.is_none() {
+            continue;
+        }
+        let malformed = |reason: String| AppError::MalformedWitness { input_index, reason };
+        match pu.script_type {
+            ScriptType::P2WPKH => {
+                let witness = &tx_in.witness;
+                if !tx_in.script_sig.is_empty() {
+                    return Err(malformed("P2WPKH の script_sig が空ではありません".to_string()));
+                }
+                if witness.len() != 2 {
+                    return Err(malformed(format!("P2WPKH のwitnessの要素数が {} です (署名と公開鍵の2つである必要があります)", witness.len())));
+                }
+                let signature = bitcoin::ecdsa::Signature::from_slice(&witness[0])
+                    .map_err(|e| malformed(format!("署名がDER形式として不正です: {}", e)))?;
+                if signature.sighash_type != pu.sighash_type {
+                    return Err(malformed(format!("署名の sighash type {} が宣言された {} と一致しません", signature.sighash_type, pu.sighash_type)));
+                }
+                let mut normalized = signature.signature;
+                normalized.normalize_s();
+                if normalized != signature.signature {
+                    return Err(malformed("署名が low-S ではありません (第三者が書き換え可能です)".to_string()));
+                }
+                let public_key_bytes = &witness[1];
+                if public_key_bytes.len() != 33 {
+                    return Err(malformed(format!("公開鍵が {} バイトです (圧縮公開鍵の33バイトである必要があります)", public_key_bytes.len())));
+                }
+                if PublicKey::from_slice(public_key_bytes).ok() != signed_input.public_key {
+                    return Err(malformed("witnessの公開鍵が署名に使った鍵と一致しません".to_string()));
+                }
+            }
+            ScriptType::P2TR => {
+                if !tx_in.script_sig.is_empty() {
+                    return Err(malformed("P2TR の script_sig が空ではありません".to_string()));
+                }
+                // 鍵パスの witness は署名1つのみ (annex なし)
+                if tx_in.witness.len() != 1 {
+                    return Err(malformed(format!("P2TR のwitnessの要素数が {} です (署名1つである必要があります)", tx_in.witness.len())));
+                }
+                let signature = bitcoin::taproot::Signature::from_slice(&tx_in.witness[0])
+                    .map_err(|e| malformed(format!("Schnorr署名として不正です: {}", e)))?;
+                if signature.sighash_type != TapSighashType::Default {
+                    return Err(malformed(format!("署名の sighash type {} が SIGHASH_DEFAULT ではありません", signature.sighash_type)));
+                }
+            }
+            // レガシー入力に witness があるトランザクションはコンセンサスで無効になる
+            ScriptType::P2PKH | ScriptType::P2PK | ScriptType::BareMultisig { .. } if !tx_in.witness.is_empty() => {
+                return Err(malformed(format!("{:?} の入力に witness があります", pu.script_type)));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// ベアマルチシグの privateKeyWifs を検証する。OP_CHECKMULTISIG は署名を公開鍵の順に照合するため、
+// WIFは必要署名数と同じ数を scriptPubKey の公開鍵と同じ順で指定する必要がある
+fn multisig_signing_keys<C: Signing>(
+    utxo_input: &UtxoInput,
+    script_pubkey: &ScriptBuf,
     cli_network: BitcoinNetwork,
-    secp: &Secp256k1<All>,
-) -> Result<Transaction, AppError> {
-    log::info!("トランザクション構築処理を開始します。");
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+    field_path: &str,
+) -> Result<InputSigning, AppError> {
+    let (required, script_keys) = bare_multisig_keys(script_pubkey)
+        .ok_or_else(|| AppError::UnknownScriptType { script_hex: script_pubkey.to_hex_string() })?;
+    if utxo_input.private_key_wifs.len() != usize::from(required) {
+        return Err(field_error(field_path, "privateKeyWifs", format!(
+            "{} 個指定されていますが、{}-of-{} のため {} 個必要です",
+            utxo_input.private_key_wifs.len(), required, script_keys.len(), required)));
+    }
+    let mut keys = Vec::with_capacity(utxo_input.private_key_wifs.len());
+    let mut next_position = 0;
+    for (wif_index, wif) in utxo_input.private_key_wifs.iter().enumerate() {
+        let wif_field = format!("privateKeyWifs[{}]", wif_index);
+        let (private_key, inner_public_key) = key_cache.get_or_derive(wif, secp)?;
+        if private_key.network != cli_network.into() {
+            return Err(AppError::NetworkMismatch {
+                cli_network: format!("{:?}", cli_network),
+                inferred_network: format!("{:?}", private_key.network),
+            });
+        }
+        // scriptPubKey 内の公開鍵のエンコード (圧縮・非圧縮) とWIFの指定が一致する必要がある
+        let public_key = PublicKey { compressed: private_key.compressed, inner: inner_public_key };
+        let position = script_keys.iter().position(|key| *key == public_key).ok_or_else(|| field_error(
+            field_path, &wif_field, format!("公開鍵 {} がマルチシグのscriptPubKeyに含まれていません", public_key)))?;
+        if position < next_position {
+            return Err(field_error(field_path, &wif_field, "scriptPubKey の公開鍵と同じ順で指定してください (重複も不可)"));
+        }
+        next_position = position + 1;
+        keys.push((Redacted(private_key), public_key));
+    }
+    Ok(InputSigning::Multisig { keys })
+}
 
-    // 1. 入力データの検証とProcessedUtxoへの変換
-    let mut processed_utxos: Vec<ProcessedUtxo> = Vec::new();
-    let mut total_input_value_sats = 0;
+// WIFごとのデコード結果と公開鍵のキャッシュ。多数のUTXOが同じ鍵を共有する場合に公開鍵の導出 (スカラー倍算) を繰り返さない。
+// 呼び出し側で1つ作り、同じ実行内のトランザクション (手数料率の変種、--round-up-fee の再署名、集約の見積もり) で共有する
+#[derive(Default)]
+pub(crate) struct KeyCache {
+    keys: HashMap<String, (PrivateKey, secp256k1::PublicKey)>,
+}
+
+// キーはWIFそのもののため、件数のみ表示する
+impl fmt::Debug for KeyCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyCache").field("keys", &self.keys.len()).finish()
+    }
+}
+
+impl KeyCache {
+    fn get_or_derive<C: Signing>(&mut self, wif: &str, secp: &Secp256k1<C>) -> Result<(PrivateKey, secp256k1::PublicKey), AppError> {
+        if let Some(entry) = self.keys.get(wif) {
+            return Ok(*entry);
+        }
+        let private_key = PrivateKey::from_wif(wif).map_err(AppError::BitcoinKey)?;
+        let entry = (private_key, private_key.inner.public_key(secp));
+        self.keys.insert(wif.to_string(), entry);
+        Ok(entry)
+    }
+}
 
-    for utxo_input in config.utxos.iter() {
-        let private_key = PrivateKey::from_wif(&utxo_input.private_key_wif)
-            .map_err(AppError::BitcoinKey)?;
+// UTXOの scriptPubkeyHex / scriptPubkeyAddress のどちらか一方から scriptPubKey を解決する
+pub(crate) fn resolve_utxo_script_pubkey(utxo_input: &UtxoInput, field_path: &str, network: BitcoinNetwork) -> Result<ScriptBuf, AppError> {
+    match (&utxo_input.script_pubkey_hex, &utxo_input.script_pubkey_address) {
+        (Some(script_hex), None) => {
+            let script_pubkey_bytes = hex::decode(script_hex)
+                .map_err(|e| field_error(field_path, "scriptPubkeyHex", format!("デコード失敗: {}", e)))?;
+            Ok(ScriptBuf::from_bytes(script_pubkey_bytes))
+        }
+        (None, Some(addr)) => Ok(Address::from_str(addr)
+            .and_then(|a| a.require_network(network))
+            .map_err(|e| field_error(field_path, "scriptPubkeyAddress", format!("アドレス形式エラーまたはネットワーク不整合 ({}): {}", addr, e)))?
+            .script_pubkey()),
+        _ => Err(field_error(field_path, "scriptPubkeyHex", "scriptPubkeyHex か scriptPubkeyAddress のどちらか一方を指定してください")),
+    }
+}
+
+// UtxoInput を検証し、署名に必要な情報を持つ ProcessedUtxo に変換する
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn process_utxo_input<C: Signing>(
+    input_index: usize,
+    field_path: &str,
+    utxo_input: &UtxoInput,
+    default_sequence: Option<u32>,
+    cli_network: BitcoinNetwork,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+    options: &SigningOptions,
+    warnings: &mut Vec<SigningWarning>,
+) -> Result<ProcessedUtxo, AppError> {
+    let txid = Txid::from_str(&utxo_input.txid)
+        .map_err(|e| field_error(field_path, "txid", format!("無効なTXID形式 ({}): {}", utxo_input.txid, e)))?;
+    let out_point = OutPoint::new(txid, utxo_input.vout);
+    let input_name = format!("{}{}", out_point, label_suffix(utxo_input.label.as_deref()));
+    if utxo_input.is_coinbase {
+        let confirmations = utxo_input.confirmations.ok_or_else(|| field_error(
+            field_path, "confirmations", "isCoinbase のため指定が必要です"))?;
+        if confirmations < COINBASE_MATURITY {
+            return Err(AppError::ImmatureCoinbase { input: input_name, confirmations });
+        }
+    }
+
+    let script_pubkey = resolve_utxo_script_pubkey(utxo_input, field_path, cli_network)?;
+    // 途中で切れたプッシュなどはここで検出し、署名ハッシュ計算まで持ち越さない
+    if let Some(Err(e)) = script_pubkey.instructions().find(Result::is_err) {
+        return Err(AppError::InvalidScript { input_index, reason: format!("{} ({})", e, input_name) });
+    }
+    if options.show_input_addresses {
+        match Address::from_script(&script_pubkey, cli_network) {
+            Ok(address) => eprintln!("入力 {} アドレス: {}", input_name, address),
+            Err(e) => eprintln!("入力 {} アドレス: (導出不可: {})", input_name, e),
+        }
+    }
+    // P2SH / P2WSH の script code は scriptPubKey のハッシュから復元できない (redeemScript / witnessScript)
+    if !utxo_input.skip_signing && utxo_input.script_code_hex.is_none() && (script_pubkey.is_p2sh() || script_pubkey.is_p2wsh()) {
+        let kind = if script_pubkey.is_p2sh() { "P2SH" } else { "P2WSH" };
+        return Err(AppError::InputValidation(format!(
+            "入力 {} は {} のため scriptCodeHex (script code となるスクリプト) の指定が必要です", input_name, kind)));
+    }
+    let script_type = ScriptType::from_script_buf(&script_pubkey)?; // ScriptTypeの導出
+    if let ScriptType::UnknownWitness { version, program } = script_type
+        && !utxo_input.skip_signing
+    {
+        return Err(AppError::UnsupportedWitnessVersion {
+            version: version.to_num(),
+            program_hex: hex::encode(program.program().as_bytes()),
+        });
+    }
+
+    if !utxo_input.skip_signing && (utxo_input.script_sig_hex.is_some() || !utxo_input.witness_hex.is_empty()) {
+        let field = if utxo_input.script_sig_hex.is_some() { "scriptSigHex" } else { "witnessHex" };
+        return Err(field_error(field_path, field, "skipSigning の入力でのみ指定できます"));
+    }
+    // scriptPubKey から script code を導出できるスクリプトタイプでは scriptCodeHex は任意
+    let script_code = match &utxo_input.script_code_hex {
+        Some(_) if utxo_input.skip_signing => {
+            return Err(field_error(field_path, "scriptCodeHex", "skipSigning の入力では使用されません"));
+        }
+        Some(script_code_hex) => Some(ScriptBuf::from_bytes(hex::decode(script_code_hex)
+            .map_err(|e| field_error(field_path, "scriptCodeHex", format!("不正なhexです: {}", e)))?)),
+        None => None,
+    };
+    let is_bare_multisig = matches!(script_type, ScriptType::BareMultisig { .. });
+    if !utxo_input.private_key_wifs.is_empty() && (utxo_input.skip_signing || !is_bare_multisig) {
+        return Err(field_error(field_path, "privateKeyWifs", "署名するベアマルチシグの入力でのみ指定できます"));
+    }
+    let signing = if utxo_input.skip_signing {
+        decode_presigned_input(utxo_input, field_path)?
+    } else if is_bare_multisig && (!utxo_input.private_key_wifs.is_empty() || !options.sighash_only) {
+        if utxo_input.private_key_wif.is_some() {
+            return Err(field_error(field_path, "privateKeyWif", "ベアマルチシグの入力では privateKeyWifs を指定してください"));
+        }
+        multisig_signing_keys(utxo_input, &script_pubkey, cli_network, secp, key_cache, field_path)?
+    } else if let Some(wif) = utxo_input.private_key_wif.as_deref() {
+        let (mut private_key, inner_public_key) = key_cache.get_or_derive(wif, secp)?;
+        // 圧縮形式は公開鍵のエンコードのみに影響するため、キャッシュした点から作り直す
+        let encoded_public_key = |compressed: bool| PublicKey { compressed, inner: inner_public_key };
         if private_key.network != cli_network.into() {
             return Err(AppError::NetworkMismatch {
                 cli_network: format!("{:?}", cli_network),
                 inferred_network: format!("{:?}", private_key.network),
             });
         }
-        let public_key = private_key.public_key(secp);
+        // アドレスで指定された場合は、WIFがそのアドレスを制御していないことが分かるように示す
+        let script_source = match &utxo_input.script_pubkey_address {
+            Some(address) => format!("アドレス {} ", address),
+            None => "scriptPubKey".to_string(),
+        };
+        match script_type {
+            // P2PKHは圧縮・非圧縮どちらの公開鍵でも作れるため、scriptPubKeyのハッシュと一致するエンコードで署名する (WIFの指定を優先)
+            ScriptType::P2PKH => {
+                let compressed = [private_key.compressed, !private_key.compressed]
+                    .into_iter()
+                    .find(|compressed| script_pubkey == ScriptBuf::new_p2pkh(&encoded_public_key(*compressed).pubkey_hash()))
+                    .ok_or_else(|| field_error(field_path, "privateKeyWif", format!(
+                        "P2PKH {}がWIFの公開鍵 (圧縮・非圧縮とも) のハッシュと一致しません", script_source)))?;
+                if compressed != private_key.compressed {
+                    log::info!("UTXO {}: scriptPubKeyに合わせて{}公開鍵で署名します。", input_name, if compressed { "圧縮" } else { "非圧縮" });
+                    private_key.compressed = compressed;
+                }
+            }
+            // P2WPKHは圧縮公開鍵のみ。非圧縮WIFは鍵の取り違えの可能性があるため、圧縮形式に読み替えずにエラーにする
+            ScriptType::P2WPKH => {
+                if !private_key.compressed {
+                    return Err(AppError::UncompressedKeyForSegwit { input_index, label: utxo_input.label.clone() });
+                }
+                let matches_script = encoded_public_key(true)
+                    .wpubkey_hash()
+                    .is_ok_and(|hash| script_pubkey == ScriptBuf::new_p2wpkh(&hash));
+                if !matches_script {
+                    return Err(field_error(field_path, "privateKeyWif", format!(
+                        "P2WPKH {}がWIFの圧縮公開鍵のハッシュと一致しません", script_source)));
+                }
+            }
+            // 出力鍵はWIFの公開鍵 (内部鍵) をスクリプトツリーなしで調整したもの (BIP86)。調整の計算には検証用コンテキストが必要
+            ScriptType::P2TR => {
+                let internal_key = XOnlyPublicKey::from(inner_public_key);
+                if script_pubkey != ScriptBuf::new_p2tr(&Secp256k1::verification_only(), internal_key, None) {
+                    return Err(field_error(field_path, "privateKeyWif", format!(
+                        "P2TR {}がWIFの公開鍵を内部鍵とする出力鍵 (BIP86、スクリプトツリーなし) と一致しません", script_source)));
+                }
+            }
+            ScriptType::P2PK | ScriptType::BareMultisig { .. } | ScriptType::UnknownWitness { .. } => {}
+        }
+        let public_key = encoded_public_key(private_key.compressed);
+        // P2PKの場合、scriptPubKey内の公開鍵 (エンコード形式も含め) がWIFの公開鍵と一致する必要がある
+        if script_type == ScriptType::P2PK && script_pubkey.p2pk_public_key() != Some(public_key) {
+            return Err(field_error(field_path, "privateKeyWif", format!(
+                "P2PK scriptPubKeyの公開鍵がWIFの公開鍵 ({}) と一致しません", public_key)));
+        }
+        if script_type == ScriptType::P2PKH && !private_key.compressed {
+            warn_or_fail(options, warnings, SigningWarning::UncompressedKey { outpoint: out_point, label: utxo_input.label.clone() })?;
+        }
+        // P2PKは鍵そのものを含み、P2TRは圧縮形式によらないため対象外
+        let likely_type = if private_key.compressed { ScriptType::P2WPKH } else { ScriptType::P2PKH };
+        if !matches!(script_type, ScriptType::P2PK | ScriptType::P2TR) && script_type != likely_type {
+            warn_or_fail(options, warnings, SigningWarning::ScriptTypeMismatch {
+                outpoint: out_point,
+                label: utxo_input.label.clone(),
+                script_type,
+                likely_type,
+            })?;
+        }
+        InputSigning::Key { private_key: Redacted(private_key), public_key }
+    } else if options.sighash_only {
+        external_signer_input(utxo_input, &script_pubkey, script_type, field_path)?
+    } else {
+        return Err(field_error(field_path, "privateKeyWif", "指定が必要です (署名しない場合は skipSigning を指定してください)"));
+    };
+
+    if let (Some(sequence), Some(default_sequence)) = (utxo_input.sequence, default_sequence)
+        && sequence != default_sequence
+    {
+        warn_or_fail(options, warnings, SigningWarning::SequenceOverride {
+            outpoint: out_point,
+            label: utxo_input.label.clone(),
+            sequence,
+            default_sequence,
+        })?;
+    }
+    let sequence_num = utxo_input.sequence.or(default_sequence).unwrap_or(Sequence::MAX.0);
+    let mut sequence = Sequence(sequence_num);
+    let required_lock_time = utxo_input.requires_locktime.map(LockTime::from_consensus);
+    // OP_CHECKLOCKTIMEVERIFY は入力の sequence が final (0xffffffff) だと失敗する
+    if let Some(required_lock_time) = required_lock_time
+        && sequence == Sequence::MAX
+    {
+        // 明示された sequence と、署名済み (sequence にコミット済み) の入力は変更しない
+        if utxo_input.sequence.is_some() || utxo_input.skip_signing {
+            return Err(field_error(field_path, "sequence", format!(
+                "0xffffffff では requiresLocktime ({}) の OP_CHECKLOCKTIMEVERIFY を満たせません", required_lock_time)));
+        }
+        log::info!("UTXO {}: requiresLocktime のため sequence を 0x{:08x} にします。", input_name, Sequence::ENABLE_LOCKTIME_NO_RBF.0);
+        sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+    }
+    // BIP68: sequence が相対ロックタイムを表す場合、confirmations からこの入力が次のブロックに含められるかを確認する
+    match (sequence.to_relative_lock_time(), utxo_input.confirmations) {
+        (Some(bitcoin::relative::LockTime::Blocks(height)), Some(confirmations)) => {
+            let required_blocks = height.value();
+            if confirmations < u32::from(required_blocks) {
+                warn_or_fail(options, warnings, SigningWarning::RelativeLockTimeNotMet {
+                    outpoint: out_point,
+                    label: utxo_input.label.clone(),
+                    required_blocks,
+                    confirmations,
+                })?;
+            } else {
+                log::info!("UTXO {}: 相対ロックタイム {} ブロックは確認数 {} で満たされています。", input_name, required_blocks, confirmations);
+            }
+        }
+        (Some(bitcoin::relative::LockTime::Time(time)), _) => {
+            // 時間指定はブロックのmedian time pastが必要なため、ローカルでは確認できない
+            log::info!("UTXO {}: 相対ロックタイム {} 秒 (512秒単位 {}) の成立は確認していません。", input_name, u32::from(time.value()) * 512, time.value());
+        }
+        (Some(bitcoin::relative::LockTime::Blocks(height)), None) => {
+            log::debug!("UTXO {}: 相対ロックタイム {} ブロック (confirmations 未指定のため成立は確認しません)。", input_name, height.value());
+        }
+        (None, _) => {}
+    }
+
+    let sighash_type = match &utxo_input.sighash_type {
+        // P2TR は常に SIGHASH_DEFAULT (全入力・全出力にコミット) で署名する
+        Some(_) if script_type == ScriptType::P2TR && !utxo_input.skip_signing => {
+            return Err(field_error(field_path, "sighashType", "P2TR の入力では指定できません (SIGHASH_DEFAULT で署名します)"));
+        }
+        Some(name) => parse_sighash_type(name, field_path)?,
+        None => EcdsaSighashType::All,
+    };
+    // SIGHASH_NONE は出力にコミットしない (legacy / BIP143 とも outputs のハッシュが空になる) ため、署名後に出力を書き換えられる
+    if matches!(sighash_type, EcdsaSighashType::None | EcdsaSighashType::NonePlusAnyoneCanPay) && !utxo_input.skip_signing {
+        warn_or_fail(options, warnings, SigningWarning::SighashNone { outpoint: out_point, label: utxo_input.label.clone(), sighash_type })?;
+    }
+
+    let bip32_derivation = match &utxo_input.bip32_derivation {
+        Some(derivation) => {
+            // ベアマルチシグではどの鍵の導出情報かを publicKeyHex で明示する
+            let default_public_key = match &signing {
+                InputSigning::Key { public_key, .. } | InputSigning::External { public_key } => Some(*public_key),
+                InputSigning::Multisig { .. } | InputSigning::Presigned { .. } => None,
+            };
+            Some(parse_bip32_derivation(derivation, default_public_key, &format!("{}.bip32Derivation", field_path))?)
+        }
+        None => None,
+    };
+
+    let utxo_value = Amount::from_sat(utxo_input.value_sats);
+    let tx_out = TxOut {
+        value: utxo_value,
+        script_pubkey: script_pubkey.clone(),
+    };
+    let previous_transaction = utxo_input
+        .prev_tx_hex
+        .as_deref()
+        .map(|prev_tx_hex| decode_previous_transaction(prev_tx_hex, out_point, &tx_out, field_path))
+        .transpose()?;
+
+    Ok(ProcessedUtxo {
+        out_point,
+        tx_out,
+        signing,
+        script_type, // script_type が Copy または Clone であることを確認
+        sequence,
+        value: utxo_value,
+        sighash_type,
+        label: utxo_input.label.clone(),
+        bip32_derivation,
+        previous_transaction,
+        script_code,
+        required_lock_time,
+    })
+}
+
+// 送金額 (おつり以外の出力の合計)。おつり出力しかないスイープではその金額
+fn sent_value_sats(outputs: &[TxOut], change_index: Option<usize>) -> u64 {
+    outputs
+        .iter()
+        .enumerate()
+        .filter(|(output_index, _)| Some(*output_index) != change_index || outputs.len() == 1)
+        .map(|(_, output)| output.value.to_sat())
+        .sum()
+}
+
+// --safe の条件を確定した出力に対して検査する。change_index はおつり出力がある場合のその位置
+fn check_safe_mode(policy: &SafeModePolicy, outputs: &[TxOut], change_index: Option<usize>, fee_sats: u64) -> Result<(), AppError> {
+    for (output_index, output) in outputs.iter().enumerate() {
+        if !policy.allowed_script_pubkeys.contains(&output.script_pubkey) {
+            let kind = if Some(output_index) == change_index { "おつり出力" } else { "出力" };
+            return Err(AppError::SafeModeViolation(format!(
+                "{} {} の送金先 ({}) は許可リストにありません", kind, output_index, output.script_pubkey)));
+        }
+    }
+    let sent_sats = sent_value_sats(outputs, change_index);
+    let max_fee_sats = (sent_sats as f64 * policy.max_fee_percent / 100.0).floor() as u64;
+    if fee_sats > max_fee_sats {
+        return Err(AppError::SafeModeViolation(format!(
+            "手数料 {} sats が送金額 {} sats の {}% ({} sats) を超えています", fee_sats, sent_sats, policy.max_fee_percent, max_fee_sats)));
+    }
+    Ok(())
+}
+
+// 構築前の一括検査: すべてのWIFとアドレスがネットワークと一致することを確認し、不一致を1つのエラーにまとめて報告する。
+// 形式自体が不正な鍵やアドレスはここでは無視し、個別の処理でエラーにする
+fn validate_network_consistency(config: &InputConfig, network: BitcoinNetwork) -> Result<(), AppError> {
+    let mut outliers = Vec::new();
+    let utxos = config.utxos.iter().map(|utxo| ("utxos", utxo))
+        .chain(config.fee_bump_utxos.iter().map(|utxo| ("feeBumpUtxos", utxo)));
+    let mut indices: HashMap<&str, usize> = HashMap::new();
+    for (field, utxo) in utxos {
+        let index = indices.entry(field).or_insert(0);
+        let name = format!("{}[{}] ({}:{}{})", field, index, utxo.txid, utxo.vout, label_suffix(utxo.label.as_deref()));
+        *index += 1;
+        if let Some(private_key) = utxo.private_key_wif.as_deref().and_then(|wif| PrivateKey::from_wif(wif).ok())
+            && private_key.network != network.into()
+        {
+            outliers.push(format!("{} の privateKeyWif ({:?})", name, private_key.network));
+        }
+        if let Some(address) = utxo.script_pubkey_address.as_deref() {
+            check_address_network(address, network, &format!("{} の scriptPubkeyAddress", name), &mut outliers);
+        }
+    }
+    let outputs = config.outputs.iter().map(|output| ("outputs", output))
+        .chain(config.append_outputs.iter().map(|output| ("appendOutputs", output)));
+    for (field, output) in outputs {
+        let index = indices.entry(field).or_insert(0);
+        if let Some(address) = output.address.as_deref() {
+            let name = format!("{}[{}]{} の address", field, index, label_suffix(output.label.as_deref()));
+            check_address_network(address, network, &name, &mut outliers);
+        }
+        *index += 1;
+    }
+    for address in config.change_address.iter().flat_map(ChangeAddressDef::addresses) {
+        check_address_network(address, network, "changeAddress", &mut outliers);
+    }
+    if outliers.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::NetworkInconsistency {
+        network: format!("{:?}", network),
+        outliers: outliers.iter().map(|outlier| format!("  - {}", outlier)).collect::<Vec<_>>().join("\n"),
+    })
+}
+
+fn check_address_network(address: &str, network: BitcoinNetwork, name: &str, outliers: &mut Vec<String>) {
+    if let Ok(address) = Address::from_str(address)
+        && !address.is_valid_for_network(network)
+    {
+        outliers.push(format!("{} ({})", name, address.assume_checked_ref()));
+    }
+}
+
+// 手数料率から求める手数料。ancestorFeeSats / ancestorVsize の指定時は、未承認の祖先を含むパッケージ全体が手数料率を
+// 満たすよう (祖先のvsize + 自身のvsize) × 手数料率 - 祖先の手数料 とする。ただし自身の最低リレー手数料は下回らない
+fn target_fee_sats(config: &InputConfig, vsize: u64, fee_rate_sats_per_vb: FeeRate) -> Option<u64> {
+    let (Some(ancestor_fee_sats), Some(ancestor_vsize)) = (config.ancestor_fee_sats, config.ancestor_vsize) else {
+        return fee_rate_sats_per_vb.fee_for_vsize(vsize);
+    };
+    let package_fee_sats = fee_rate_sats_per_vb.fee_for_vsize(vsize.checked_add(ancestor_vsize)?)?;
+    Some(package_fee_sats.saturating_sub(ancestor_fee_sats).max(vsize.saturating_mul(MIN_RELAY_FEE_RATE_SATS_PER_VB)))
+}
+
+// 手数料の指定方法 (feeRateSatsPerVb / feePercent) の組み合わせを検証する
+fn validate_fee_mode(config: &InputConfig, options: &SigningOptions) -> Result<(), AppError> {
+    match (config.ancestor_fee_sats, config.ancestor_vsize) {
+        (Some(_), None) | (None, Some(_)) => {
+            let missing = if config.ancestor_fee_sats.is_none() { "ancestorFeeSats" } else { "ancestorVsize" };
+            return Err(field_error("", missing, "ancestorFeeSats と ancestorVsize は両方指定してください"));
+        }
+        (Some(_), Some(0)) => return Err(field_error("", "ancestorVsize", "1 以上である必要があります")),
+        _ => {}
+    }
+    let Some(percent) = config.fee_percent else {
+        return match config.fee_rate_sats_per_vb {
+            Some(_) => Ok(()),
+            None => Err(field_error("", "feeRateSatsPerVb", "feeRateSatsPerVb か feePercent のどちらかを指定してください")),
+        };
+    };
+    let conflicting = [
+        (config.fee_rate_sats_per_vb.is_some(), "feeRateSatsPerVb"),
+        (config.fee_bump_target_rate_sats_per_vb.is_some(), "feeBumpTargetRateSatsPerVb"),
+        (config.sweep_auto_lower_fee_rate, "sweepAutoLowerFeeRate"),
+        (options.round_up_fee, "--round-up-fee"),
+        (config.ancestor_fee_sats.is_some(), "ancestorFeeSats"),
+    ];
+    if let Some((_, name)) = conflicting.iter().find(|(present, _)| *present) {
+        return Err(field_error("", "feePercent", format!("{} と同時に指定できません", name)));
+    }
+    if !(percent.is_finite() && percent > 0.0 && percent <= 100.0) {
+        return Err(field_error("", "feePercent", format!("{} は 0 より大きく 100 以下である必要があります", percent)));
+    }
+    if config.outputs.is_empty() && config.append_outputs.is_empty() {
+        return Err(field_error("", "feePercent", "受信者出力のないスイープでは使用できません"));
+    }
+    Ok(())
+}
+
+// feeInputIndex: 手数料を支払う入力以外の入力と受信者出力が、設定ファイルの順に1対1で同じ金額であることを確認する。
+// 手数料の計算前に対応関係だけを検証し、手数料を賄えるかは手数料の確定後に確認する
+fn validate_fee_input(config: &InputConfig) -> Result<(), AppError> {
+    let Some(fee_input_index) = config.fee_input_index else {
+        return Ok(());
+    };
+    if fee_input_index >= config.utxos.len() {
+        return Err(field_error("", "feeInputIndex", format!("{} が範囲外です (utxos は {} 件)", fee_input_index, config.utxos.len())));
+    }
+    let conflicting = [
+        (!config.fee_bump_utxos.is_empty(), "feeBumpUtxos"),
+        (!config.append_outputs.is_empty(), "appendOutputs"),
+    ];
+    if let Some((_, name)) = conflicting.iter().find(|(present, _)| *present) {
+        return Err(field_error("", "feeInputIndex", format!("{} と同時に指定できません", name)));
+    }
+    let mapped_utxos: Vec<(usize, &UtxoInput)> = config.utxos.iter().enumerate().filter(|(index, _)| *index != fee_input_index).collect();
+    if mapped_utxos.len() != config.outputs.len() {
+        return Err(field_error("", "feeInputIndex", format!(
+            "手数料を支払う入力以外の入力 {} 件と outputs {} 件が1対1に対応しません", mapped_utxos.len(), config.outputs.len())));
+    }
+    for (output_index, ((utxo_index, utxo_input), output_def)) in mapped_utxos.iter().zip(&config.outputs).enumerate() {
+        if output_def.value_sats != utxo_input.value_sats {
+            return Err(field_error(&format!("outputs[{}]", output_index), "valueSats", format!(
+                "{} sats が対応する入力 utxos[{}] の金額 {} sats と一致しません (feeInputIndex の指定時)",
+                output_def.value_sats, utxo_index, utxo_input.value_sats)));
+        }
+    }
+    Ok(())
+}
 
-        let txid = Txid::from_str(&utxo_input.txid)
-            .map_err(|e| AppError::InputValidation(format!("無効なTXID形式 ({}): {}", utxo_input.txid, e)))?;
-        let out_point = OutPoint::new(txid, utxo_input.vout);
+pub fn create_and_sign_transaction<C: Signing>(
+    config: InputConfig,
+    cli_network: BitcoinNetwork,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+    options: &SigningOptions,
+    mut on_progress: impl FnMut(SigningProgress),
+) -> Result<SignedTransaction, AppError> {
+    validate_fee_mode(&config, options)?;
+    validate_fee_input(&config)?;
+    validate_network_consistency(&config, cli_network)?;
+    if !options.round_up_fee {
+        return build_and_sign_transaction(config, cli_network, secp, key_cache, options, &mut on_progress, 0);
+    }
+    // --round-up-fee: 署名後の実際のvsizeで手数料率が目標を下回っていれば、不足分を上乗せして一度だけ作り直す
+    let first_pass = build_and_sign_transaction(config.clone(), cli_network, secp, key_cache, options, &mut on_progress, 0)?;
+    let actual_vsize = transaction_vsize(&first_pass.transaction);
+    let required_fee_sats = target_fee_sats(&config, actual_vsize, first_pass.fee_rate_sats_per_vb).unwrap_or(u64::MAX);
+    if first_pass.fee_sats >= required_fee_sats {
+        return Ok(first_pass);
+    }
+    let shortfall_sats = required_fee_sats - first_pass.fee_sats;
+    log::info!("実際のvsize {} vB では手数料が {} sats 不足するため、上乗せして再署名します。", actual_vsize, shortfall_sats);
+    let second_pass = build_and_sign_transaction(config.clone(), cli_network, secp, key_cache, options, &mut on_progress, shortfall_sats)?;
+    let second_required_sats = target_fee_sats(&config, transaction_vsize(&second_pass.transaction), second_pass.fee_rate_sats_per_vb)
+        .unwrap_or(u64::MAX);
+    if second_pass.fee_sats < second_required_sats {
+        log::warn!("再署名後も手数料 {} sats が目標 {} sats を下回っています。", second_pass.fee_sats, second_required_sats);
+    }
+    Ok(second_pass)
+}
 
-        let script_pubkey_bytes = hex::decode(&utxo_input.script_pubkey_hex)
-            .map_err(|e| AppError::InputValidation(format!("scriptPubKeyHexのデコード失敗: {}", e)))?;
-        let script_pubkey = ScriptBuf::from_bytes(script_pubkey_bytes);
-        let script_type = ScriptType::from_script_buf(&script_pubkey)?; // ScriptTypeの導出
+// P2TR の鍵パスのSchnorr署名を witness に適用する
+fn apply_schnorr_signature<C: Signing>(secp: &Secp256k1<C>, info: &SigningInfo, transaction: &mut Transaction, signed_input: &mut SignedInput) {
+    // 鍵パスでは内部鍵をスクリプトツリーなしで調整した鍵で署名する (BIP86)
+    let keypair = Keypair::from_secret_key(secp, &info.private_key.inner);
+    let tweaked = keypair.tap_tweak(&Secp256k1::verification_only(), None);
+    let taproot_sig = bitcoin::taproot::Signature {
+        // rand 機能を有効にしていないため補助乱数なし (BIP340 で許容される決定的な署名)
+        signature: secp.sign_schnorr_no_aux_rand(&info.sighash_message, &tweaked.to_keypair()),
+        sighash_type: TapSighashType::Default,
+    };
+    transaction.input[info.input_index].witness = bitcoin::Witness::p2tr_key_spend(&taproot_sig);
+    signed_input.taproot_signature = Some(taproot_sig);
+    log::debug!("入力 {}{} (P2TR) の署名適用完了。", info.input_index, label_suffix(info.label.as_deref()));
+}
 
-        let sequence_num = utxo_input.sequence.or(config.default_sequence).unwrap_or(Sequence::MAX.0);
-        let sequence = Sequence(sequence_num);
+// ECDSA署名を script_sig / witness に適用する。ベアマルチシグは鍵ごとに呼ばれ、署名を鍵の順に追加する
+fn apply_ecdsa_signature<C: Signing>(
+    secp: &Secp256k1<C>,
+    info: &SigningInfo,
+    transaction: &mut Transaction,
+    signed_input: &mut SignedInput,
+) -> Result<(), AppError> {
+    let input_label = label_suffix(info.label.as_deref());
+    let secp_sig = secp.sign_ecdsa(&info.sighash_message, &info.private_key.inner);
+    // DER署名の末尾に sighash type のバイトを付加したものが script_sig / witness に入る
+    let btc_ecdsa_sig = bitcoin::ecdsa::Signature {
+        signature: secp_sig,
+        sighash_type: info.sighash_type,
+    };
 
-        let utxo_value = Amount::from_sat(utxo_input.value_sats);
-        let tx_out = TxOut {
-            value: utxo_value,
-            script_pubkey: script_pubkey.clone(),
+    match info.script_type {
+        ScriptType::P2PKH => {
+            let final_script_sig = bitcoin::script::Builder::new()
+                .push_slice(PushBytesBuf::try_from(btc_ecdsa_sig.to_vec())
+                    .map_err(|_| AppError::Internal(format!("P2PKH署名のPushBytes変換失敗 (input {}{})", info.input_index, input_label)))?)
+                .push_key(&info.public_key)
+                .into_script();
+            transaction.input[info.input_index].script_sig = final_script_sig;
+            log::debug!("入力 {}{} (P2PKH) の署名適用完了。", info.input_index, input_label);
+        }
+        ScriptType::P2PK => {
+            // 公開鍵はscriptPubKeyに含まれるため、script_sig は署名のみ
+            let final_script_sig = bitcoin::script::Builder::new()
+                .push_slice(PushBytesBuf::try_from(btc_ecdsa_sig.to_vec())
+                    .map_err(|_| AppError::Internal(format!("P2PK署名のPushBytes変換失敗 (input {}{})", info.input_index, input_label)))?)
+                .into_script();
+            transaction.input[info.input_index].script_sig = final_script_sig;
+            log::debug!("入力 {}{} (P2PK) の署名適用完了。", info.input_index, input_label);
+        }
+        ScriptType::BareMultisig { .. } => {
+            // 鍵の順に署名を追加する。最初の署名の前に OP_CHECKMULTISIG の余分なポップ用の OP_0 を置く
+            let script_sig = &mut transaction.input[info.input_index].script_sig;
+            if script_sig.is_empty() {
+                script_sig.push_opcode(OP_0);
+            }
+            script_sig.push_slice(PushBytesBuf::try_from(btc_ecdsa_sig.to_vec())
+                .map_err(|_| AppError::Internal(format!("マルチシグ署名のPushBytes変換失敗 (input {}{})", info.input_index, input_label)))?);
+            log::debug!("入力 {}{} (ベアマルチシグ) に公開鍵 {} の署名を追加しました。", info.input_index, input_label, info.public_key);
+        }
+        ScriptType::P2WPKH => {
+            let mut final_witness = bitcoin::Witness::new();
+            final_witness.push(btc_ecdsa_sig.to_vec());
+            final_witness.push(info.public_key.to_bytes());
+            transaction.input[info.input_index].witness = final_witness;
+            log::debug!("入力 {}{} (P2WPKH) の署名適用完了。", info.input_index, input_label);
+        }
+        // signature_scheme() が Ecdsa でないタイプはここに来ない
+        ScriptType::P2TR | ScriptType::UnknownWitness { .. } => {
+            return Err(AppError::Internal(format!(
+                "{:?} の入力 {}{} にECDSA署名を適用しようとしました", info.script_type, info.input_index, input_label)));
+        }
+    }
+    if signed_input.signature.is_none() {
+        signed_input.signature = Some(btc_ecdsa_sig);
+    } else {
+        signed_input.cosigner_signatures.push((info.public_key, btc_ecdsa_sig));
+    }
+    Ok(())
+}
+
+// extra_fee_sats は推定手数料に上乗せする額 (--round-up-fee の2回目)
+fn build_and_sign_transaction<C: Signing>(
+    mut config: InputConfig,
+    cli_network: BitcoinNetwork,
+    secp: &Secp256k1<C>,
+    key_cache: &mut KeyCache,
+    options: &SigningOptions,
+    on_progress: &mut impl FnMut(SigningProgress),
+    extra_fee_sats: u64,
+) -> Result<SignedTransaction, AppError> {
+    log::info!("トランザクション構築処理を開始します。");
+
+    // 1. 入力データの検証とProcessedUtxoへの変換
+    let utxo_phase_start = Instant::now();
+    let mut processed_utxos: Vec<ProcessedUtxo> = Vec::new();
+    let mut warnings: Vec<SigningWarning> = Vec::new();
+    let mut total_input_value_sats = 0;
+
+    // 手数料バンプ用UTXO (feeBumpUtxos) は通常の入力の後ろに追加する
+    let input_count = config.utxos.len() + config.fee_bump_utxos.len();
+    for (utxo_index, utxo_input) in config.utxos.iter().chain(config.fee_bump_utxos.iter()).enumerate() {
+        if let Some(max) = config.max_inputs.filter(|max| utxo_index >= *max) {
+            return Err(AppError::TooManyInputs { count: input_count, max });
+        }
+        if utxo_index == config.utxos.len() {
+            log::info!("手数料バンプ用UTXO {} 件を入力に追加します。", config.fee_bump_utxos.len());
+        }
+        let field_path = if utxo_index < config.utxos.len() {
+            format!("utxos[{}]", utxo_index)
+        } else {
+            format!("feeBumpUtxos[{}]", utxo_index - config.utxos.len())
         };
+        let processed_utxo = process_utxo_input(utxo_index, &field_path, utxo_input, config.default_sequence, cli_network, secp, key_cache, options, &mut warnings)?;
+        let script_type = processed_utxo.script_type;
+        processed_utxos.push(processed_utxo);
+        total_input_value_sats = checked_add_amount(total_input_value_sats, utxo_input.value_sats, "UTXO")?;
+        log::debug!("処理済みUTXO追加: txid={}, vout={}, value={}, type={:?}{}",
+            utxo_input.txid, utxo_input.vout, utxo_input.value_sats, script_type, label_suffix(utxo_input.label.as_deref()));
+    }
 
-        processed_utxos.push(ProcessedUtxo {
-            out_point,
-            tx_out,
-            private_key, // private_key はここでムーブされるか、Clone される
-            public_key,
-            script_type, // script_type が Copy または Clone であることを確認
-            sequence,
-            value: utxo_value,
-        });
-        total_input_value_sats += utxo_input.value_sats;
-        log::debug!("処理済みUTXO追加: txid={}, vout={}, value={}, type={:?}",
-            utxo_input.txid, utxo_input.vout, utxo_input.value_sats, script_type);
+    // 手数料を支払う入力の金額 (validate_fee_input で範囲は確認済み)
+    let fee_input = config.fee_input_index.map(|index| (index, config.utxos[index].value_sats));
+    // 大量のUTXOを扱う場合のメモリ削減のため、ProcessedUtxo に変換済みの設定は以降保持しない
+    config.utxos = Vec::new();
+
+    // 並び替えは署名ハッシュ計算より前に行うため、以降の input_index は並び替え後の順序を指す
+    match config.input_order {
+        InputOrder::Config => {}
+        InputOrder::Bip69 => processed_utxos.sort_by(|a, b| {
+            // Txid の Display は RPC と同じ表示順 (内部バイト列の逆順) のhex
+            a.out_point.txid.to_string().cmp(&b.out_point.txid.to_string())
+                .then(a.out_point.vout.cmp(&b.out_point.vout))
+        }),
+        InputOrder::ValueDesc => processed_utxos.sort_by_key(|pu| std::cmp::Reverse(pu.value)), // 安定ソート
+    }
+    if config.input_order != InputOrder::Config {
+        log::debug!("入力を {:?} 順に並び替えました。", config.input_order);
     }
+    let utxo_phase_elapsed = utxo_phase_start.elapsed();
 
     // 2. 受信者出力の作成 
+    let dust_relay_fee_sats_per_kvb = config.dust_relay_fee_sats_per_kvb.unwrap_or(DEFAULT_DUST_RELAY_FEE_SATS_PER_KVB);
     let mut outputs: Vec<TxOut> = Vec::new();
+    let mut output_bip32_derivations = Vec::new();
     let mut total_recipient_output_value_sats = 0;
-    for output_def in config.outputs.iter() {
-        let address = Address::from_str(&output_def.address)
-            .and_then(|addr| addr.require_network(cli_network))
-            .map_err(|e| AppError::InputValidation(format!("受信者アドレス形式エラーまたはネットワーク不整合 ({}): {}", output_def.address, e)))?;
+    // サイレントペイメント出力は全入力の秘密鍵に依存するため、入力確定後にまとめて導出する
+    #[cfg(feature = "silent-payments")]
+    let mut silent_payment_scripts = {
+        let addresses: Vec<&str> = config.outputs.iter().filter_map(|o| o.silent_payment_address.as_deref()).collect();
+        if addresses.is_empty() {
+            Vec::new()
+        } else {
+            crate::silent_payment::derive_silent_payment_scripts(&addresses, &processed_utxos, cli_network, secp)?
+        }
+    }
+    .into_iter();
+    for (output_index, output_def) in config.outputs.iter().enumerate() {
+        if let Some(max) = config.max_outputs.filter(|max| output_index >= *max) {
+            return Err(AppError::TooManyOutputs { count: config.outputs.len(), max });
+        }
+        #[cfg(feature = "silent-payments")]
+        let silent_payment_script = match &output_def.silent_payment_address {
+            Some(_) if output_def.address.is_some() || output_def.descriptor.is_some() || output_def.pay_to_pubkey_hex.is_some() => {
+                return Err(field_error(&format!("outputs[{}]", output_index), "silentPaymentAddress",
+                    "address / descriptor / payToPubkeyHex と同時に指定できません"));
+            }
+            Some(_) => silent_payment_scripts.next(),
+            None => None,
+        };
+        #[cfg(not(feature = "silent-payments"))]
+        let silent_payment_script: Option<ScriptBuf> = None;
+        let script_pubkey = match silent_payment_script {
+            Some(script) => script,
+            None => resolve_output_def_script(output_def, cli_network)
+            .map_err(|e| match e {
+                AppError::InputValidation(msg) => field_error(&format!("outputs[{}]", output_index), output_def_field(output_def), msg),
+                other => other,
+            })?,
+        };
+        check_recipient_allowed(options, &script_pubkey, cli_network, || {
+            format!("受信者出力 {}{}", output_index, label_suffix(output_def.label.as_deref()))
+        })?;
+        let bip32_derivation = output_def
+            .bip32_derivation
+            .as_ref()
+            .map(|derivation| parse_bip32_derivation(derivation, None, &format!("outputs[{}].bip32Derivation", output_index)))
+            .transpose()?;
+        output_bip32_derivations.push(bip32_derivation);
+        let threshold_sats = dust_threshold_sats(&script_pubkey, dust_relay_fee_sats_per_kvb);
+        if output_def.value_sats < threshold_sats {
+            warn_or_fail(options, &mut warnings, SigningWarning::DustOutput {
+                output_index,
+                label: output_def.label.clone(),
+                value_sats: output_def.value_sats,
+                threshold_sats,
+            })?;
+        }
         outputs.push(TxOut {
             value: Amount::from_sat(output_def.value_sats),
-            script_pubkey: address.script_pubkey(),
+            script_pubkey,
+        });
+        total_recipient_output_value_sats = checked_add_amount(total_recipient_output_value_sats, output_def.value_sats, "受信者出力")?;
+        log::debug!("受信者出力追加: address={:?}, descriptor={:?}, value={}{}",
+            output_def.address, output_def.descriptor, output_def.value_sats, label_suffix(output_def.label.as_deref()));
+    }
+
+    // appendOutputs はおつり出力の後ろに置く。金額は受信者出力と同様に必要額に含める
+    let mut appended_outputs: Vec<TxOut> = Vec::new();
+    let mut appended_bip32_derivations = Vec::new();
+    for (append_index, output_def) in config.append_outputs.iter().enumerate() {
+        let context = format!("追加出力 {}{}", append_index, label_suffix(output_def.label.as_deref()));
+        let field_path = format!("appendOutputs[{}]", append_index);
+        #[cfg(feature = "silent-payments")]
+        if output_def.silent_payment_address.is_some() {
+            return Err(field_error(&field_path, "silentPaymentAddress", "appendOutputs では使用できません"));
+        }
+        let script_pubkey = resolve_output_def_script(output_def, cli_network)
+            .map_err(|e| match e {
+                AppError::InputValidation(msg) => field_error(&field_path, output_def_field(output_def), msg),
+                other => other,
+            })?;
+        check_recipient_allowed(options, &script_pubkey, cli_network, || context.clone())?;
+        appended_bip32_derivations.push(output_def
+            .bip32_derivation
+            .as_ref()
+            .map(|derivation| parse_bip32_derivation(derivation, None, &format!("{}.bip32Derivation", field_path)))
+            .transpose()?);
+        let threshold_sats = dust_threshold_sats(&script_pubkey, dust_relay_fee_sats_per_kvb);
+        if output_def.value_sats < threshold_sats {
+            warn_or_fail(options, &mut warnings, SigningWarning::DustOutput {
+                output_index: config.outputs.len() + append_index,
+                label: output_def.label.clone(),
+                value_sats: output_def.value_sats,
+                threshold_sats,
+            })?;
+        }
+        appended_outputs.push(TxOut {
+            value: Amount::from_sat(output_def.value_sats),
+            script_pubkey,
+        });
+        total_recipient_output_value_sats = checked_add_amount(total_recipient_output_value_sats, output_def.value_sats, "追加出力")?;
+        log::debug!("{}: value={}", context, output_def.value_sats);
+    }
+
+    let lock_time = apply_required_lock_times(resolve_lock_time(config.lock_time_height, config.lock_time_timestamp)?, &processed_utxos)?;
+    if lock_time != LockTime::ZERO && processed_utxos.iter().all(|pu| pu.sequence == Sequence::MAX) {
+        log::warn!("全入力の sequence が 0xffffffff のため、nLockTime {} は適用されません。", lock_time);
+    }
+
+    // 手数料を含めるまでもなく不足している場合は InsufficientFunds より先に報告する
+    if total_recipient_output_value_sats > total_input_value_sats {
+        return Err(AppError::OutputsExceedInputs {
+            inputs: total_input_value_sats,
+            outputs: total_recipient_output_value_sats,
         });
-        total_recipient_output_value_sats += output_def.value_sats;
-        log::debug!("受信者出力追加: address={}, value={}", output_def.address, output_def.value_sats);
     }
 
     // 3. 手数料計算と変更（おつり）処理
-    let initial_inputs: Vec<TxIn> = processed_utxos
-        .iter()
-        .map(|pu| {
-            let mut tx_in = TxIn {
-                previous_output: pu.out_point,
-                script_sig: ScriptBuf::new(), // 手数料計算時は空の script_sig
-                sequence: pu.sequence,
-                witness: bitcoin::Witness::new(), // 手数料計算時は空の witness
-            };
-            // 手数料計算のためのダミー署名と公開鍵のサイズをscript_sig/witnessに反映
-            match pu.script_type {
-                ScriptType::P2PKH => {
-                    tx_in.script_sig = bitcoin::script::Builder::new()
-                        .push_slice([0u8; DUMMY_SIGNATURE_LEN])
-                        .push_key(&pu.public_key)
-                        .into_script();
-                }
-                ScriptType::P2WPKH => {
-                    tx_in.witness.push(vec![0u8; DUMMY_SIGNATURE_LEN]);
-                    tx_in.witness.push(pu.public_key.to_bytes());
-                }
-            }
-            tx_in
-        })
-        .collect();
+    let initial_inputs: Vec<TxIn> = processed_utxos.iter().map(dummy_signed_txin).collect();
 
     let mut temp_outputs_for_size_calc = outputs.clone();
-    let change_address_str = config.change_address.clone();
-    let change_address = Address::from_str(&change_address_str)
-        .and_then(|addr| addr.require_network(cli_network))
-        .map_err(|e| AppError::ChangeAddressDerivation(format!("おつりアドレス形式エラーまたはネットワーク不整合 ({}): {}", config.change_address, e)))?;
+    // アドレスのリストは呼び出し側 (--change-state-file) で1つに決めておく必要がある
+    let change_address = match &config.change_address {
+        Some(ChangeAddressDef::Single(address)) => Some(address.as_str()),
+        Some(ChangeAddressDef::Rotation(_)) => {
+            return Err(AppError::ChangeAddressDerivation(
+                "changeAddress をリストで指定する場合は --change-state-file を指定してください".to_string()));
+        }
+        None => None,
+    };
+    let change_script_pubkey = resolve_output_script(
+        change_address,
+        config.change_descriptor.as_deref(),
+        cli_network,
+    )
+    .map_err(|e| match e {
+        AppError::InputValidation(msg) => AppError::ChangeAddressDerivation(msg),
+        other => other,
+    })?;
+    // おつり出力 (スイープ出力を含む) のダスト閾値はスクリプトのサイズに依存する
+    let dust_threshold_sats = dust_threshold_sats(&change_script_pubkey, dust_relay_fee_sats_per_kvb);
+    let change_bip32_derivation = config
+        .change_bip32_derivation
+        .as_ref()
+        .map(|derivation| parse_bip32_derivation(derivation, None, "changeBip32Derivation"))
+        .transpose()?;
 
     let change_tx_out_for_size = TxOut {
         value: Amount::from_sat(0), // ダミーの金額
-        script_pubkey: change_address.script_pubkey(),
+        script_pubkey: change_script_pubkey.clone(),
     };
     temp_outputs_for_size_calc.push(change_tx_out_for_size); // おつり出力もサイズ計算に含める
+    temp_outputs_for_size_calc.extend(appended_outputs.iter().cloned());
 
     let temp_tx = Transaction {
-        version: bitcoin::transaction::Version(2),
-        lock_time: LockTime::ZERO,
+        version: TX_VERSION,
+        lock_time,
         input: initial_inputs.clone(), // ダミー署名入りの入力
         output: temp_outputs_for_size_calc.clone(),
     };
 
-    let estimated_vsize = temp_tx.vsize();
-    let total_fee_sats = estimated_vsize as u64 * config.fee_rate_sats_per_vb;
-    log::debug!("推定vsize: {} vB, 手数料率: {} sats/vB, 計算された手数料: {} sats", estimated_vsize, config.fee_rate_sats_per_vb, total_fee_sats);
+    // feePercent: 手数料は受信者出力合計の割合で決まり、手数料率はそこから逆算した実効値 (上限チェック用)
+    let percent_fee_sats = match config.fee_percent {
+        Some(percent) => {
+            let fee_sats = (total_recipient_output_value_sats as f64 * percent / 100.0).ceil() as u64;
+            let min_fee_sats = transaction_vsize(&temp_tx).saturating_mul(MIN_RELAY_FEE_RATE_SATS_PER_VB);
+            if fee_sats < min_fee_sats {
+                return Err(AppError::FeeBelowMinRelay { fee: fee_sats, min: min_fee_sats });
+            }
+            log::info!("feePercent {}%: 受信者出力合計 {} sats に対する手数料は {} sats です。", percent, total_recipient_output_value_sats, fee_sats);
+            Some(fee_sats)
+        }
+        None => None,
+    };
+    // 手数料バンプ用UTXOがあり目標手数料率が指定されていればそれを使う。増えた入力額はおつりに回る
+    let mut fee_rate_sats_per_vb = match (percent_fee_sats, config.fee_bump_target_rate_sats_per_vb) {
+        (Some(fee_sats), _) => FeeRate::from_fee_and_vsize(fee_sats, transaction_vsize(&temp_tx)),
+        (None, Some(target_rate)) if !config.fee_bump_utxos.is_empty() => {
+            log::info!("手数料バンプ: 手数料率を {} から {} sats/vB に引き上げます。",
+                config.fee_rate_sats_per_vb.map_or_else(|| "未指定".to_string(), |rate| format!("{} sats/vB", rate)), target_rate);
+            target_rate
+        }
+        // feeRateSatsPerVb の指定は validate_fee_mode で確認済み
+        (None, _) => config.fee_rate_sats_per_vb.unwrap_or_default(),
+    };
+
+    if let Some(ceiling) = options.fee_rate_ceiling_sats_per_vb.filter(|ceiling| fee_rate_sats_per_vb > FeeRate::from_sat_per_vb(*ceiling)) {
+        return Err(AppError::FeeRateTooHigh { fee_rate_sats_per_vb, ceiling });
+    }
+    // 手数料0はregtestでは便利だが、メインネットではリレーされずに資金が滞留するだけなので明示的な許可を求める
+    if fee_rate_sats_per_vb == FeeRate::ZERO && cli_network == BitcoinNetwork::Bitcoin && !options.allow_low_fee {
+        return Err(AppError::ZeroFeeRateOnMainnet);
+    }
+    // ダストのおつりはメインネットではリレーされず資金が滞留するだけなので、試験用のネットワークに限る
+    if options.force_change && cli_network == BitcoinNetwork::Bitcoin {
+        return Err(AppError::InputValidation("--force-change はメインネットでは使用できません".to_string()));
+    }
+    if fee_rate_sats_per_vb < MIN_RELAY_FEE_RATE {
+        warn_or_fail(options, &mut warnings, SigningWarning::LowFeeRate { fee_rate_sats_per_vb })?;
+    }
+    let mut first_output_by_script: HashMap<&ScriptBuf, usize> = HashMap::new();
+    for (output_index, (output, output_def)) in outputs.iter().zip(&config.outputs).enumerate() {
+        if let Some(&first_output_index) = first_output_by_script.get(&output.script_pubkey)
+            && !options.allow_address_reuse
+        {
+            warn_or_fail(options, &mut warnings, SigningWarning::AddressReuse {
+                output_index,
+                first_output_index,
+                label: output_def.label.clone(),
+                script_pubkey: output.script_pubkey.clone(),
+            })?;
+        }
+        first_output_by_script.entry(&output.script_pubkey).or_insert(output_index);
+        let is_self_send = output.script_pubkey == change_script_pubkey
+            || processed_utxos.iter().any(|pu| pu.tx_out.script_pubkey == output.script_pubkey);
+        if is_self_send {
+            warn_or_fail(options, &mut warnings, SigningWarning::SelfSend {
+                output_index,
+                label: output_def.label.clone(),
+                script_pubkey: output.script_pubkey.clone(),
+            })?;
+        }
+    }
+
+    let estimated_vsize = transaction_vsize(&temp_tx);
+
+    // 署名前に判定し、リレーできない大きさのトランザクションの署名に時間をかけないようにする
+    let estimated_weight = temp_tx.weight().to_wu();
+    let max_weight = config.max_weight_wu.map_or(MAX_STANDARD_TX_WEIGHT_WU, |max| max.min(MAX_STANDARD_TX_WEIGHT_WU));
+    if estimated_weight > max_weight {
+        return Err(AppError::TransactionTooHeavy { weight: estimated_weight, max: max_weight });
+    }
+
+    // スイープ (受信者出力なし) では全額がおつり出力になるため、それがダスト未満だと出力のないトランザクションになる
+    if config.outputs.is_empty() {
+        let sweep_fee_sats = target_fee_sats(&config, estimated_vsize, fee_rate_sats_per_vb).unwrap_or(u64::MAX);
+        if total_input_value_sats < sweep_fee_sats.saturating_add(dust_threshold_sats) {
+            // スイープ出力がダスト閾値以上に残る最大の手数料率
+            let max_fee_rate = total_input_value_sats
+                .checked_sub(dust_threshold_sats)
+                .map(|spendable| FeeRate::from_fee_and_vsize(spendable, estimated_vsize))
+                .filter(|rate| *rate >= MIN_RELAY_FEE_RATE);
+            match max_fee_rate {
+                Some(_) if config.sweep_auto_lower_fee_rate => {
+                    record_warning(&mut warnings, SigningWarning::SweepFeeRateLowered {
+                        from_sats_per_vb: fee_rate_sats_per_vb,
+                        to_sats_per_vb: MIN_RELAY_FEE_RATE,
+                    });
+                    fee_rate_sats_per_vb = MIN_RELAY_FEE_RATE;
+                }
+                _ => {
+                    let suggestion = match max_fee_rate {
+                        Some(rate) => format!("手数料率を {} sats/vB 以下にしてください (sweepAutoLowerFeeRate で最低リレー手数料率に自動調整できます)", rate),
+                        None => format!("最低リレー手数料率 {} sats/vB でもダスト閾値 {} sats を残せません", MIN_RELAY_FEE_RATE_SATS_PER_VB, dust_threshold_sats),
+                    };
+                    return Err(AppError::SweepOutputBelowDust {
+                        available: total_input_value_sats,
+                        fee: sweep_fee_sats,
+                        suggestion,
+                    });
+                }
+            }
+        }
+    }
+
+    if let (Some(ancestor_fee_sats), Some(ancestor_vsize)) = (config.ancestor_fee_sats, config.ancestor_vsize) {
+        log::info!("祖先パッケージ (手数料 {} sats, {} vB) を含めて手数料率 {} sats/vB を満たすよう手数料を計算します。",
+            ancestor_fee_sats, ancestor_vsize, fee_rate_sats_per_vb);
+    }
+    let total_fee_sats = percent_fee_sats
+        .or_else(|| target_fee_sats(&config, estimated_vsize, fee_rate_sats_per_vb))
+        .and_then(|fee| fee.checked_add(extra_fee_sats))
+        .ok_or_else(|| AppError::InputValidation(format!(
+            "手数料の計算がオーバーフローしました (vsize {} vB, 手数料率 {} sats/vB)", estimated_vsize, fee_rate_sats_per_vb)))?;
+    log::debug!("推定vsize: {} vB, 手数料率: {} sats/vB, 計算された手数料: {} sats", estimated_vsize, fee_rate_sats_per_vb, total_fee_sats);
 
-    if total_input_value_sats < total_recipient_output_value_sats + total_fee_sats {
+    // 他の入力は受信者出力と同額のため、手数料を支払う入力の金額がそのまま手数料とおつりの原資になる
+    if let Some((input_index, value_sats)) = fee_input {
+        if total_fee_sats > value_sats {
+            return Err(AppError::FeeInputInsufficient { input_index, value_sats, fee_sats: total_fee_sats });
+        }
+        log::info!("手数料 {} sats を utxos[{}] ({} sats) から支払い、残り {} sats をおつりにします。",
+            total_fee_sats, input_index, value_sats, value_sats - total_fee_sats);
+    }
+    // 手数料が総供給量を超える場合もここで InputValidation となる
+    let required_sats = checked_add_amount(total_recipient_output_value_sats, total_fee_sats, "受信者出力と手数料")?;
+    if total_input_value_sats < required_sats {
         return Err(AppError::InsufficientFunds {
             available: total_input_value_sats,
-            required: total_recipient_output_value_sats + total_fee_sats,
+            required: required_sats,
             fee: total_fee_sats,
         });
     }
 
-    let change_value_sats = total_input_value_sats - total_recipient_output_value_sats - total_fee_sats;
+    let change_value_sats = total_input_value_sats - required_sats;
     let mut final_outputs = outputs; // 受信者出力
 
-    if change_value_sats >= DUST_THRESHOLD_SATS {
-        log::debug!("おつり発生: {} sats, おつりアドレス: {}", change_value_sats, change_address);
-        final_outputs.push(TxOut {
+    // 境界条件: おつりがダスト閾値ちょうどなら出力を作成し、それ未満は changeDustPolicy に従う
+    // --force-change では 1 sat 以上のおつりをすべて出力に含める
+    let force_change = options.force_change && change_value_sats > 0;
+    let include_below_dust_change = match config.change_dust_policy {
+        _ if force_change => true,
+        ChangeDustPolicy::FoldToFee => false,
+        ChangeDustPolicy::IncludeAnyway => change_value_sats >= config.change_dust_band_min_sats.unwrap_or(1).max(1),
+    };
+
+    // スイープでは全額がおつり出力のため minChangeSats は適用しない
+    let is_sweep = final_outputs.is_empty() && appended_outputs.is_empty();
+    let min_change_sats = config
+        .min_change_sats
+        .filter(|min| !force_change && !is_sweep && change_value_sats >= dust_threshold_sats && change_value_sats < *min);
+
+    // おつり以外の出力数までの位置 (末尾を含む) を指定できる
+    let other_output_count = final_outputs.len() + appended_outputs.len();
+    if let Some(position) = config.change_position.filter(|position| *position > other_output_count) {
+        return Err(field_error("", "changePosition", format!("{} が範囲外です (0 から {} まで)", position, other_output_count)));
+    }
+    let mut change_output = None;
+
+    if let Some(min_change_sats) = min_change_sats {
+        log::info!("おつり {} sats は minChangeSats {} sats 未満のため出力を作らず手数料に含めます (手数料 +{} sats, 合計 {} sats)。",
+            change_value_sats, min_change_sats, change_value_sats, total_fee_sats + change_value_sats);
+    } else if change_value_sats >= dust_threshold_sats || include_below_dust_change {
+        // 明示的に指定された挙動のため strict モードでもエラーにはしない
+        if force_change && (change_value_sats < dust_threshold_sats || config.min_change_sats.is_some_and(|min| change_value_sats < min)) {
+            record_warning(&mut warnings, SigningWarning::ForcedChange {
+                change_sats: change_value_sats,
+                threshold_sats: dust_threshold_sats,
+            });
+        } else if change_value_sats < dust_threshold_sats {
+            record_warning(&mut warnings, SigningWarning::DustChangeIncluded {
+                change_sats: change_value_sats,
+                threshold_sats: dust_threshold_sats,
+            });
+        }
+        if let Some(max) = config.max_outputs.filter(|max| final_outputs.len() >= *max) {
+            return Err(AppError::TooManyOutputs { count: final_outputs.len() + 1, max });
+        }
+        log::debug!("おつり発生: {} sats, おつりscriptPubKey: {}", change_value_sats, change_script_pubkey);
+        change_output = Some((TxOut {
             value: Amount::from_sat(change_value_sats),
-            script_pubkey: change_address.script_pubkey(),
-        });
+            script_pubkey: change_script_pubkey,
+        }, change_bip32_derivation));
     } else if change_value_sats > 0 {
-        log::warn!("おつり {} sats はダスト閾値 {} sats 未満のため手数料に含めます。", change_value_sats, DUST_THRESHOLD_SATS);
+        warn_or_fail(options, &mut warnings, SigningWarning::DustChangeFolded {
+            change_sats: change_value_sats,
+            threshold_sats: dust_threshold_sats,
+        })?;
         // この場合、手数料が実質的に total_fee_sats + change_value_sats となる
     }
+
+    // 出力の位置は手数料 (vsize) に影響しないため、推定に使った並びと異なってもよい
+    let change_index = config.change_position.unwrap_or(final_outputs.len());
+    final_outputs.extend(appended_outputs);
+    output_bip32_derivations.extend(appended_bip32_derivations);
+    let mut output_memos: Vec<Option<String>> =
+        config.outputs.iter().chain(&config.append_outputs).map(|output_def| output_def.memo.clone()).collect();
+    let change_index = change_output.is_some().then_some(change_index);
+    if let (Some(change_index), Some((change_tx_out, change_bip32_derivation))) = (change_index, change_output) {
+        final_outputs.insert(change_index, change_tx_out);
+        output_bip32_derivations.insert(change_index, change_bip32_derivation);
+        output_memos.insert(change_index, None);
+    }
+    if let Some(max) = config.max_outputs.filter(|max| final_outputs.len() > *max) {
+        return Err(AppError::TooManyOutputs { count: final_outputs.len(), max });
+    }
+    if let Some(max_ratio) = options.max_io_ratio {
+        let (inputs, outputs) = (processed_utxos.len(), final_outputs.len());
+        if inputs.max(outputs) as f64 > inputs.min(outputs) as f64 * max_ratio {
+            return Err(AppError::InputOutputRatioExceeded { inputs, outputs, max_ratio });
+        }
+    }
+
+    // 実際の手数料 (ダストの繰り入れなどを含む) に対する検査はすべて署名前に行う
+    let final_output_value_sats: u64 = final_outputs.iter().map(|output| output.value.to_sat()).sum();
+    let actual_fee_sats = total_input_value_sats - final_output_value_sats;
+    if let Some(expected) = options.expected_fee_sats
+        && actual_fee_sats != expected
+    {
+        return Err(AppError::FeeMismatch { expected, actual: actual_fee_sats });
+    }
+    let sent_sats = sent_value_sats(&final_outputs, change_index);
+    if let Some(max_fee_sats) = options.max_fee_ratio.map(|ratio| (sent_sats as f64 * ratio).floor() as u64)
+        && actual_fee_sats > max_fee_sats
+    {
+        warn_or_fail(options, &mut warnings, SigningWarning::HighFeeRatio { fee_sats: actual_fee_sats, sent_sats, max_fee_sats })?;
+    }
+    if let Some(policy) = &options.safe_mode {
+        check_safe_mode(policy, &final_outputs, change_index, actual_fee_sats)?;
+    }
     
     // 署名対象のトランザクションを初期化 
     let mut transaction = Transaction {
-        version: bitcoin::transaction::Version(2),
-        lock_time: LockTime::ZERO,
+        version: TX_VERSION,
+        lock_time,
         input: processed_utxos.iter().map(|pu| TxIn {
             previous_output: pu.out_point,
             script_sig: ScriptBuf::new(), // 署名前は空
@@ -182,123 +1546,1012 @@ pub fn create_and_sign_transaction(
         }).collect(),
         output: final_outputs,
     };
+    let unsigned_transaction = transaction.clone();
+    // 署名済み (skipSigning) の入力はここで確定させる。script_sig / witness は他の入力の署名ハッシュに影響しない
+    for (tx_in, pu) in transaction.input.iter_mut().zip(&processed_utxos) {
+        if let InputSigning::Presigned { script_sig, witness } = &pu.signing {
+            tx_in.script_sig = script_sig.clone();
+            tx_in.witness = witness.clone();
+        }
+    }
+    let mut signed_inputs: Vec<SignedInput> = processed_utxos
+        .iter()
+        .map(|pu| SignedInput {
+            spent_output: pu.tx_out.clone(),
+            public_key: pu.public_key(),
+            sighash_type: pu.sighash_type,
+            sighash: None,
+            bip32_derivation: pu.bip32_derivation.clone(),
+            signature: None,
+            taproot_signature: None,
+            cosigner_signatures: Vec::new(),
+            previous_transaction: pu.previous_transaction.clone(),
+        })
+        .collect();
 
     // --- ここから署名処理の変更 ---
     log::info!("トランザクション署名処理を開始します。");
     let mut signing_infos: Vec<SigningInfo> = Vec::new();
 
     // 1. 署名ハッシュ計算フェーズ
+    if let Some(&out_of_range) = options.sign_inputs.iter().flatten().find(|index| **index >= processed_utxos.len()) {
+        return Err(AppError::InputValidation(format!(
+            "--sign-inputs の入力インデックス {} が範囲外です (入力数 {})", out_of_range, processed_utxos.len())));
+    }
+    let sighash_phase_start = Instant::now();
     // このスコープ内で SighashCache を使用し、transaction を可変借用する
     {
         // SighashCache は署名がまだないトランザクションのコピーまたは参照で初期化
         // SighashCache::new に渡す transaction は、このスコープ内でのみ可変借用される
+        // P2TR の署名ハッシュは全入力の金額とscriptPubKeyにコミットする (BIP341)
+        let prevouts: Vec<TxOut> = processed_utxos.iter().map(|pu| pu.tx_out.clone()).collect();
         let mut sighash_cache = SighashCache::new(&mut transaction);
 
+        let output_count = sighash_cache.transaction().output.len();
         for (input_index, p_utxo) in processed_utxos.iter().enumerate() {
-            log::debug!("入力 {} (txid={}, vout={}) の署名ハッシュ計算を開始します。", input_index, p_utxo.out_point.txid, p_utxo.out_point.vout);
-            let sighash_type = EcdsaSighashType::All;
-            let current_sighash_message: Message;
-
-            // Debug: input_index と script_pubkey の情報
-            println!("DEBUG: Current input_index: {}", input_index);
-            println!("DEBUG: script_pubkey (bytes): {:?}", p_utxo.tx_out.script_pubkey.as_bytes());
-            println!("DEBUG: script_pubkey (hex): {}", p_utxo.tx_out.script_pubkey.to_string()); // to_string()はhexを返すことを期待
-
-            // Debug: is_p2pkh と is_p2wpkh の結果
-            println!("DEBUG: is_p2pkh: {}", p_utxo.tx_out.script_pubkey.is_p2pkh());
-            println!("DEBUG: is_p2wpkh: {}", p_utxo.tx_out.script_pubkey.is_p2wpkh());
-
-            match &p_utxo.tx_out.script_pubkey { // 直接script_pubkeyオブジェクトに対してメソッドを呼ぶ
-                script if script.is_p2pkh() => {
-                    // P2PKHの処理
+            let Some(public_key) = p_utxo.public_key() else {
+                log::debug!("入力 {} は skipSigning のため署名しません。", input_index);
+                continue;
+            };
+            log::debug!("入力 {}{} (txid={}, vout={}) の署名ハッシュ計算を開始します。",
+                input_index, label_suffix(p_utxo.label.as_deref()), p_utxo.out_point.txid, p_utxo.out_point.vout);
+            let sighash_type = p_utxo.sighash_type;
+            log::debug!("入力 {}: script_pubkey={}, sighash_type={}", input_index, p_utxo.tx_out.script_pubkey, sighash_type);
+
+            // SIGHASH_SINGLE は同じインデックスの出力にのみコミットする。対応する出力がない場合、
+            // レガシーでは "SIGHASH_SINGLE bug" (ハッシュ値 1) となり署名が流用可能になるため拒否する
+            let (base_type, anyone_can_pay) = match sighash_type {
+                EcdsaSighashType::SinglePlusAnyoneCanPay => (EcdsaSighashType::Single, true),
+                EcdsaSighashType::AllPlusAnyoneCanPay => (EcdsaSighashType::All, true),
+                EcdsaSighashType::NonePlusAnyoneCanPay => (EcdsaSighashType::None, true),
+                other => (other, false),
+            };
+            if base_type == EcdsaSighashType::Single && input_index >= output_count {
+                return Err(AppError::InputValidation(format!(
+                    "入力 {} は {} ですが、対応する出力 (インデックス {}) がありません (出力数 {})",
+                    input_index, sighash_type, input_index, output_count)));
+            }
+            if anyone_can_pay {
+                log::debug!("入力 {}: ANYONECANPAY のため、この入力のみにコミットします。", input_index);
+            }
+
+            let current_sighash_message: Message = match &p_utxo.tx_out.script_pubkey { // 直接script_pubkeyオブジェクトに対してメソッドを呼ぶ
+                script if script.is_p2pkh() || script.is_p2pk() || script.is_multisig() => {
+                    // P2PKH / P2PK / ベアマルチシグの処理 (いずれもレガシーsighashでscriptPubKeyをscript codeとする)
                     let sighash = sighash_cache.legacy_signature_hash(
                         input_index,
-                        script,
+                        p_utxo.script_code.as_ref().unwrap_or(script),
                         sighash_type.to_u32(),
-                    ).map_err(|e| AppError::IndexError { input_index, source: e })?;
-                    current_sighash_message = Message::from_digest_slice(sighash.as_ref())
-                         .map_err(|e| AppError::SignatureError{input_index, source: bitcoin::ecdsa::Error::Secp256k1(e)})?;
+                    ).map_err(|e| AppError::IndexError { input_index, label: p_utxo.label.clone(), source: e })?;
+                    Message::from_digest_slice(sighash.as_ref())
+                         .map_err(|e| AppError::SignatureError{input_index, label: p_utxo.label.clone(), source: bitcoin::ecdsa::Error::Secp256k1(e)})?
                 },
                 script if script.is_p2wpkh() => {
-                    println!("DEBUG: Matched to P2WPKH branch.");
-                    
-                    // P2WPKHのscriptPubKeyから公開鍵ハッシュを抽出
-                    let pubkey_hash_bytes = script.as_bytes();
-                    if pubkey_hash_bytes.len() != 22 || pubkey_hash_bytes[0] != 0x00 || pubkey_hash_bytes[1] != 0x14 {
-                        return Err(AppError::Internal(format!("P2WPKH script_pubkeyの形式が不正 (input {})", input_index)));
-                    }
-                    let pubkey_hash: [u8; 20] = pubkey_hash_bytes[2..22]
-                        .try_into()
-                        .map_err(|_| AppError::Internal(format!("公開鍵ハッシュのサイズが不正 (input {})", input_index)))?;
-
-                    // P2PKH相当のscript_codeをScriptBuilderで直接構築
-                    // Opcodeを直接プッシュする形式に変更
-                    let script_code = Builder::new()
-                        .push_opcode(OP_DUP) 
-                        .push_opcode(OP_HASH160)
-                        .push_slice(pubkey_hash)
-                        .push_opcode(OP_EQUALVERIFY)
-                        .push_opcode(OP_CHECKSIG)
-                        .into_script(); // ScriptBuf を得る
-
-                    println!("DEBUG: P2WPKH script_code (bytes) directly built: {:?}", script_code.as_bytes());
-                    println!("DEBUG: P2WPKH script_code (hex) directly built: {}", script_code.to_string());
-                    
-                    let sighash = sighash_cache.p2wpkh_signature_hash(
-                        input_index,
-                        &script_code, // ScriptBufの参照を渡す
-                        p_utxo.value,
-                        sighash_type,
-                    ).map_err(|e| AppError::SighashError{input_index, source: e})?;
-                    current_sighash_message = Message::from_digest_slice(sighash.as_ref())
-                        .map_err(|e| AppError::SignatureError{input_index, source: bitcoin::ecdsa::Error::Secp256k1(e)})?;
+                    // BIP143: script code (P2PKH相当) は scriptPubKey から p2wpkh_signature_hash 内部で導出される。
+                    // scriptCodeHex の指定時は p2wsh_signature_hash でその script code をそのまま使う
+                    let sighash = match &p_utxo.script_code {
+                        Some(script_code) => sighash_cache.p2wsh_signature_hash(input_index, script_code, p_utxo.value, sighash_type)
+                            .map_err(|e| AppError::IndexError { input_index, label: p_utxo.label.clone(), source: e })?,
+                        None => sighash_cache.p2wpkh_signature_hash(
+                            input_index,
+                            script,
+                            p_utxo.value,
+                            sighash_type,
+                        ).map_err(|e| AppError::SighashError{input_index, label: p_utxo.label.clone(), source: e})?,
+                    };
+                    Message::from_digest_slice(sighash.as_ref())
+                        .map_err(|e| AppError::SignatureError{input_index, label: p_utxo.label.clone(), source: bitcoin::ecdsa::Error::Secp256k1(e)})?
+                },
+                script if script.is_p2tr() => {
+                    let sighash = sighash_cache
+                        .taproot_key_spend_signature_hash(input_index, &Prevouts::All(&prevouts), TapSighashType::Default)
+                        .map_err(|e| AppError::TaprootSighashError { input_index, label: p_utxo.label.clone(), source: e })?;
+                    Message::from(sighash)
                 },
                 _script => {
                     return Err(AppError::UnknownScriptType {
                         script_hex: _script.to_string(), // スクリプトの16進数表現を渡す
                     });
                 } 
+            };
+            signed_inputs[input_index].sighash = Some(current_sighash_message);
+            // ベアマルチシグは鍵ごとに署名する (scriptPubKey の公開鍵の順)
+            let signing_keys = match &p_utxo.signing {
+                InputSigning::Key { private_key, .. } => vec![(*private_key, public_key)],
+                InputSigning::Multisig { keys } => keys.clone(),
+                InputSigning::External { .. } | InputSigning::Presigned { .. } => Vec::new(),
+            };
+            if !options.sighash_only && options.sign_inputs.as_ref().is_none_or(|indices| indices.contains(&input_index)) {
+                for (private_key, public_key) in signing_keys {
+                    signing_infos.push(SigningInfo {
+                        input_index,
+                        sighash_message: current_sighash_message,
+                        private_key,
+                        public_key,
+                        script_type: p_utxo.script_type,       // ScriptTypeがCopyかCloneであることを確認
+                        sighash_type: p_utxo.sighash_type,
+                        label: p_utxo.label.clone(),
+                    });
+                }
             }
-            signing_infos.push(SigningInfo {
-                input_index,
-                sighash_message: current_sighash_message,
-                private_key: p_utxo.private_key.clone(), // PrivateKeyはClone
-                public_key: p_utxo.public_key,         // PublicKeyはCopy
-                script_type: p_utxo.script_type,       // ScriptTypeがCopyかCloneであることを確認
-            });
+            on_progress(SigningProgress::HashComputed { input_index, total: processed_utxos.len() });
         }
     } // ここで sighash_cache が破棄され、transaction の可変借用が解放される
 
+    if options.sign_inputs.is_some() {
+        // 明示的に指定された挙動のため strict モードでもエラーにはしない
+        let unsigned_inputs: Vec<usize> = (0..processed_utxos.len())
+            .filter(|input_index| {
+                matches!(processed_utxos[*input_index].signing, InputSigning::Key { .. } | InputSigning::Multisig { .. })
+                    && !signing_infos.iter().any(|info| info.input_index == *input_index)
+            })
+            .collect();
+        if !unsigned_inputs.is_empty() {
+            record_warning(&mut warnings, SigningWarning::PartiallySigned { unsigned_inputs });
+        }
+    }
+    let sighash_phase_elapsed = sighash_phase_start.elapsed();
     log::info!("全ての署名ハッシュの計算が完了しました。署名生成と適用を開始します。");
 
     // 2. 署名生成と適用フェーズ
+    let signing_phase_start = Instant::now();
+    let total_inputs = signing_infos.len();
+    if let Some(audit_dir) = &options.audit_dir {
+        std::fs::create_dir_all(audit_dir)?;
+    }
     for info in signing_infos {
-        log::debug!("入力 {} ({:?}) の署名生成と適用を開始します。", info.input_index, info.script_type);
+        let input_label = label_suffix(info.label.as_deref());
+        log::debug!("入力 {}{} ({:?}) の署名生成と適用を開始します。", info.input_index, input_label, info.script_type);
 
-        let secp_sig = secp.sign_ecdsa(&info.sighash_message, &info.private_key.inner);
-        let btc_ecdsa_sig = bitcoin::ecdsa::Signature::from_slice(&secp_sig.serialize_compact())
-            .map_err(|e| AppError::SignatureError { input_index: info.input_index, source: e })?;
+        // 署名方式は script_type だけで決まる。同じ鍵でも P2TR は鍵パスのSchnorr署名、それ以外はECDSA
+        match info.script_type.signature_scheme() {
+            Some(SignatureScheme::Schnorr) => apply_schnorr_signature(secp, &info, &mut transaction, &mut signed_inputs[info.input_index]),
+            Some(SignatureScheme::Ecdsa) => apply_ecdsa_signature(secp, &info, &mut transaction, &mut signed_inputs[info.input_index])?,
+            None => {
+                return Err(AppError::Internal(format!(
+                    "{:?} の入力 {}{} は署名対象になりません", info.script_type, info.input_index, input_label)));
+            }
+        }
+        if let Some(audit_dir) = &options.audit_dir {
+            // ゼロ埋めにより、ファイル名の辞書順が入力番号順になる
+            let audit_path = audit_dir.join(format!("input-{:04}.hex", info.input_index));
+            std::fs::write(&audit_path, bitcoin::consensus::encode::serialize_hex(&transaction))?;
+            log::debug!("入力 {}{} 署名後のトランザクションを {:?} に書き出しました。", info.input_index, input_label, audit_path);
+        }
+        on_progress(SigningProgress::Signed { input_index: info.input_index, total: total_inputs });
+    }
+    let signing_phase_elapsed = signing_phase_start.elapsed();
+    log::info!("全ての入力の署名が完了しました。");
+    check_inputs_signed(&transaction, &processed_utxos, options)?;
+    verify_witnesses(&transaction, &processed_utxos, &signed_inputs)?;
 
-        match info.script_type {
-            ScriptType::P2PKH => {
-                let final_script_sig = bitcoin::script::Builder::new()
-                    .push_slice(PushBytesBuf::try_from(btc_ecdsa_sig.to_vec())
-                        .map_err(|_| AppError::Internal(format!("P2PKH署名のPushBytes変換失敗 (input {})", info.input_index)))?)
-                    .push_key(&info.public_key)
-                    .into_script();
-                transaction.input[info.input_index].script_sig = final_script_sig;
-                log::debug!("入力 {} (P2PKH) の署名適用完了。", info.input_index);
+    if options.metrics {
+        let summary = format!(
+            "メトリクス: 入力 {} 件, 出力 {} 件 / UTXO処理 {:?}, 署名ハッシュ計算 {:?}, 署名 {:?}, 合計 {:?}",
+            transaction.input.len(),
+            transaction.output.len(),
+            utxo_phase_elapsed,
+            sighash_phase_elapsed,
+            signing_phase_elapsed,
+            utxo_phase_start.elapsed(),
+        );
+        log::info!("{}", summary);
+        eprintln!("{}", summary);
+    }
+
+    let total_output_value_sats: u64 = transaction.output.iter().map(|output| output.value.to_sat()).sum();
+    Ok(SignedTransaction {
+        fee_sats: total_input_value_sats - total_output_value_sats,
+        fee_rate_sats_per_vb,
+        transaction,
+        unsigned_transaction,
+        warnings,
+        inputs: signed_inputs,
+        output_bip32_derivations,
+        output_memos,
+        dust_relay_fee_sats_per_kvb,
+        change_index,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use serde_json::{json, Value};
+
+    // 秘密鍵 1 のテストネットWIF (公開鍵は secp256k1 の生成元 0279be66...1798)
+    const WIF: &str = "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA";
+    const PUBLIC_KEY_HEX: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const P2WPKH_SCRIPT_HEX: &str = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+    const RECIPIENT_ADDRESS: &str = "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r";
+    const CHANGE_ADDRESS: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+    fn utxo(txid_byte: u8, script_pubkey_hex: &str, value_sats: u64) -> Value {
+        json!({
+            "txid": format!("{:064x}", txid_byte),
+            "vout": 0,
+            "scriptPubkeyHex": script_pubkey_hex,
+            "valueSats": value_sats,
+            "privateKeyWif": WIF,
+        })
+    }
+
+    // 受信者へ 60,000 sats を 1 sat/vB で送る設定。fields の項目で上書きする
+    fn config_with(utxos: Vec<Value>, fields: Value) -> InputConfig {
+        let mut config = json!({
+            "utxos": utxos,
+            "outputs": [{"address": RECIPIENT_ADDRESS, "valueSats": 60_000}],
+            "feeRateSatsPerVb": 1,
+            "changeAddress": CHANGE_ADDRESS,
+        });
+        if let (Some(config), Value::Object(fields)) = (config.as_object_mut(), fields) {
+            config.extend(fields);
+        }
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn sign(config: InputConfig) -> Result<SignedTransaction, AppError> {
+        create_and_sign_transaction(config, BitcoinNetwork::Testnet, &Secp256k1::new(), &mut KeyCache::default(), &SigningOptions::default(), |_| {})
+    }
+
+    fn change_value_sats(signed: &SignedTransaction) -> Option<u64> {
+        signed.change_index.map(|index| signed.transaction.output[index].value.to_sat())
+    }
+
+    // 各 P2WPKH 入力の witness の署名が、その位置の入力として計算した署名ハッシュで検証できることを確認する
+    fn assert_p2wpkh_signatures_valid(signed: &SignedTransaction) {
+        let secp = Secp256k1::verification_only();
+        let mut cache = SighashCache::new(&signed.transaction);
+        for (input_index, (tx_in, signed_input)) in signed.transaction.input.iter().zip(&signed.inputs).enumerate() {
+            let signature = bitcoin::ecdsa::Signature::from_slice(&tx_in.witness.to_vec()[0]).unwrap();
+            let public_key = PublicKey::from_slice(&tx_in.witness.to_vec()[1]).unwrap();
+            let sighash = cache
+                .p2wpkh_signature_hash(input_index, &signed_input.spent_output.script_pubkey, signed_input.spent_output.value, signature.sighash_type)
+                .unwrap();
+            secp.verify_ecdsa(&Message::from(sighash), &signature.signature, &public_key.inner).unwrap();
+        }
+    }
+
+    #[test]
+    fn change_at_dust_threshold_is_kept_and_below_follows_change_dust_policy() {
+        let change_script = Address::from_str(CHANGE_ADDRESS).unwrap().assume_checked().script_pubkey();
+        let threshold_sats = dust_threshold_sats(&change_script, DEFAULT_DUST_RELAY_FEE_SATS_PER_KVB);
+        assert_eq!(threshold_sats, 294);
+        // 手数料はおつり出力を含めて見積もるため、おつりの金額によらない
+        let fee_sats = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 100_000)], json!({}))).unwrap().fee_sats;
+        let at_threshold_sats = 60_000 + fee_sats + threshold_sats;
+
+        let at_threshold = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, at_threshold_sats)], json!({}))).unwrap();
+        assert_eq!(change_value_sats(&at_threshold), Some(threshold_sats));
+
+        let folded = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, at_threshold_sats - 1)], json!({}))).unwrap();
+        assert_eq!(folded.change_index, None);
+        assert_eq!(folded.fee_sats, fee_sats + threshold_sats - 1);
+
+        let included = sign(config_with(
+            vec![utxo(1, P2WPKH_SCRIPT_HEX, at_threshold_sats - 1)],
+            json!({"changeDustPolicy": "includeAnyway"}),
+        ))
+        .unwrap();
+        assert_eq!(change_value_sats(&included), Some(threshold_sats - 1));
+
+        let below_band = sign(config_with(
+            vec![utxo(1, P2WPKH_SCRIPT_HEX, at_threshold_sats - 1)],
+            json!({"changeDustPolicy": "includeAnyway", "changeDustBandMinSats": threshold_sats}),
+        ))
+        .unwrap();
+        assert_eq!(below_band.change_index, None);
+    }
+
+    #[test]
+    fn force_change_keeps_dust_change_with_a_warning_and_is_refused_on_mainnet() {
+        let force = SigningOptions { force_change: true, ..SigningOptions::default() };
+        let sign_forced = |value_sats: u64, fields: Value, network: BitcoinNetwork| {
+            create_and_sign_transaction(
+                config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, value_sats)], fields), network, &Secp256k1::new(), &mut KeyCache::default(), &force, |_| {})
+        };
+        let fee_sats = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 100_000)], json!({}))).unwrap().fee_sats;
+
+        // ダスト閾値未満 (1 sat) のおつりも foldToFee や minChangeSats によらず出力に含め、ForcedChange を記録する
+        for fields in [json!({}), json!({"minChangeSats": 10_000})] {
+            let signed = sign_forced(60_000 + fee_sats + 1, fields, BitcoinNetwork::Testnet).unwrap();
+            assert_eq!(change_value_sats(&signed), Some(1));
+            assert!(signed.warnings.iter().any(|warning| matches!(warning, SigningWarning::ForcedChange { change_sats: 1, .. })));
+        }
+
+        // おつりが 0 の場合は出力を作らない
+        let exact = sign_forced(60_000 + fee_sats, json!({}), BitcoinNetwork::Testnet).unwrap();
+        assert_eq!(exact.change_index, None);
+
+        // メインネットでは他の検証を通る設定でも拒否する
+        let mut mainnet_utxo = utxo(1, P2WPKH_SCRIPT_HEX, 100_000);
+        mainnet_utxo["privateKeyWif"] = json!("KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn");
+        let mainnet = json!({
+            "outputs": [{"address": "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", "valueSats": 60_000}],
+            "changeAddress": "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+        });
+        let mainnet_config = config_with(vec![mainnet_utxo], mainnet);
+        let refused = create_and_sign_transaction(mainnet_config.clone(), BitcoinNetwork::Bitcoin, &Secp256k1::new(), &mut KeyCache::default(), &force, |_| {});
+        assert!(matches!(refused, Err(AppError::InputValidation(message)) if message.contains("--force-change")));
+        create_and_sign_transaction(mainnet_config, BitcoinNetwork::Bitcoin, &Secp256k1::new(), &mut KeyCache::default(), &SigningOptions::default(), |_| {})
+            .unwrap();
+    }
+
+    // 1入力の P2WPKH トランザクションに署名し、検証用に入力の ProcessedUtxo を組み立てる
+    fn signed_p2wpkh_with_processed_utxo() -> (SignedTransaction, ProcessedUtxo) {
+        let signed = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 100_000)], json!({}))).unwrap();
+        let private_key = PrivateKey::from_wif(WIF).unwrap();
+        let input = &signed.inputs[0];
+        let processed_utxo = ProcessedUtxo {
+            out_point: signed.transaction.input[0].previous_output,
+            tx_out: input.spent_output.clone(),
+            signing: InputSigning::Key { private_key: Redacted(private_key), public_key: input.public_key.unwrap() },
+            script_type: ScriptType::P2WPKH,
+            sequence: signed.transaction.input[0].sequence,
+            value: input.spent_output.value,
+            sighash_type: input.sighash_type,
+            label: None,
+            bip32_derivation: None,
+            previous_transaction: None,
+            script_code: None,
+            required_lock_time: None,
+        };
+        (signed, processed_utxo)
+    }
+
+    #[test]
+    fn tampered_p2wpkh_witnesses_are_reported_as_malformed() {
+        let (signed, processed_utxo) = signed_p2wpkh_with_processed_utxo();
+        let processed_utxos = [processed_utxo];
+        verify_witnesses(&signed.transaction, &processed_utxos, &signed.inputs).unwrap();
+
+        let signature = signed.transaction.input[0].witness.nth(0).unwrap().to_vec();
+        let public_key = signed.transaction.input[0].witness.nth(1).unwrap().to_vec();
+        let ecdsa_signature = bitcoin::ecdsa::Signature::from_slice(&signature).unwrap();
+        // s を n - s に置き換えた high-S の署名 (検証は通るが第三者が作れる)
+        let mut compact = ecdsa_signature.signature.serialize_compact();
+        let order = secp256k1::constants::CURVE_ORDER;
+        let mut borrow = 0i16;
+        for i in (32..64).rev() {
+            let difference = i16::from(order[i - 32]) - i16::from(compact[i]) - borrow;
+            compact[i] = difference.rem_euclid(256) as u8;
+            borrow = i16::from(difference < 0);
+        }
+        let high_s = bitcoin::ecdsa::Signature {
+            signature: secp256k1::ecdsa::Signature::from_compact(&compact).unwrap(),
+            sighash_type: ecdsa_signature.sighash_type,
+        };
+        let mut wrong_sighash = signature.clone();
+        *wrong_sighash.last_mut().unwrap() = EcdsaSighashType::Single as u8;
+        let uncompressed_public_key = PublicKey::from_slice(&public_key).unwrap().inner.serialize_uncompressed().to_vec();
+
+        let cases: [(&str, Vec<Vec<u8>>); 5] = [
+            ("要素数", vec![signature.clone()]),
+            ("DER", vec![signature[1..].to_vec(), public_key.clone()]),
+            ("sighash type", vec![wrong_sighash, public_key.clone()]),
+            ("low-S", vec![high_s.to_vec(), public_key.clone()]),
+            ("33バイト", vec![signature.clone(), uncompressed_public_key]),
+        ];
+        for (expected_reason, witness) in cases {
+            let mut tampered = signed.transaction.clone();
+            tampered.input[0].witness = Witness::from_slice(&witness);
+            match verify_witnesses(&tampered, &processed_utxos, &signed.inputs) {
+                Err(AppError::MalformedWitness { input_index: 0, reason }) => assert!(reason.contains(expected_reason), "{}", reason),
+                other => panic!("{}: MalformedWitness になりませんでした: {:?}", expected_reason, other),
             }
-            ScriptType::P2WPKH => {
-                let mut final_witness = bitcoin::Witness::new();
-                final_witness.push(btc_ecdsa_sig.to_vec());
-                final_witness.push(info.public_key.to_bytes());
-                transaction.input[info.input_index].witness = final_witness;
-                log::debug!("入力 {} (P2WPKH) の署名適用完了。", info.input_index);
+        }
+
+        // レガシーの script_sig に署名を移した形も検出する
+        let mut with_script_sig = signed.transaction.clone();
+        with_script_sig.input[0].script_sig = ScriptBuf::from_bytes(vec![0x51]);
+        assert!(matches!(
+            verify_witnesses(&with_script_sig, &processed_utxos, &signed.inputs),
+            Err(AppError::MalformedWitness { input_index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn requires_locktime_raises_lock_time_to_the_max_and_rejects_mixed_units() {
+        let cltv_utxo = |txid_byte: u8, requires_locktime: u32| {
+            let mut utxo = utxo(txid_byte, P2WPKH_SCRIPT_HEX, 100_000);
+            utxo["requiresLocktime"] = json!(requires_locktime);
+            utxo
+        };
+
+        // 最も大きい要求に引き上げ、final な sequence は CLTV が効くよう 0xfffffffe にする
+        let signed = sign(config_with(vec![cltv_utxo(1, 800_000), cltv_utxo(2, 800_100)], json!({"lockTimeHeight": 700_000}))).unwrap();
+        assert_eq!(signed.transaction.lock_time, LockTime::from_height(800_100).unwrap());
+        assert!(signed.transaction.input.iter().all(|tx_in| tx_in.sequence == Sequence::ENABLE_LOCKTIME_NO_RBF));
+        // 設定の nLockTime が要求以上ならそのまま使う
+        let signed = sign(config_with(vec![cltv_utxo(1, 800_000)], json!({"lockTimeHeight": 900_000}))).unwrap();
+        assert_eq!(signed.transaction.lock_time, LockTime::from_height(900_000).unwrap());
+
+        assert!(matches!(
+            sign(config_with(vec![cltv_utxo(1, 800_000), cltv_utxo(2, 1_700_000_000)], json!({}))),
+            Err(AppError::IncompatibleLockTimes { .. })
+        ));
+        assert!(matches!(
+            sign(config_with(vec![cltv_utxo(1, 800_000)], json!({"lockTimeTimestamp": 1_700_000_000}))),
+            Err(AppError::IncompatibleLockTimes { .. })
+        ));
+        // 明示された final な sequence は書き換えない
+        let mut explicit_final = cltv_utxo(1, 800_000);
+        explicit_final["sequence"] = json!(0xffffffffu32);
+        assert!(matches!(
+            sign(config_with(vec![explicit_final], json!({}))),
+            Err(AppError::FieldValidation { path, .. }) if path == "utxos[0].sequence"
+        ));
+    }
+
+    #[test]
+    fn inputs_left_unsigned_by_a_skipped_branch_are_reported_as_missing_signatures() {
+        let (signed, mut processed_utxo) = signed_p2wpkh_with_processed_utxo();
+        let options = SigningOptions::default();
+        check_inputs_signed(&signed.transaction, std::slice::from_ref(&processed_utxo), &options).unwrap();
+
+        let mut unsigned = signed.transaction.clone();
+        unsigned.input[0].witness = Witness::new();
+        processed_utxo.label = Some("cold".to_string());
+        let processed_utxos = [processed_utxo];
+        assert!(matches!(
+            check_inputs_signed(&unsigned, &processed_utxos, &options),
+            Err(AppError::MissingSignature { input_index: 0, location: "witness", label: Some(label), .. }) if label == "cold"
+        ));
+        // 署名しない指定の入力や署名ハッシュのみの出力は対象外
+        check_inputs_signed(&unsigned, &processed_utxos, &SigningOptions { sign_inputs: Some(vec![1]), ..SigningOptions::default() }).unwrap();
+        check_inputs_signed(&unsigned, &processed_utxos, &SigningOptions { sighash_only: true, ..SigningOptions::default() }).unwrap();
+
+        // レガシー入力は witness ではなく script_sig を確認する
+        let [mut legacy] = processed_utxos;
+        legacy.script_type = ScriptType::P2PKH;
+        assert!(matches!(
+            check_inputs_signed(&signed.transaction, &[legacy], &options),
+            Err(AppError::MissingSignature { input_index: 0, location: "script_sig", .. })
+        ));
+    }
+
+    #[test]
+    fn max_io_ratio_counts_change_and_applies_in_both_directions() {
+        let sign_with_ratio = |config: InputConfig, max_io_ratio: f64| {
+            let options = SigningOptions { max_io_ratio: Some(max_io_ratio), ..SigningOptions::default() };
+            create_and_sign_transaction(config, BitcoinNetwork::Testnet, &Secp256k1::new(), &mut KeyCache::default(), &options, |_| {})
+        };
+        // 4入力に対して受信者とおつりの2出力
+        let many_inputs = || config_with((1..=4).map(|txid_byte| utxo(txid_byte, P2WPKH_SCRIPT_HEX, 50_000)).collect(), json!({}));
+        assert_eq!(sign_with_ratio(many_inputs(), 2.0).unwrap().transaction.output.len(), 2);
+        assert!(matches!(
+            sign_with_ratio(many_inputs(), 1.5),
+            Err(AppError::InputOutputRatioExceeded { inputs: 4, outputs: 2, .. })
+        ));
+
+        // 1入力に対して受信者3つとおつりの4出力
+        let outputs: Vec<Value> = (0..3).map(|_| json!({"address": RECIPIENT_ADDRESS, "valueSats": 10_000})).collect();
+        let many_outputs = || config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 100_000)], json!({"outputs": outputs}));
+        assert!(matches!(
+            sign_with_ratio(many_outputs(), 3.0),
+            Err(AppError::InputOutputRatioExceeded { inputs: 1, outputs: 4, .. })
+        ));
+        sign_with_ratio(many_outputs(), 4.0).unwrap();
+    }
+
+    #[test]
+    fn fee_input_pays_the_whole_fee_while_other_inputs_map_one_to_one() {
+        let utxos = |fee_input_sats: u64| vec![
+            utxo(1, P2WPKH_SCRIPT_HEX, 30_000),
+            utxo(2, P2WPKH_SCRIPT_HEX, fee_input_sats),
+            utxo(3, P2WPKH_SCRIPT_HEX, 40_000),
+        ];
+        let fields = |second_output_sats: u64| json!({
+            "feeInputIndex": 1,
+            "outputs": [
+                {"address": RECIPIENT_ADDRESS, "valueSats": 30_000},
+                {"address": RECIPIENT_ADDRESS, "valueSats": second_output_sats},
+            ],
+        });
+
+        // 受信者はそれぞれ対応する入力と同額を受け取り、手数料を引いた手数料入力の残りがおつりになる
+        let signed = sign(config_with(utxos(20_000), fields(40_000))).unwrap();
+        let recipient_sats: Vec<u64> = signed.transaction.output.iter().enumerate()
+            .filter(|(index, _)| Some(*index) != signed.change_index)
+            .map(|(_, output)| output.value.to_sat())
+            .collect();
+        assert_eq!(recipient_sats, [30_000, 40_000]);
+        assert_eq!(change_value_sats(&signed), Some(20_000 - signed.fee_sats));
+
+        assert!(matches!(
+            sign(config_with(utxos(100), fields(40_000))),
+            Err(AppError::FeeInputInsufficient { input_index: 1, value_sats: 100, .. })
+        ));
+        assert!(matches!(
+            sign(config_with(utxos(20_000), fields(39_999))),
+            Err(AppError::FieldValidation { path, .. }) if path == "outputs[1].valueSats"
+        ));
+        let mut out_of_range = fields(40_000);
+        out_of_range["feeInputIndex"] = json!(3);
+        let mut with_append_outputs = fields(40_000);
+        with_append_outputs["appendOutputs"] = json!([{"address": RECIPIENT_ADDRESS, "valueSats": 1_000}]);
+        for invalid in [out_of_range, with_append_outputs] {
+            assert!(matches!(
+                sign(config_with(utxos(20_000), invalid)),
+                Err(AppError::FieldValidation { path, .. }) if path == "feeInputIndex"
+            ));
+        }
+    }
+
+    #[test]
+    fn change_to_p2tr_address_is_a_v1_witness_program_above_taproot_dust() {
+        let secp = Secp256k1::new();
+        let internal_key = XOnlyPublicKey::from(PublicKey::from_str(PUBLIC_KEY_HEX).unwrap().inner);
+        let change_address = Address::p2tr(&secp, internal_key, None, bitcoin::KnownHrp::Testnets);
+        assert!(change_address.to_string().starts_with("tb1p"));
+        let change_script = change_address.script_pubkey();
+        assert!(change_script.is_p2tr());
+        assert_eq!(change_script.witness_version(), Some(bitcoin::WitnessVersion::V1));
+        assert_eq!(change_script, ScriptBuf::new_p2tr(&secp, internal_key, None));
+        // P2TR の出力は P2WPKH (294) より大きく P2PKH (546) より小さい 330 sats がダスト閾値
+        let threshold_sats = dust_threshold_sats(&change_script, DEFAULT_DUST_RELAY_FEE_SATS_PER_KVB);
+        assert_eq!(threshold_sats, 330);
+
+        let fields = json!({"changeAddress": change_address.to_string()});
+        let signed = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 100_000)], fields.clone())).unwrap();
+        let change_index = signed.change_index.unwrap();
+        assert_eq!(signed.transaction.output[change_index].script_pubkey, change_script);
+        assert!(change_value_sats(&signed).unwrap() >= threshold_sats);
+        assert_p2wpkh_signatures_valid(&signed);
+
+        // 閾値ちょうどのおつりは残り、1 sat 少なければ手数料に回る
+        let at_threshold_sats = 60_000 + signed.fee_sats + threshold_sats;
+        let at_threshold = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, at_threshold_sats)], fields.clone())).unwrap();
+        assert_eq!(change_value_sats(&at_threshold), Some(threshold_sats));
+        let folded = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, at_threshold_sats - 1)], fields)).unwrap();
+        assert_eq!(folded.change_index, None);
+    }
+
+    #[test]
+    fn script_hash_inputs_without_script_code_are_rejected() {
+        // P2WSH (OP_0 <32バイト>) と P2SH (OP_HASH160 <20バイト> OP_EQUAL)
+        let p2wsh = ScriptBuf::new_p2wsh(&bitcoin::WScriptHash::all_zeros()).to_hex_string();
+        let p2sh = ScriptBuf::new_p2sh(&bitcoin::ScriptHash::all_zeros()).to_hex_string();
+        for (script_hex, kind) in [(&p2wsh, "P2WSH"), (&p2sh, "P2SH")] {
+            match sign(config_with(vec![utxo(1, script_hex, 100_000)], json!({}))) {
+                Err(AppError::InputValidation(message)) => assert!(message.contains(kind) && message.contains("scriptCodeHex"), "{}", message),
+                other => panic!("{} の入力で InputValidation になりません: {:?}", kind, other.map(|signed| signed.transaction)),
             }
+            // scriptCodeHex を指定した場合は検証を通過し、スクリプトタイプの判定に進む
+            let mut with_script_code = utxo(1, script_hex, 100_000);
+            with_script_code["scriptCodeHex"] = json!("51");
+            assert!(matches!(
+                sign(config_with(vec![with_script_code], json!({}))),
+                Err(AppError::UnknownScriptType { .. })
+            ));
         }
     }
-    log::info!("全ての入力の署名が完了しました。");
 
-    Ok(transaction)
-}
\ No newline at end of file
+    #[test]
+    fn field_validation_errors_carry_the_json_path() {
+        let field_path = |config: InputConfig| match sign(config) {
+            Err(AppError::FieldValidation { path, .. }) => path,
+            other => panic!("FieldValidation になりません: {:?}", other.map(|signed| signed.transaction)),
+        };
+
+        let mut bad_txid = utxo(2, P2WPKH_SCRIPT_HEX, 50_000);
+        bad_txid["txid"] = json!("not-a-txid");
+        assert_eq!(field_path(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 50_000), bad_txid], json!({}))), "utxos[1].txid");
+
+        assert_eq!(field_path(config_with(vec![utxo(1, "zz", 100_000)], json!({}))), "utxos[0].scriptPubkeyHex");
+
+        let outputs = json!({"outputs": [
+            {"address": RECIPIENT_ADDRESS, "valueSats": 30_000},
+            {"address": "not-an-address", "valueSats": 30_000},
+        ]});
+        assert_eq!(field_path(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 100_000)], outputs)), "outputs[1].address");
+
+        // トップレベルの項目は項目名のみ
+        let lock_times = json!({"lockTimeHeight": 100, "lockTimeTimestamp": 600_000_000});
+        assert_eq!(field_path(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 100_000)], lock_times)), "lockTimeTimestamp");
+    }
+
+    #[test]
+    fn uncompressed_wif_for_p2wpkh_input_is_refused() {
+        // 同じ秘密鍵 1 の非圧縮WIF。圧縮形式に読み替えれば scriptPubKey と一致するが、読み替えずにエラーにする
+        let mut private_key = PrivateKey::from_wif(WIF).unwrap();
+        private_key.compressed = false;
+        let mut uncompressed_input = utxo(2, P2WPKH_SCRIPT_HEX, 50_000);
+        uncompressed_input["privateKeyWif"] = json!(private_key.to_wif());
+        uncompressed_input["label"] = json!("cold");
+        let result = sign(config_with(vec![utxo(1, P2WPKH_SCRIPT_HEX, 50_000), uncompressed_input], json!({})));
+        match result {
+            Err(AppError::UncompressedKeyForSegwit { input_index, label }) => {
+                assert_eq!(input_index, 1);
+                assert_eq!(label.as_deref(), Some("cold"));
+            }
+            other => panic!("UncompressedKeyForSegwit を期待しましたが {:?} でした", other.map(|signed| signed.transaction.compute_txid())),
+        }
+    }
+
+    #[test]
+    fn p2pk_input_is_signed_with_signature_only_script_sig() {
+        let p2pk_script_hex = format!("21{}ac", PUBLIC_KEY_HEX);
+        let signed = sign(config_with(vec![utxo(1, &p2pk_script_hex, 100_000)], json!({}))).unwrap();
+        let tx_in = &signed.transaction.input[0];
+        assert!(tx_in.witness.is_empty());
+
+        // script_sig は署名のプッシュ1つのみ (公開鍵は scriptPubKey に含まれる)
+        let pushes: Vec<_> = tx_in.script_sig.instructions().map(Result::unwrap).collect();
+        assert_eq!(pushes.len(), 1);
+        let signature = bitcoin::ecdsa::Signature::from_slice(pushes[0].push_bytes().unwrap().as_bytes()).unwrap();
+        assert_eq!(signature.sighash_type, EcdsaSighashType::All);
+
+        // 署名ハッシュはレガシー形式で、scriptPubKey をスクリプトコードとする
+        let script_pubkey = ScriptBuf::from_hex(&p2pk_script_hex).unwrap();
+        let sighash = SighashCache::new(&signed.transaction)
+            .legacy_signature_hash(0, &script_pubkey, EcdsaSighashType::All.to_u32())
+            .unwrap();
+        let public_key = PublicKey::from_str(PUBLIC_KEY_HEX).unwrap();
+        Secp256k1::verification_only()
+            .verify_ecdsa(&Message::from(sighash), &signature.signature, &public_key.inner)
+            .unwrap();
+    }
+
+    #[test]
+    fn bare_multisig_script_sig_holds_op_0_and_signatures_in_key_order() {
+        // 秘密鍵 1, 2, 3 の 2-of-3。鍵1と鍵3で署名する
+        let secp = Secp256k1::new();
+        let keys: Vec<(String, PublicKey)> = test_keys(3)
+            .into_iter()
+            .map(|(wif, _)| {
+                let private_key = PrivateKey::from_wif(&wif).unwrap();
+                (wif, private_key.public_key(&secp))
+            })
+            .collect();
+        let multisig_script = bitcoin::script::Builder::new()
+            .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_2)
+            .push_key(&keys[0].1)
+            .push_key(&keys[1].1)
+            .push_key(&keys[2].1)
+            .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_3)
+            .push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let multisig_utxo = |wifs: &[&String]| {
+            let mut input = utxo(1, &multisig_script.to_hex_string(), 100_000);
+            input.as_object_mut().unwrap().remove("privateKeyWif");
+            input["privateKeyWifs"] = json!(wifs);
+            input
+        };
+        let signed = sign(config_with(vec![multisig_utxo(&[&keys[0].0, &keys[2].0])], json!({}))).unwrap();
+        let tx_in = &signed.transaction.input[0];
+        assert!(tx_in.witness.is_empty());
+
+        // OP_0 (OP_CHECKMULTISIG が余分にポップする要素) の後に、scriptPubKey の鍵の順で署名が並ぶ
+        let pushes: Vec<_> = tx_in.script_sig.instructions().map(Result::unwrap).collect();
+        assert_eq!(pushes.len(), 3);
+        assert!(pushes[0].push_bytes().unwrap().is_empty());
+        let sighash = SighashCache::new(&signed.transaction)
+            .legacy_signature_hash(0, &multisig_script, EcdsaSighashType::All.to_u32())
+            .unwrap();
+        for (push, public_key) in pushes[1..].iter().zip([keys[0].1, keys[2].1]) {
+            let signature = bitcoin::ecdsa::Signature::from_slice(push.push_bytes().unwrap().as_bytes()).unwrap();
+            secp.verify_ecdsa(&Message::from(sighash), &signature.signature, &public_key.inner).unwrap();
+        }
+        assert_eq!(signed.inputs[0].cosigner_signatures.len(), 1);
+        assert_eq!(signed.inputs[0].cosigner_signatures[0].0, keys[2].1);
+
+        // WIFの数が必要署名数と異なる場合、scriptPubKey と逆順の場合はエラー
+        for wifs in [vec![&keys[0].0], vec![&keys[2].0, &keys[0].0]] {
+            assert!(matches!(
+                sign(config_with(vec![multisig_utxo(&wifs)], json!({}))),
+                Err(AppError::FieldValidation { path, .. }) if path.starts_with("utxos[0].privateKeyWifs")
+            ));
+        }
+    }
+
+    #[test]
+    fn p2tr_input_is_signed_with_schnorr_for_the_bip86_output_key() {
+        let secp = Secp256k1::new();
+        let internal_key = XOnlyPublicKey::from(PublicKey::from_str(PUBLIC_KEY_HEX).unwrap().inner);
+        let p2tr_script = ScriptBuf::new_p2tr(&secp, internal_key, None);
+        let signed = sign(config_with(
+            vec![utxo(1, &p2tr_script.to_hex_string(), 40_000), utxo(2, &p2tr_script.to_hex_string(), 60_000)],
+            json!({}),
+        ))
+        .unwrap();
+
+        let prevouts: Vec<TxOut> = signed.inputs.iter().map(|input| input.spent_output.clone()).collect();
+        let output_key = XOnlyPublicKey::from_slice(&p2tr_script.as_bytes()[2..]).unwrap();
+        assert_ne!(output_key, internal_key);
+        let mut cache = SighashCache::new(&signed.transaction);
+        for (input_index, tx_in) in signed.transaction.input.iter().enumerate() {
+            // sighash type のバイトを付けない64バイトの署名1要素
+            assert!(tx_in.script_sig.is_empty());
+            assert_eq!(tx_in.witness.len(), 1);
+            assert_eq!(tx_in.witness[0].len(), 64);
+            let signature = bitcoin::taproot::Signature::from_slice(&tx_in.witness[0]).unwrap();
+            assert_eq!(signed.inputs[input_index].taproot_signature, Some(signature));
+            // 署名ハッシュは全入力の金額とscriptPubKeyにコミットし、調整後の出力鍵で検証できる
+            let sighash = cache
+                .taproot_key_spend_signature_hash(input_index, &Prevouts::All(&prevouts), TapSighashType::Default)
+                .unwrap();
+            secp.verify_schnorr(&signature.signature, &Message::from(sighash), &output_key).unwrap();
+        }
+    }
+
+    #[test]
+    fn same_key_signs_p2wpkh_with_ecdsa_and_p2tr_with_schnorr() {
+        let secp = Secp256k1::new();
+        let internal_key = XOnlyPublicKey::from(PublicKey::from_str(PUBLIC_KEY_HEX).unwrap().inner);
+        let p2tr_script = ScriptBuf::new_p2tr(&secp, internal_key, None);
+        let signed = sign(config_with(
+            vec![utxo(1, P2WPKH_SCRIPT_HEX, 50_000), utxo(2, &p2tr_script.to_hex_string(), 50_000)],
+            json!({}),
+        ))
+        .unwrap();
+
+        // P2WPKH: DERのECDSA署名と公開鍵の2要素
+        let p2wpkh_in = &signed.transaction.input[0];
+        assert_eq!(p2wpkh_in.witness.len(), 2);
+        let ecdsa_signature = bitcoin::ecdsa::Signature::from_slice(&p2wpkh_in.witness[0]).unwrap();
+        assert_eq!(signed.inputs[0].signature, Some(ecdsa_signature));
+        assert!(signed.inputs[0].taproot_signature.is_none());
+
+        // P2TR: sighash type のバイトを付けない64バイトのSchnorr署名1要素
+        let p2tr_in = &signed.transaction.input[1];
+        assert!(p2tr_in.script_sig.is_empty());
+        assert_eq!(p2tr_in.witness.len(), 1);
+        assert_eq!(p2tr_in.witness[0].len(), 64);
+        let schnorr_signature = bitcoin::taproot::Signature::from_slice(&p2tr_in.witness[0]).unwrap();
+        assert_eq!(schnorr_signature.sighash_type, TapSighashType::Default);
+        assert_eq!(signed.inputs[1].taproot_signature, Some(schnorr_signature));
+        assert!(signed.inputs[1].signature.is_none());
+
+        // ECDSA署名はWIFの公開鍵、Schnorr署名は全入力にコミットする署名ハッシュで調整後の出力鍵に対して検証できる
+        let mut cache = SighashCache::new(&signed.transaction);
+        let ecdsa_sighash = cache
+            .p2wpkh_signature_hash(0, &signed.inputs[0].spent_output.script_pubkey, signed.inputs[0].spent_output.value, EcdsaSighashType::All)
+            .unwrap();
+        let public_key = PublicKey::from_str(PUBLIC_KEY_HEX).unwrap();
+        secp.verify_ecdsa(&Message::from(ecdsa_sighash), &ecdsa_signature.signature, &public_key.inner).unwrap();
+        let prevouts: Vec<TxOut> = signed.inputs.iter().map(|input| input.spent_output.clone()).collect();
+        let taproot_sighash = cache
+            .taproot_key_spend_signature_hash(1, &Prevouts::All(&prevouts), TapSighashType::Default)
+            .unwrap();
+        let output_key = XOnlyPublicKey::from_slice(&p2tr_script.as_bytes()[2..]).unwrap();
+        assert_ne!(output_key, internal_key);
+        secp.verify_schnorr(&schnorr_signature.signature, &Message::from(taproot_sighash), &output_key).unwrap();
+    }
+
+    #[test]
+    fn single_anyonecanpay_commits_only_to_own_input_and_matching_output() {
+        let mut single_acp = utxo(1, P2WPKH_SCRIPT_HEX, 100_000);
+        single_acp["sighashType"] = json!("SINGLE|ANYONECANPAY");
+        let signed = sign(config_with(vec![single_acp, utxo(2, P2WPKH_SCRIPT_HEX, 50_000)], json!({}))).unwrap();
+        let signed_input = &signed.inputs[0];
+        assert_eq!(signed_input.sighash_type, EcdsaSighashType::SinglePlusAnyoneCanPay);
+        assert_eq!(signed_input.signature.unwrap().sighash_type, EcdsaSighashType::SinglePlusAnyoneCanPay);
+
+        let script_pubkey = ScriptBuf::from_hex(P2WPKH_SCRIPT_HEX).unwrap();
+        let sighash_of = |tx: &Transaction| {
+            let sighash = SighashCache::new(tx)
+                .p2wpkh_signature_hash(0, &script_pubkey, Amount::from_sat(100_000), EcdsaSighashType::SinglePlusAnyoneCanPay)
+                .unwrap();
+            Message::from(sighash)
+        };
+        assert_eq!(Some(sighash_of(&signed.transaction)), signed_input.sighash);
+
+        // 他の入力・対応しない出力を変更・追加しても署名ハッシュは変わらない
+        let mut assembled = signed.transaction.clone();
+        assembled.input[1].previous_output.vout = 7;
+        assembled.input.push(TxIn { previous_output: OutPoint::null(), ..assembled.input[1].clone() });
+        assembled.output[1].value = Amount::from_sat(1_000);
+        assembled.output.push(assembled.output[1].clone());
+        assert_eq!(Some(sighash_of(&assembled)), signed_input.sighash);
+
+        // 同じインデックスの出力を変更すると署名ハッシュが変わる
+        let mut tampered = signed.transaction.clone();
+        tampered.output[0].value = Amount::from_sat(59_999);
+        assert_ne!(Some(sighash_of(&tampered)), signed_input.sighash);
+    }
+
+    #[test]
+    fn sighash_none_warning_is_recorded_only_for_none_types() {
+        let sighash_none_warnings = |signed: &SignedTransaction| -> Vec<EcdsaSighashType> {
+            signed
+                .warnings
+                .iter()
+                .filter_map(|warning| match warning {
+                    SigningWarning::SighashNone { sighash_type, .. } => Some(*sighash_type),
+                    _ => None,
+                })
+                .collect()
+        };
+        for (name, expected) in [("NONE", EcdsaSighashType::None), ("NONE|ANYONECANPAY", EcdsaSighashType::NonePlusAnyoneCanPay)] {
+            let mut none_input = utxo(1, P2WPKH_SCRIPT_HEX, 100_000);
+            none_input["sighashType"] = json!(name);
+            let signed = sign(config_with(vec![none_input], json!({}))).unwrap();
+            assert_eq!(sighash_none_warnings(&signed), [expected], "sighashType={}", name);
+            assert!(signed.warnings.iter().any(|warning| warning.code() == "sighash-none"));
+        }
+
+        // ALL (明示・省略とも) では警告しない
+        let mut all_input = utxo(1, P2WPKH_SCRIPT_HEX, 100_000);
+        all_input["sighashType"] = json!("ALL");
+        for utxos in [vec![all_input], vec![utxo(1, P2WPKH_SCRIPT_HEX, 100_000)]] {
+            let signed = sign(config_with(utxos, json!({}))).unwrap();
+            assert!(sighash_none_warnings(&signed).is_empty());
+        }
+
+        // strictモードではエラーとして中断する
+        let mut none_input = utxo(1, P2WPKH_SCRIPT_HEX, 100_000);
+        none_input["sighashType"] = json!("NONE");
+        let strict = SigningOptions { strict: true, ..SigningOptions::default() };
+        let result = create_and_sign_transaction(
+            config_with(vec![none_input], json!({})), BitcoinNetwork::Testnet, &Secp256k1::new(), &mut KeyCache::default(), &strict, |_| {});
+        assert!(matches!(result, Err(AppError::StrictModeViolation(_))));
+    }
+
+    #[test]
+    fn sighash_none_signatures_survive_output_changes_and_all_signatures_do_not() {
+        const P2PKH_SCRIPT_HEX: &str = "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac";
+        let secp = Secp256k1::verification_only();
+        // 出力をすべて別の宛先・金額に置き換え、各入力の署名を再計算した署名ハッシュで検証する (P2WPKH は BIP143、P2PKH はレガシー)
+        let verifies_after_output_change = |signed: &SignedTransaction| -> Vec<bool> {
+            let mut modified = signed.transaction.clone();
+            modified.output = vec![TxOut { value: Amount::from_sat(1_000), script_pubkey: ScriptBuf::new_op_return([0u8; 4]) }];
+            let mut cache = SighashCache::new(&modified);
+            signed
+                .inputs
+                .iter()
+                .enumerate()
+                .map(|(input_index, signed_input)| {
+                    let signature = signed_input.signature.unwrap();
+                    let script_pubkey = &signed_input.spent_output.script_pubkey;
+                    let message = if script_pubkey.is_p2wpkh() {
+                        Message::from(cache.p2wpkh_signature_hash(input_index, script_pubkey, signed_input.spent_output.value, signature.sighash_type).unwrap())
+                    } else {
+                        Message::from(cache.legacy_signature_hash(input_index, script_pubkey, signature.sighash_type.to_u32()).unwrap())
+                    };
+                    secp.verify_ecdsa(&message, &signature.signature, &signed_input.public_key.unwrap().inner).is_ok()
+                })
+                .collect()
+        };
+        let signed_with = |name: &str| {
+            let mut p2wpkh_input = utxo(1, P2WPKH_SCRIPT_HEX, 50_000);
+            let mut p2pkh_input = utxo(2, P2PKH_SCRIPT_HEX, 50_000);
+            p2wpkh_input["sighashType"] = json!(name);
+            p2pkh_input["sighashType"] = json!(name);
+            sign(config_with(vec![p2wpkh_input, p2pkh_input], json!({}))).unwrap()
+        };
+
+        // NONE は出力にコミットしないため、出力を変えても両方の署名が有効なまま (警告の理由)
+        assert_eq!(verifies_after_output_change(&signed_with("NONE")), [true, true]);
+        // ALL の署名は同じ変更で無効になる
+        assert_eq!(verifies_after_output_change(&signed_with("ALL")), [false, false]);
+    }
+
+    #[test]
+    fn input_order_is_applied_before_signing() {
+        let utxos = || vec![utxo(3, P2WPKH_SCRIPT_HEX, 10_000), utxo(1, P2WPKH_SCRIPT_HEX, 50_000), utxo(2, P2WPKH_SCRIPT_HEX, 30_000)];
+        // 入力の順序を utxo() に渡した txid_byte の並びで返す
+        let txid_bytes = |signed: &SignedTransaction| -> Vec<u8> {
+            signed.transaction.input.iter().map(|tx_in| tx_in.previous_output.txid.to_byte_array()[0]).collect()
+        };
+        for (input_order, expected) in [("config", [3, 1, 2]), ("bip69", [1, 2, 3]), ("valueDesc", [1, 2, 3])] {
+            let signed = sign(config_with(utxos(), json!({"inputOrder": input_order}))).unwrap();
+            assert_eq!(txid_bytes(&signed), expected, "inputOrder={}", input_order);
+            assert_p2wpkh_signatures_valid(&signed);
+        }
+        // 未指定時は設定の順序のまま
+        assert_eq!(txid_bytes(&sign(config_with(utxos(), json!({}))).unwrap()), [3, 1, 2]);
+        // valueDesc は金額の降順で、同額の入力は設定の順序を保つ
+        let tied = vec![utxo(2, P2WPKH_SCRIPT_HEX, 40_000), utxo(3, P2WPKH_SCRIPT_HEX, 20_000), utxo(1, P2WPKH_SCRIPT_HEX, 40_000)];
+        assert_eq!(txid_bytes(&sign(config_with(tied, json!({"inputOrder": "valueDesc"}))).unwrap()), [2, 1, 3]);
+    }
+
+    #[test]
+    fn lock_time_fields_are_checked_at_the_500_000_000_boundary() {
+        assert_eq!(resolve_lock_time(Some(499_999_999), None).unwrap(), LockTime::from_consensus(499_999_999));
+        assert!(resolve_lock_time(Some(500_000_000), None).is_err());
+        assert!(resolve_lock_time(Some(500_000_001), None).is_err());
+
+        assert!(resolve_lock_time(None, Some(499_999_999)).is_err());
+        let at_boundary = resolve_lock_time(None, Some(500_000_000)).unwrap();
+        assert!(at_boundary.is_block_time());
+        assert_eq!(at_boundary.to_consensus_u32(), 500_000_000);
+        let above_boundary = resolve_lock_time(None, Some(500_000_001)).unwrap();
+        assert!(above_boundary.is_block_time());
+        assert_eq!(above_boundary.to_consensus_u32(), 500_000_001);
+
+        assert!(resolve_lock_time(Some(499_999_999), Some(500_000_000)).is_err());
+        assert_eq!(resolve_lock_time(None, None).unwrap(), LockTime::ZERO);
+    }
+
+    #[test]
+    fn vsize_matches_core_for_known_transactions() {
+        // ウェイト (WU) が4の倍数でない場合は切り上げる
+        assert_eq!(vsize_from_weight(440), 110);
+        assert_eq!(vsize_from_weight(441), 111);
+        assert_eq!(vsize_from_weight(443), 111);
+        assert_eq!(vsize_from_weight(444), 111);
+
+        // メインネットのトランザクションと、Bitcoin Core (getrawtransaction / decoderawtransaction) が報告する weight と vsize
+        let known_transactions = [
+            // P2PKH 1入力 (witnessなし)
+            ("0100000001c336895d9fa674f8b1e294fd006b1ac8266939161600e04788c515089991b50a030000006a47304402204213769e823984b31dcb7104f2c99279e74249eacd4246dabcf2575f85b365aa02200c3ee89c84344ae326b637101a92448664a8d39a009c8ad5d147c752cbe112970121028b1b44b4903c9103c07d5a23e3c7cf7aeb0ba45ddbd2cfdce469ab197381f195fdffffff040000000000000000536a4c5058325bb7b7251cf9e36cac35d691bd37431eeea426d42cbdecca4db20794f9a4030e6cb5211fabf887642bcad98c9994430facb712da8ae5e12c9ae5ff314127d33665000bb26c0067000bb0bf00322a50c300000000000017a9145ca04fdc0a6d2f4e3f67cfeb97e438bb6287725f8750c30000000000001976a91423086a767de0143523e818d4273ddfe6d9e4bbcc88acc8465003000000001976a914c95cbacc416f757c65c942f9b6b8a20038b9b12988ac00000000", 1396, 349),
+            // P2WPKH 1入力
+            ("020000000001018a763b78d3e17acea0625bf9e52b0dc1beb2241b2502185348ba8ff4a253176e0100000000ffffffff0280d725000000000017a914c07ed639bd46bf7087f2ae1dfde63b815a5f8b488767fda20300000000160014869ec8520fa2801c8a01bfdd2e82b19833cd0daf02473044022016243edad96b18c78b545325aaff80131689f681079fb107a67018cb7fb7830e02205520dae761d89728f73f1a7182157f6b5aecf653525855adb7ccb998c8e6143b012103b9489bde92afbcfa85129a82ffa512897105d1a27ad9806bded27e0532fc84e700000000", 565, 142),
+            // P2SH-P2WPKH 1入力
+            ("010000000001018aec7e0729ba5a2d284303c89b3f397e92d54472a225d28eb0ae2fa65a7d1a2e02000000171600145ad5db65f313ab76726eb178c2fd8f21f977838dfdffffff03102700000000000017a914dca89e03ba124c2c70e55533f91100f2d9dab04587f2d71d00000000001976a91442a34f4b0a65bc81278b665d37fd15910d261ec588ac292c3b000000000017a91461978dcebd0db2da0235c1ba3e8087f9fd74c57f87024730440220009226f8def30a8ffa53e55ca5d71a72a64cd20ae7f3112562e3413bd0731d2c0220360d220435e67eef7f2bf0258d1dded706e3824f06d961ba9eeaed300b16c2cc012103180cff753d3e4ee1aa72b2b0fd72ce75956d04f4c19400a3daed0b18c3ab831e00000000", 797, 200),
+            // P2TR 1入力 (鍵パス)
+            ("01000000000101b5cee87f1a60915c38bb0bc26aaf2b67be2b890bbc54bb4be1e40272e0d2fe0b0000000000ffffffff025529000000000000225120106daad8a5cb2e6fc74783714273bad554a148ca2d054e7a19250e9935366f3033760000000000002200205e6d83c44f57484fd2ef2a62b6d36cdcd6b3e06b661e33fd65588a28ad0dbe060141df9d1bfce71f90d68bf9e9461910b3716466bfe035c7dbabaa7791383af6c7ef405a3a1f481488a91d33cd90b098d13cb904323a3e215523aceaa04e1bb35cdb0100000000", 617, 155),
+        ];
+        for (tx_hex, weight_wu, core_vsize) in known_transactions {
+            let tx: Transaction = bitcoin::consensus::encode::deserialize_hex(tx_hex).unwrap();
+            assert_eq!(tx.weight().to_wu(), weight_wu, "{}", tx.compute_txid());
+            assert_eq!(transaction_vsize(&tx), core_vsize, "{}", tx.compute_txid());
+        }
+    }
+
+    // 秘密鍵 1..=count のテストネットWIFと、対応する P2WPKH の scriptPubKey (hex)
+    fn test_keys(count: u8) -> Vec<(String, String)> {
+        let secp = Secp256k1::signing_only();
+        (1..=count)
+            .map(|n| {
+                let mut secret = [0u8; 32];
+                secret[31] = n;
+                let private_key = PrivateKey::new(secp256k1::SecretKey::from_slice(&secret).unwrap(), BitcoinNetwork::Testnet);
+                let public_key = bitcoin::CompressedPublicKey::from_private_key(&secp, &private_key).unwrap();
+                (private_key.to_wif(), ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()).to_hex_string())
+            })
+            .collect()
+    }
+
+    // index 番目のトランザクション用に、keys を順に使う2入力の設定を作る
+    fn shared_key_config(keys: &[(String, String)], index: usize) -> InputConfig {
+        let utxos = (0..2)
+            .map(|input| {
+                let (wif, script_hex) = &keys[(index * 2 + input) % keys.len()];
+                json!({"txid": format!("{:064x}", index * 2 + input + 1), "vout": 0, "scriptPubkeyHex": script_hex, "valueSats": 50_000, "privateKeyWif": wif})
+            })
+            .collect();
+        config_with(utxos, json!({}))
+    }
+
+    #[test]
+    fn key_cache_is_shared_across_transactions() {
+        let keys = test_keys(3);
+        let secp = Secp256k1::new();
+        let mut key_cache = KeyCache::default();
+        for index in 0..4 {
+            let signed = create_and_sign_transaction(shared_key_config(&keys, index), BitcoinNetwork::Testnet, &secp, &mut key_cache, &SigningOptions::default(), |_| {}).unwrap();
+            assert_p2wpkh_signatures_valid(&signed);
+        }
+        // 8入力で使った鍵は3種類のみ
+        assert_eq!(key_cache.keys.len(), 3);
+    }
+
+    // 数種類の鍵を共有する1000件のトランザクションの署名時間を、鍵のキャッシュを共有する場合としない場合で比較する:
+    // cargo test --release -- --ignored bench_key_cache --nocapture
+    #[test]
+    #[ignore]
+    fn bench_key_cache() {
+        const TRANSACTIONS: usize = 1000;
+        let keys = test_keys(4);
+        let configs = || (0..TRANSACTIONS).map(|index| shared_key_config(&keys, index));
+        let secp = Secp256k1::new();
+        let options = SigningOptions::default();
+
+        let start = Instant::now();
+        for config in configs() {
+            create_and_sign_transaction(config, BitcoinNetwork::Testnet, &secp, &mut KeyCache::default(), &options, |_| {}).unwrap();
+        }
+        let uncached = start.elapsed();
+
+        let start = Instant::now();
+        let mut key_cache = KeyCache::default();
+        for config in configs() {
+            create_and_sign_transaction(config, BitcoinNetwork::Testnet, &secp, &mut key_cache, &options, |_| {}).unwrap();
+        }
+        let cached = start.elapsed();
+        println!("{} 件 ({} 種類の鍵): キャッシュなし {:?}, 共有キャッシュ {:?}", TRANSACTIONS, keys.len(), uncached, cached);
+    }
+}
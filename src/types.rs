@@ -1,11 +1,23 @@
-use bitcoin::{OutPoint, TxOut, PrivateKey, PublicKey, Sequence, script::ScriptBuf, Amount};
-use crate::error::AppError;
+use crate::redact::Redacted;
+use bitcoin::{absolute::LockTime, bip32::KeySource, opcodes::{Class, ClassifyContext}, script::{Instruction, Script}, WitnessProgram, WitnessVersion, secp256k1::{self, Message}, OutPoint, TxOut, PrivateKey, PublicKey, Sequence, script::ScriptBuf, Amount, Transaction, Witness, sighash::EcdsaSighashType};
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::error::{label_suffix, AppError};
+use crate::fee_rate::FeeRate;
 
 // #[derive(Debug, Clone, PartialEq, Eq)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)] // <- Copy と Clone を追加 (他に必要なトレイトも適宜)
 pub enum ScriptType {
     P2PKH,
     P2WPKH,
+    P2PK, // <pubkey> OP_CHECKSIG。script_sig は署名のみ
+    // m <pubkey>... n OP_CHECKMULTISIG (P2SHで包まないもの)。script_sig は OP_0 と m 個の署名
+    BareMultisig { required: u8, total: u8 },
+    // v1 の32バイトのwitnessプログラム。鍵パス (BIP86、スクリプトツリーなし) でSchnorr署名する
+    P2TR,
+    // 将来のwitnessバージョン (Taprootでないv1、v2以降)。署名はできないが skipSigning の入力としては使える
+    UnknownWitness { version: WitnessVersion, program: WitnessProgram },
     // 他のタイプも追加可能
 }
 
@@ -15,22 +27,307 @@ impl ScriptType {
             Ok(ScriptType::P2PKH)
         } else if script.is_p2wpkh() {
             Ok(ScriptType::P2WPKH)
+        } else if script.is_p2pk() {
+            Ok(ScriptType::P2PK)
+        } else if let Some((required, public_keys)) = bare_multisig_keys(script) {
+            let total = u8::try_from(public_keys.len())
+                .map_err(|_| AppError::UnknownScriptType { script_hex: script.to_hex_string() })?;
+            Ok(ScriptType::BareMultisig { required, total })
+        } else if script.is_p2tr() {
+            Ok(ScriptType::P2TR)
+        } else if let Some(version) = script.witness_version()
+            && version != WitnessVersion::V0
+        {
+            // witness_version() が Some なら先頭2バイト (バージョン, プッシュ長) の後ろがプログラム
+            let program = WitnessProgram::new(version, &script.as_bytes()[2..])
+                .map_err(|_| AppError::UnknownScriptType { script_hex: script.to_hex_string() })?;
+            Ok(ScriptType::UnknownWitness { version, program })
         }
-        // is_p2sh(), is_p2wsh(), is_v0_p2tr() なども将来的に対応可能
+        // is_p2sh(), is_p2wsh() なども将来的に対応可能
         else {
             Err(AppError::UnknownScriptType { script_hex: script.to_hex_string() })
         }
     }
+
+    // 入力の署名方式。鍵ではなくスクリプトタイプだけで決まるため、同じ鍵を複数のタイプで使っても混ざらない。
+    // 署名できない UnknownWitness は None
+    pub fn signature_scheme(&self) -> Option<SignatureScheme> {
+        match self {
+            ScriptType::P2PKH | ScriptType::P2WPKH | ScriptType::P2PK | ScriptType::BareMultisig { .. } => Some(SignatureScheme::Ecdsa),
+            ScriptType::P2TR => Some(SignatureScheme::Schnorr),
+            ScriptType::UnknownWitness { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignatureScheme {
+    Ecdsa,   // DER署名 + sighash type のバイト (レガシー / BIP143)
+    Schnorr, // BIP340 の64バイト署名 (BIP341 の鍵パス、SIGHASH_DEFAULT)
+}
+
+// OP_1 から OP_16 が表す数値
+fn pushnum_value(instruction: &Instruction) -> Option<u8> {
+    match instruction.opcode()?.classify(ClassifyContext::Legacy) {
+        Class::PushNum(value) if (1..=16).contains(&value) => u8::try_from(value).ok(),
+        _ => None,
+    }
+}
+
+/// ベアマルチシグのscriptPubKeyから必要署名数と公開鍵 (スクリプト内の順) を取り出す。形式が異なる場合や
+/// 公開鍵として解釈できない要素がある場合は None。
+pub fn bare_multisig_keys(script: &Script) -> Option<(u8, Vec<PublicKey>)> {
+    if !script.is_multisig() {
+        return None;
+    }
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let required = pushnum_value(instructions.first()?)?;
+    let public_keys = instructions
+        .iter()
+        .filter_map(Instruction::push_bytes)
+        .map(|bytes| PublicKey::from_slice(bytes.as_bytes()).ok())
+        .collect::<Option<Vec<_>>>()?;
+    (usize::from(required) <= public_keys.len()).then_some((required, public_keys))
 }
 
+// 入力の署名方法
+#[derive(Debug, Clone)]
+pub enum InputSigning {
+    // WIFの秘密鍵で署名する
+    Key { private_key: Redacted<PrivateKey>, public_key: PublicKey },
+    // 外部の署名者が署名する (compute-sighashes)。秘密鍵は持たない
+    External { public_key: PublicKey },
+    // ベアマルチシグ: privateKeyWifs の鍵 (scriptPubKey の公開鍵の順) でそれぞれ署名する
+    Multisig { keys: Vec<(Redacted<PrivateKey>, PublicKey)> },
+    // skipSigning: 他者が署名済みの script_sig / witness をそのまま使う
+    Presigned { script_sig: ScriptBuf, witness: Witness },
+}
 
 #[derive(Debug)]
 pub struct ProcessedUtxo {
     pub out_point: OutPoint,
     pub tx_out: TxOut, // 元の value と script_pubkey を含む
-    pub private_key: PrivateKey,
-    pub public_key: PublicKey,
+    pub signing: InputSigning,
     pub script_type: ScriptType,
     pub sequence: Sequence,
     pub value: Amount, // u64 から Amount に変更 (Sighash計算にAmount型が必要なため)
-}
\ No newline at end of file
+    pub sighash_type: EcdsaSighashType,
+    pub label: Option<String>,
+    pub bip32_derivation: Option<Bip32Derivation>,
+    // prevTxHex で指定された前トランザクション (txidと参照先の出力は照合済み)
+    pub previous_transaction: Option<Transaction>,
+    // scriptCodeHex で指定された署名ハッシュ用の script code
+    pub script_code: Option<ScriptBuf>,
+    // requiresLocktime (OP_CHECKLOCKTIMEVERIFY) が要求する nLockTime の下限
+    pub required_lock_time: Option<LockTime>,
+}
+
+// PSBTの bip32_derivation に格納する (公開鍵, (マスターフィンガープリント, 導出パス))
+pub type Bip32Derivation = (secp256k1::PublicKey, KeySource);
+
+impl ProcessedUtxo {
+    // 署名済み (skipSigning) の入力では None。ベアマルチシグでは最初の鍵
+    pub fn public_key(&self) -> Option<PublicKey> {
+        match &self.signing {
+            InputSigning::Key { public_key, .. } | InputSigning::External { public_key } => Some(*public_key),
+            InputSigning::Multisig { keys } => keys.first().map(|(_, public_key)| *public_key),
+            InputSigning::Presigned { .. } => None,
+        }
+    }
+}
+
+// 署名処理の進捗をライブラリ利用者へ通知するためのイベント
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SigningProgress {
+    // 入力 input_index の署名ハッシュ計算が完了した (total は入力総数)
+    HashComputed { input_index: usize, total: usize },
+    // 入力 input_index への署名適用が完了した
+    Signed { input_index: usize, total: usize },
+}
+
+// 署名処理中に検出された注意事項。ログに出すだけでなく呼び出し元へ返す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SigningWarning {
+    // ダスト閾値未満のおつりを手数料に繰り入れた
+    DustChangeFolded { change_sats: u64, threshold_sats: u64 },
+    // changeDustPolicy=includeAnyway によりダスト閾値未満のおつりを出力に含めた
+    DustChangeIncluded { change_sats: u64, threshold_sats: u64 },
+    // --force-change によりダスト閾値または minChangeSats 未満のおつりを出力に含めた
+    ForcedChange { change_sats: u64, threshold_sats: u64 },
+    // 受信者出力の金額がダスト閾値未満 (ノードにリレーされない)
+    DustOutput { output_index: usize, label: Option<String>, value_sats: u64, threshold_sats: u64 },
+    // 非圧縮公開鍵でP2PKH入力を署名する
+    UncompressedKey { outpoint: OutPoint, label: Option<String> },
+    // UTXO個別の sequence が defaultSequence を上書きした
+    SequenceOverride { outpoint: OutPoint, label: Option<String>, sequence: u32, default_sequence: u32 },
+    // sequence の相対ロックタイム (ブロック数) が confirmations ではまだ満たされない (次のブロックに含められない)
+    RelativeLockTimeNotMet { outpoint: OutPoint, label: Option<String>, required_blocks: u16, confirmations: u32 },
+    // 受信者出力がおつり先または入力と同じscriptPubKeyに送金している
+    SelfSend { output_index: usize, label: Option<String>, script_pubkey: ScriptBuf },
+    // 2つの受信者出力が同じscriptPubKeyに送金している (設定ミスの可能性、プライバシー上も不利)
+    AddressReuse { output_index: usize, first_output_index: usize, label: Option<String>, script_pubkey: ScriptBuf },
+    // --sign-inputs により未署名のまま残した入力がある (すべて署名されるまでブロードキャストできない)
+    PartiallySigned { unsigned_inputs: Vec<usize> },
+    // 手数料が送金額に対して max_fee_ratio を超えている (手数料率や金額の指定ミスの可能性)
+    HighFeeRatio { fee_sats: u64, sent_sats: u64, max_fee_sats: u64 },
+    // 手数料率が最低リレー手数料 (1 sat/vB) 未満
+    LowFeeRate { fee_rate_sats_per_vb: FeeRate },
+    // スイープ出力をダスト閾値以上に保つため手数料率を引き下げた
+    SweepFeeRateLowered { from_sats_per_vb: FeeRate, to_sats_per_vb: FeeRate },
+    // scriptPubKeyのタイプが鍵の圧縮形式から想定されるタイプ (圧縮: P2WPKH, 非圧縮: P2PKH) と異なる
+    ScriptTypeMismatch { outpoint: OutPoint, label: Option<String>, script_type: ScriptType, likely_type: ScriptType },
+    // SIGHASH_NONE の署名はどの出力にもコミットしない (トランザクションを受け取った第三者が送金先と金額を変更できる)
+    SighashNone { outpoint: OutPoint, label: Option<String>, sighash_type: EcdsaSighashType },
+}
+
+impl SigningWarning {
+    /// 監視システムなどで警告の種類を判別するための安定したコード (--warnings-json)。表示メッセージと異なり変更しない
+    pub fn code(&self) -> &'static str {
+        match self {
+            SigningWarning::DustChangeFolded { .. } => "dust-change-folded",
+            SigningWarning::DustChangeIncluded { .. } => "dust-change-included",
+            SigningWarning::ForcedChange { .. } => "forced-change",
+            SigningWarning::DustOutput { .. } => "dust-output",
+            SigningWarning::UncompressedKey { .. } => "uncompressed-key",
+            SigningWarning::SequenceOverride { .. } => "sequence-override",
+            SigningWarning::RelativeLockTimeNotMet { .. } => "relative-lock-time-not-met",
+            SigningWarning::SelfSend { .. } => "self-send",
+            SigningWarning::AddressReuse { .. } => "address-reuse",
+            SigningWarning::PartiallySigned { .. } => "partially-signed",
+            SigningWarning::HighFeeRatio { .. } => "high-fee-ratio",
+            SigningWarning::LowFeeRate { .. } => "low-fee-rate",
+            SigningWarning::SweepFeeRateLowered { .. } => "sweep-fee-rate-lowered",
+            SigningWarning::ScriptTypeMismatch { .. } => "script-type-mismatch",
+            SigningWarning::SighashNone { .. } => "sighash-none",
+        }
+    }
+}
+
+impl fmt::Display for SigningWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SigningWarning::DustChangeFolded { change_sats, threshold_sats } =>
+                write!(f, "おつり {} sats はダスト閾値 {} sats 未満のため手数料に含めます。", change_sats, threshold_sats),
+            SigningWarning::DustChangeIncluded { change_sats, threshold_sats } =>
+                write!(f, "おつり {} sats はダスト閾値 {} sats 未満ですが、changeDustPolicy=includeAnyway のため出力に含めます。", change_sats, threshold_sats),
+            SigningWarning::ForcedChange { change_sats, threshold_sats } =>
+                write!(f, "--force-change により、おつり {} sats を出力に含めます (ダスト閾値 {} sats)。ダスト出力を含むトランザクションは非標準です。", change_sats, threshold_sats),
+            SigningWarning::DustOutput { output_index, label, value_sats, threshold_sats } =>
+                write!(f, "出力 {}{} の金額 {} sats はダスト閾値 {} sats 未満のため、ノードにリレーされない可能性があります", output_index, label_suffix(label.as_deref()), value_sats, threshold_sats),
+            SigningWarning::UncompressedKey { outpoint, label } =>
+                write!(f, "UTXO {}{} は非圧縮公開鍵でP2PKHを署名します (scriptPubKeyが非圧縮鍵のハッシュであることを前提とします)", outpoint, label_suffix(label.as_deref())),
+            SigningWarning::SequenceOverride { outpoint, label, sequence, default_sequence } =>
+                write!(f, "UTXO {}{} の sequence {} が defaultSequence {} を上書きします", outpoint, label_suffix(label.as_deref()), sequence, default_sequence),
+            SigningWarning::RelativeLockTimeNotMet { outpoint, label, required_blocks, confirmations } =>
+                write!(f, "UTXO {}{} の相対ロックタイム {} ブロックは確認数 {} ではまだ満たされていません (あと {} ブロック必要です)",
+                    outpoint, label_suffix(label.as_deref()), required_blocks, confirmations, u32::from(*required_blocks) - confirmations),
+            SigningWarning::SelfSend { output_index, label, script_pubkey } =>
+                write!(f, "受信者出力 {}{} はおつり先または入力と同じscriptPubKey ({}) への送金です", output_index, label_suffix(label.as_deref()), script_pubkey),
+            SigningWarning::AddressReuse { output_index, first_output_index, label, script_pubkey } =>
+                write!(f, "受信者出力 {}{} は受信者出力 {} と同じscriptPubKey ({}) への送金です", output_index, label_suffix(label.as_deref()), first_output_index, script_pubkey),
+            SigningWarning::PartiallySigned { unsigned_inputs } =>
+                write!(f, "入力 {:?} は未署名です。すべての入力が署名されるまでこのトランザクションはブロードキャストできません", unsigned_inputs),
+            SigningWarning::HighFeeRatio { fee_sats, sent_sats, max_fee_sats } =>
+                write!(f, "手数料 {} sats が送金額 {} sats に対する上限 {} sats (--max-fee-ratio) を超えています。設定ミスではありませんか?", fee_sats, sent_sats, max_fee_sats),
+            SigningWarning::LowFeeRate { fee_rate_sats_per_vb } =>
+                write!(f, "手数料率 {} sats/vB は最低リレー手数料 1 sat/vB 未満です", fee_rate_sats_per_vb),
+            SigningWarning::SweepFeeRateLowered { from_sats_per_vb, to_sats_per_vb } =>
+                write!(f, "スイープ出力がダストにならないよう手数料率を {} から {} sats/vB に引き下げました", from_sats_per_vb, to_sats_per_vb),
+            SigningWarning::ScriptTypeMismatch { outpoint, label, script_type, likely_type } =>
+                write!(f, "UTXO {}{} のscriptPubKeyは {:?} ですが、WIFの鍵の圧縮形式からは {:?} が想定されます (スクリプトタイプの取り違えではありませんか?)",
+                    outpoint, label_suffix(label.as_deref()), script_type, likely_type),
+            SigningWarning::SighashNone { outpoint, label, sighash_type } =>
+                write!(f, "UTXO {}{} は {} で署名するため出力にコミットしません。署名後に第三者が送金先と金額を変更できます", outpoint, label_suffix(label.as_deref()), sighash_type),
+        }
+    }
+}
+
+// 入力ごとの署名に関する情報 (PSBT出力などで使用)
+#[derive(Debug, Clone)]
+pub struct SignedInput {
+    // 使用するUTXOの value と scriptPubKey
+    pub spent_output: TxOut,
+    // skipSigning の入力では None
+    pub public_key: Option<PublicKey>,
+    // 設定で宣言された sighash type
+    pub sighash_type: EcdsaSighashType,
+    // skipSigning の入力では None
+    pub sighash: Option<Message>,
+    pub signature: Option<bitcoin::ecdsa::Signature>,
+    // P2TR入力の鍵パスのSchnorr署名。P2TR以外の入力では常に None (ECDSAの署名は signature)
+    pub taproot_signature: Option<bitcoin::taproot::Signature>,
+    // ベアマルチシグ入力の2つ目以降の鍵とその署名 (scriptPubKey の公開鍵の順)。他の入力では空
+    pub cosigner_signatures: Vec<(PublicKey, bitcoin::ecdsa::Signature)>,
+    pub bip32_derivation: Option<Bip32Derivation>,
+    // PSBTの non_witness_utxo に使う前トランザクション
+    pub previous_transaction: Option<Transaction>,
+}
+
+// create_and_sign_transaction の結果
+#[derive(Debug)]
+pub struct SignedTransaction {
+    pub transaction: Transaction,
+    // 署名ループ前の状態 (script_sig/witness が空) のトランザクション
+    pub unsigned_transaction: Transaction,
+    pub warnings: Vec<SigningWarning>,
+    // transaction.input と同じ順序
+    pub inputs: Vec<SignedInput>,
+    // transaction.output と同じ順序の、各出力のBIP32導出情報
+    pub output_bip32_derivations: Vec<Option<Bip32Derivation>>,
+    // transaction.output と同じ順序の、各出力のメモ (おつり出力は None)
+    pub output_memos: Vec<Option<String>>,
+    // おつり出力 (スイープではスイープ出力) の transaction.output でのインデックス。おつりを作らなかった場合は None
+    pub change_index: Option<usize>,
+    // 実際の手数料 (入力合計 - 出力合計)
+    pub fee_sats: u64,
+    // 手数料計算に使用した手数料率 (手数料バンプやスイープの引き下げ後)
+    pub fee_rate_sats_per_vb: FeeRate,
+    // ダスト閾値の計算に使用したダストリレー手数料率 (sats/kvB)
+    pub dust_relay_fee_sats_per_kvb: u64,
+}
+
+// CLI から create_and_sign_transaction へ渡す動作オプション
+#[derive(Debug, Clone, Default)]
+pub struct SigningOptions {
+    // true の場合、暗黙の仮定に関する警告をすべてエラーとして扱う
+    pub strict: bool,
+    // 各UTXOのscriptPubKeyに対応するアドレスを標準エラー出力に表示する
+    pub show_input_addresses: bool,
+    // 各処理フェーズの所要時間と件数のサマリーを出力する
+    pub metrics: bool,
+    // 手数料率の上限 (sats/vB)。超えた場合はエラー。None なら無制限
+    pub fee_rate_ceiling_sats_per_vb: Option<u64>,
+    // 署名ハッシュの計算までで終え、署名しない。WIFのない入力 (publicKeyHex) を受け付ける
+    pub sighash_only: bool,
+    // 実際の手数料 (入力合計 - 出力合計) がこの値と一致しなければエラー
+    pub expected_fee_sats: Option<u64>,
+    // 署名後の実際のvsizeで手数料率が目標を下回る場合、手数料を上乗せして再署名する
+    pub round_up_fee: bool,
+    // 各入力の署名適用後のトランザクションhexを input-<入力番号>.hex として書き出すディレクトリ (監査用)
+    pub audit_dir: Option<std::path::PathBuf>,
+    // メインネットでの手数料率 0 sats/vB を許可する
+    pub allow_low_fee: bool,
+    // 受信者出力間のアドレス再利用を警告しない
+    pub allow_address_reuse: bool,
+    // おつりがダスト閾値・minChangeSats 未満でも 0 より大きければ出力を作る (試験用、メインネット不可)
+    pub force_change: bool,
+    // 指定した入力インデックス (並び替え後) のみ署名する。None なら全入力
+    pub sign_inputs: Option<Vec<usize>>,
+    // 手数料が送金額のこの割合を超えたら警告する (strict ではエラー)。None なら検査しない
+    pub max_fee_ratio: Option<f64>,
+    // 入力数と出力数の比 (大きい方 / 小さい方) の上限。None なら検査しない
+    pub max_io_ratio: Option<f64>,
+    // --safe: 資金の安全に関わる条件を署名前に検査し、満たさなければ中断する
+    pub safe_mode: Option<SafeModePolicy>,
+    // 受信者出力 (appendOutputs を含む) の送金先として許可するscriptPubKey。None なら制限しない
+    pub recipient_allowlist: Option<HashSet<ScriptBuf>>,
+}
+
+// --safe で適用する条件
+#[derive(Debug, Clone)]
+pub struct SafeModePolicy {
+    // 送金先として許可するscriptPubKey (おつり先を含む)
+    pub allowed_script_pubkeys: HashSet<ScriptBuf>,
+    // 手数料の上限 (送金額に対する割合、%)。スイープではスイープ出力の金額に対する割合
+    pub max_fee_percent: f64,
+}
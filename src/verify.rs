@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use bitcoin::{
+    ecdsa::Signature,
+    network::Network as BitcoinNetwork,
+    script::Instruction,
+    secp256k1::{Message, Secp256k1, VerifyOnly},
+    key::XOnlyPublicKey,
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    Address, Amount, OutPoint, PublicKey, ScriptBuf, Transaction, TxOut, Txid,
+};
+use std::str::FromStr;
+
+use crate::{
+    config::{InputConfig, TransactionOutputDef},
+    error::{field_error, label_suffix, AppError},
+    fee_rate::FeeRate,
+    preflight::{check, PreflightCheck},
+    transaction::{resolve_output_def_script, resolve_output_script, resolve_utxo_script_pubkey, transaction_vsize},
+    types::{bare_multisig_keys, ScriptType},
+};
+
+// 設定ファイルに記述された、署名対象のUTXO
+struct ExpectedInput {
+    name: String,
+    tx_out: TxOut,
+}
+
+// verify-tx の結果。外部で署名されたトランザクションが設定ファイルの意図と一致するかを項目ごとに示す
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl VerifyReport {
+    pub fn matches_config(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &PreflightCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let checks: Vec<serde_json::Value> = self
+            .checks
+            .iter()
+            .map(|check| serde_json::json!({ "name": check.name, "passed": check.passed, "detail": check.detail }))
+            .collect();
+        serde_json::json!({ "matches_config": self.matches_config(), "checks": checks })
+    }
+}
+
+/// 外部で署名されたトランザクションを設定ファイル (utxos / feeBumpUtxos / outputs / appendOutputs) と照合する。
+/// 使用するUTXO、送金先と金額、各入力の署名 (設定の scriptPubKey と金額に対する検証) を確認する
+pub fn verify_transaction(tx: &Transaction, config: &InputConfig, network: BitcoinNetwork) -> Result<VerifyReport, AppError> {
+    let mut expected_inputs = HashMap::new();
+    let utxos = config.utxos.iter().enumerate().map(|(index, utxo)| (format!("utxos[{}]", index), utxo));
+    let fee_bump_utxos = config.fee_bump_utxos.iter().enumerate().map(|(index, utxo)| (format!("feeBumpUtxos[{}]", index), utxo));
+    for (field_path, utxo_input) in utxos.chain(fee_bump_utxos) {
+        let txid = Txid::from_str(&utxo_input.txid)
+            .map_err(|e| field_error(&field_path, "txid", format!("無効なTXID形式 ({}): {}", utxo_input.txid, e)))?;
+        let out_point = OutPoint::new(txid, utxo_input.vout);
+        let script_pubkey = resolve_utxo_script_pubkey(utxo_input, &field_path, network)?;
+        let name = format!("{}{}", out_point, label_suffix(utxo_input.label.as_deref()));
+        let tx_out = TxOut { value: Amount::from_sat(utxo_input.value_sats), script_pubkey };
+        if expected_inputs.insert(out_point, ExpectedInput { name, tx_out }).is_some() {
+            return Err(field_error(&field_path, "txid", format!("{} が重複しています", out_point)));
+        }
+    }
+
+    let outputs = config.outputs.iter().enumerate().map(|(index, output)| (format!("outputs[{}]", index), output));
+    let append_outputs = config.append_outputs.iter().enumerate().map(|(index, output)| (format!("appendOutputs[{}]", index), output));
+    let mut expected_outputs = Vec::new();
+    let mut output_problems = Vec::new();
+    for (field_path, output_def) in outputs.chain(append_outputs) {
+        match expected_output_script(output_def, network, &field_path)? {
+            Some(script_pubkey) => expected_outputs.push((field_path, script_pubkey, output_def.value_sats)),
+            None => output_problems.push(format!("{} はサイレントペイメントの出力のため、秘密鍵なしでは送金先を照合できません", field_path)),
+        }
+    }
+    // おつり先がアドレスのリストの場合は、いずれへの出力もおつりとみなす
+    let change_script_pubkeys = match (&config.change_address, config.change_descriptor.as_deref()) {
+        (None, None) => Vec::new(),
+        (Some(change_address), None) => change_address
+            .addresses()
+            .iter()
+            .map(|address| resolve_output_script(Some(address), None, network))
+            .collect::<Result<_, _>>()?,
+        (change_address, descriptor) => {
+            let address = change_address.as_ref().and_then(|change_address| change_address.addresses().first());
+            vec![resolve_output_script(address.map(String::as_str), descriptor, network)?]
+        }
+    };
+
+    let checks = vec![
+        check_inputs(tx, &expected_inputs),
+        check_outputs(tx, &expected_outputs, &change_script_pubkeys, network, output_problems),
+        check_fee(tx, &expected_inputs),
+        check_signatures(tx, &expected_inputs),
+    ];
+    Ok(VerifyReport { checks })
+}
+
+// 受信者出力の scriptPubKey。サイレントペイメントの出力は入力の秘密鍵が必要なため None
+fn expected_output_script(output_def: &TransactionOutputDef, network: BitcoinNetwork, field_path: &str) -> Result<Option<ScriptBuf>, AppError> {
+    #[cfg(feature = "silent-payments")]
+    if output_def.silent_payment_address.is_some() {
+        return Ok(None);
+    }
+    resolve_output_def_script(output_def, network)
+        .map(Some)
+        .map_err(|e| field_error(field_path, "address", e.to_string()))
+}
+
+fn check_inputs(tx: &Transaction, expected_inputs: &HashMap<OutPoint, ExpectedInput>) -> PreflightCheck {
+    let mut problems = Vec::new();
+    for (index, tx_in) in tx.input.iter().enumerate() {
+        if !expected_inputs.contains_key(&tx_in.previous_output) {
+            problems.push(format!("入力 {} ({}) は設定ファイルにないUTXOです", index, tx_in.previous_output));
+        }
+    }
+    let mut missing: Vec<&str> = expected_inputs
+        .iter()
+        .filter(|(out_point, _)| !tx.input.iter().any(|tx_in| tx_in.previous_output == **out_point))
+        .map(|(_, expected)| expected.name.as_str())
+        .collect();
+    missing.sort_unstable();
+    problems.extend(missing.into_iter().map(|name| format!("UTXO {} が入力に含まれていません", name)));
+    check("inputs", problems, format!("入力 {} 件はすべて設定ファイルのUTXOです", tx.input.len()))
+}
+
+// 受信者出力はそれぞれ同じ scriptPubKey・金額の出力1つと対応させる。残りの出力はおつりの送金先への1つのみ許可する
+fn check_outputs(
+    tx: &Transaction,
+    expected_outputs: &[(String, ScriptBuf, u64)],
+    change_script_pubkeys: &[ScriptBuf],
+    network: BitcoinNetwork,
+    mut problems: Vec<String>,
+) -> PreflightCheck {
+    let describe = |script_pubkey: &ScriptBuf| {
+        Address::from_script(script_pubkey, network).map_or_else(|_| script_pubkey.to_string(), |address| address.to_string())
+    };
+    let mut matched = vec![false; tx.output.len()];
+    for (field_path, script_pubkey, value_sats) in expected_outputs {
+        let found = tx.output.iter().enumerate().position(|(index, output)| {
+            !matched[index] && output.script_pubkey == *script_pubkey && output.value.to_sat() == *value_sats
+        });
+        match found {
+            Some(index) => matched[index] = true,
+            None => problems.push(format!("{} ({} への {} sats) に一致する出力がありません", field_path, describe(script_pubkey), value_sats)),
+        }
+    }
+    let mut change_outputs = 0;
+    for (index, output) in tx.output.iter().enumerate().filter(|(index, _)| !matched[*index]) {
+        if change_script_pubkeys.contains(&output.script_pubkey) {
+            change_outputs += 1;
+            if change_outputs > 1 {
+                problems.push(format!("出力 {} はおつりの送金先への2つ目の出力です", index));
+            }
+        } else {
+            problems.push(format!("出力 {} ({} への {} sats) は設定ファイルにない送金先・金額です", index, describe(&output.script_pubkey), output.value.to_sat()));
+        }
+    }
+    check("outputs", problems, format!("受信者出力 {} 件、おつり出力 {} 件が設定ファイルと一致します", expected_outputs.len(), change_outputs))
+}
+
+fn check_fee(tx: &Transaction, expected_inputs: &HashMap<OutPoint, ExpectedInput>) -> PreflightCheck {
+    let input_values: Option<Vec<u64>> = tx
+        .input
+        .iter()
+        .map(|tx_in| expected_inputs.get(&tx_in.previous_output).map(|expected| expected.tx_out.value.to_sat()))
+        .collect();
+    let Some(input_values) = input_values else {
+        return check("fee", vec!["設定ファイルにない入力があるため手数料を計算できません".to_string()], String::new());
+    };
+    let total_input_sats: u64 = input_values.iter().sum();
+    let total_output_sats: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+    match total_input_sats.checked_sub(total_output_sats) {
+        Some(fee_sats) => {
+            let vsize = transaction_vsize(tx);
+            let fee_rate = FeeRate::from_fee_and_vsize(fee_sats, vsize);
+            check("fee", Vec::new(), format!("手数料 {} sats ({} vB, {} sats/vB)", fee_sats, vsize, fee_rate))
+        }
+        None => check("fee", vec![format!(
+            "出力の合計 {} sats が入力の合計 {} sats を超えています", total_output_sats, total_input_sats)], String::new()),
+    }
+}
+
+// 各入力の署名を、設定ファイルの scriptPubKey と金額に対する署名ハッシュで検証する
+fn check_signatures(tx: &Transaction, expected_inputs: &HashMap<OutPoint, ExpectedInput>) -> PreflightCheck {
+    let secp = Secp256k1::verification_only();
+    let mut sighash_cache = SighashCache::new(tx);
+    // P2TR の署名ハッシュは全入力の使用するUTXOにコミットするため、設定ファイルにない入力があると計算できない
+    let prevouts: Option<Vec<TxOut>> = tx
+        .input
+        .iter()
+        .map(|tx_in| expected_inputs.get(&tx_in.previous_output).map(|expected| expected.tx_out.clone()))
+        .collect();
+    let mut verified = 0;
+    let mut problems = Vec::new();
+    for (index, tx_in) in tx.input.iter().enumerate() {
+        let Some(expected) = expected_inputs.get(&tx_in.previous_output) else {
+            continue;
+        };
+        match verify_input(&secp, &mut sighash_cache, index, expected, prevouts.as_deref()) {
+            Ok(count) => verified += count,
+            Err(reason) => problems.push(format!("入力 {} ({}): {}", index, expected.name, reason)),
+        }
+    }
+    check("signatures", problems, format!("署名 {} 件を検証しました", verified))
+}
+
+// 入力1つ分の署名を検証し、検証した署名の数を返す
+fn verify_input(
+    secp: &Secp256k1<VerifyOnly>,
+    sighash_cache: &mut SighashCache<&Transaction>,
+    index: usize,
+    expected: &ExpectedInput,
+    prevouts: Option<&[TxOut]>,
+) -> Result<usize, String> {
+    let tx_in = &sighash_cache.transaction().input[index];
+    let (script_sig, witness) = (tx_in.script_sig.clone(), tx_in.witness.clone());
+    let script_pubkey = &expected.tx_out.script_pubkey;
+    let script_type = ScriptType::from_script_buf(script_pubkey).map_err(|e| e.to_string())?;
+    let parse_signature = |bytes: &[u8]| Signature::from_slice(bytes).map_err(|e| format!("署名をデコードできません: {}", e));
+    let verify = |message: Message, signature: &Signature, public_key: &PublicKey| {
+        secp.verify_ecdsa(&message, &signature.signature, &public_key.inner)
+            .map_err(|e| format!("公開鍵 {} の署名が一致しません: {}", public_key, e))
+    };
+    let script_sig_pushes = || -> Result<Vec<Vec<u8>>, String> {
+        script_sig
+            .instructions()
+            .map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => Ok(bytes.as_bytes().to_vec()),
+                _ => Err("script_sig がプッシュ命令のみではありません".to_string()),
+            })
+            .collect()
+    };
+
+    match script_type {
+        ScriptType::P2WPKH => {
+            if witness.len() != 2 || !script_sig.is_empty() {
+                return Err(format!("P2WPKH のwitnessの要素数が {} です (署名と公開鍵の2つである必要があります)", witness.len()));
+            }
+            let signature = parse_signature(&witness[0])?;
+            let public_key = PublicKey::from_slice(&witness[1]).map_err(|e| format!("公開鍵をデコードできません: {}", e))?;
+            if public_key.wpubkey_hash().map(|hash| ScriptBuf::new_p2wpkh(&hash)).as_ref() != Ok(script_pubkey) {
+                return Err(format!("公開鍵 {} が scriptPubKey と一致しません", public_key));
+            }
+            let sighash = sighash_cache
+                .p2wpkh_signature_hash(index, script_pubkey, expected.tx_out.value, signature.sighash_type)
+                .map_err(|e| e.to_string())?;
+            verify(Message::from(sighash), &signature, &public_key).map(|()| 1)
+        }
+        ScriptType::P2PKH | ScriptType::P2PK | ScriptType::BareMultisig { .. } => {
+            if !witness.is_empty() {
+                return Err(format!("{:?} の入力に witness があります", script_type));
+            }
+            let pushes = script_sig_pushes()?;
+            // 署名と、それぞれの署名が対応する公開鍵の候補
+            let (signatures, public_keys): (Vec<&[u8]>, Vec<PublicKey>) = match script_type {
+                ScriptType::P2PKH => {
+                    let [signature, public_key] = pushes.as_slice() else {
+                        return Err(format!("P2PKH の script_sig のプッシュ数が {} です (署名と公開鍵の2つである必要があります)", pushes.len()));
+                    };
+                    let public_key = PublicKey::from_slice(public_key).map_err(|e| format!("公開鍵をデコードできません: {}", e))?;
+                    if ScriptBuf::new_p2pkh(&public_key.pubkey_hash()) != *script_pubkey {
+                        return Err(format!("公開鍵 {} が scriptPubKey と一致しません", public_key));
+                    }
+                    (vec![signature.as_slice()], vec![public_key])
+                }
+                ScriptType::P2PK => {
+                    let [signature] = pushes.as_slice() else {
+                        return Err(format!("P2PK の script_sig のプッシュ数が {} です (署名の1つである必要があります)", pushes.len()));
+                    };
+                    let public_key = script_pubkey.p2pk_public_key().ok_or("P2PK の公開鍵を取得できません")?;
+                    (vec![signature.as_slice()], vec![public_key])
+                }
+                _ => {
+                    let (required, keys) = bare_multisig_keys(script_pubkey).ok_or("マルチシグの公開鍵を取得できません")?;
+                    // OP_CHECKMULTISIG のバグにより先頭に余分な OP_0 (空のプッシュ) が必要
+                    let Some((_, signatures)) = pushes.split_first().filter(|(dummy, _)| dummy.is_empty()) else {
+                        return Err("ベアマルチシグの script_sig が OP_0 で始まっていません".to_string());
+                    };
+                    if signatures.len() != usize::from(required) {
+                        return Err(format!("ベアマルチシグの署名が {} 個です ({} 個必要です)", signatures.len(), required));
+                    }
+                    (signatures.iter().map(Vec::as_slice).collect(), keys)
+                }
+            };
+            // OP_CHECKMULTISIG と同様に、署名は公開鍵の順に照合する (P2PKH / P2PK は候補が1つ)
+            let signature_count = signatures.len();
+            let mut remaining_keys = public_keys.iter();
+            for signature_bytes in signatures {
+                let signature = parse_signature(signature_bytes)?;
+                let sighash = sighash_cache
+                    .legacy_signature_hash(index, script_pubkey, signature.sighash_type.to_u32())
+                    .map_err(|e| e.to_string())?;
+                let message = Message::from(sighash);
+                remaining_keys
+                    .by_ref()
+                    .find(|public_key| verify(message, &signature, public_key).is_ok())
+                    .ok_or_else(|| format!("署名 {} が公開鍵のいずれとも一致しません (公開鍵の順で照合します)", signature))?;
+            }
+            Ok(signature_count)
+        }
+        ScriptType::P2TR => {
+            if witness.len() != 1 || !script_sig.is_empty() {
+                return Err(format!("P2TR のwitnessの要素数が {} です (鍵パスの署名1つである必要があります)", witness.len()));
+            }
+            let prevouts = prevouts.ok_or("設定ファイルにない入力があるため P2TR の署名ハッシュを計算できません")?;
+            let signature = bitcoin::taproot::Signature::from_slice(&witness[0])
+                .map_err(|e| format!("Schnorr署名をデコードできません: {}", e))?;
+            // scriptPubKey は OP_1 と32バイトの出力鍵のプッシュ
+            let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+                .map_err(|e| format!("出力鍵をデコードできません: {}", e))?;
+            let sighash = sighash_cache
+                .taproot_key_spend_signature_hash(index, &Prevouts::All(prevouts), signature.sighash_type)
+                .map_err(|e| e.to_string())?;
+            if signature.sighash_type != TapSighashType::Default {
+                return Err(format!("署名の sighash type {} が SIGHASH_DEFAULT ではありません", signature.sighash_type));
+            }
+            secp.verify_schnorr(&signature.signature, &Message::from(sighash), &output_key)
+                .map(|()| 1)
+                .map_err(|e| format!("出力鍵 {} の署名が一致しません: {}", output_key, e))
+        }
+        ScriptType::UnknownWitness { version, .. } => Err(format!("witness v{} の署名は検証できません", version.to_num())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        transaction::{create_and_sign_transaction, KeyCache},
+        types::SigningOptions,
+    };
+    use serde_json::{json, Value};
+
+    const RECIPIENT_ADDRESS: &str = "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r";
+
+    // P2WPKH と P2PKH の2入力、受信者1つとおつりの設定
+    fn intended_config() -> Value {
+        let wif = "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA";
+        json!({
+            "utxos": [
+                {"txid": format!("{:064x}", 1), "vout": 0, "scriptPubkeyHex": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                    "valueSats": 100_000, "privateKeyWif": wif, "label": "segwit"},
+                {"txid": format!("{:064x}", 2), "vout": 1, "scriptPubkeyHex": "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac",
+                    "valueSats": 50_000, "privateKeyWif": wif},
+            ],
+            "outputs": [{"address": RECIPIENT_ADDRESS, "valueSats": 60_000}],
+            "feeRateSatsPerVb": 2,
+            "changeAddress": "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+        })
+    }
+
+    fn parse(config: Value) -> InputConfig {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn failed_checks(tx: &Transaction, config: Value) -> Vec<(&'static str, String)> {
+        let report = verify_transaction(tx, &parse(config), BitcoinNetwork::Testnet).unwrap();
+        report.failures().map(|check| (check.name, check.detail.clone())).collect()
+    }
+
+    fn signed_transaction() -> Transaction {
+        create_and_sign_transaction(
+            parse(intended_config()), BitcoinNetwork::Testnet, &Secp256k1::new(), &mut KeyCache::default(), &SigningOptions::default(), |_| {})
+            .unwrap()
+            .transaction
+    }
+
+    #[test]
+    fn transaction_signed_from_the_same_config_matches() {
+        let tx = signed_transaction();
+        let report = verify_transaction(&tx, &parse(intended_config()), BitcoinNetwork::Testnet).unwrap();
+        assert!(report.matches_config(), "{:?}", report.checks);
+        assert_eq!(report.to_json()["matches_config"], true);
+        let signatures = report.checks.iter().find(|check| check.name == "signatures").unwrap();
+        assert!(signatures.detail.contains("2 件"), "{}", signatures.detail);
+    }
+
+    #[test]
+    fn discrepancies_with_the_intended_config_are_reported_per_check() {
+        let tx = signed_transaction();
+
+        // 受信者の金額を書き換えると、出力の照合と (署名ハッシュが変わるため) 署名の検証の両方が失敗する
+        let mut redirected = tx.clone();
+        let recipient_index = redirected.output.iter().position(|output| output.value.to_sat() == 60_000).unwrap();
+        redirected.output[recipient_index].value = Amount::from_sat(59_000);
+        let failures = failed_checks(&redirected, intended_config());
+        assert_eq!(failures.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["outputs", "signatures"]);
+        assert!(failures[0].1.contains("outputs[0]"), "{}", failures[0].1);
+
+        // 設定にあるUTXOを使っていない
+        let mut config = intended_config();
+        config["utxos"].as_array_mut().unwrap().push(json!({
+            "txid": format!("{:064x}", 3), "vout": 0, "scriptPubkeyHex": "0014751e76e8199196d454941c45d1b3a323f1433bd6", "valueSats": 1_000,
+        }));
+        let failures = failed_checks(&tx, config);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "inputs");
+
+        // 設定の金額と異なるUTXOへの署名は (SegWitの署名ハッシュが金額にコミットするため) 一致しない
+        let mut config = intended_config();
+        config["utxos"][0]["valueSats"] = json!(100_001);
+        let failures = failed_checks(&tx, config);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "signatures");
+        assert!(failures[0].1.contains("segwit"), "{}", failures[0].1);
+
+        // 設定ファイルにない送金先への出力
+        let mut config = intended_config();
+        config["outputs"][0]["address"] = json!("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx");
+        config["changeAddress"] = json!(RECIPIENT_ADDRESS);
+        let failures = failed_checks(&tx, config);
+        assert_eq!(failures.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["outputs"]);
+    }
+}